@@ -0,0 +1,69 @@
+// Compares inserting a frame with the default `SqlWriter` against
+// `SqlWriter::new_bulk` with the default `BulkLoadProfile`.
+//
+//     BULK_BENCH_ROWS=5000000 cargo bench --bench bulk_load
+use db_cov19mx::pl_sql::{BulkLoadProfile, IfExistsOption, SqlWriter};
+use polars::prelude::*;
+use std::env;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Instant;
+fn frame(n: usize) -> PolarsResult<DataFrame> {
+    df!(
+        "ID_REGISTRO" => (0..n).map(|i| format!("z{:07x}", i)).collect::<Vec<_>>(),
+        "ENTIDAD_RES" => (0..n).map(|i| (i % 32 + 1) as u64).collect::<Vec<_>>(),
+        "EDAD" => (0..n).map(|i| (i % 110) as i64).collect::<Vec<_>>(),
+        "FECHA_INGRESO" => (0..n).map(|i| format!("2021-{:02}-{:02}", i % 12 + 1, i % 28 + 1)).collect::<Vec<_>>(),
+    )
+}
+fn temp_db(name: &str) -> PathBuf {
+    let path = env::temp_dir().join(format!("db_cov19mx_bench_{}.db", name));
+    let _ = fs::remove_file(&path);
+    path
+}
+fn insert(
+    sql_write: &SqlWriter,
+    df: &DataFrame,
+    batch_size: NonZeroUsize,
+) -> Result<u64, color_eyre::eyre::Error> {
+    sql_write
+        .clone()
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false)
+        .if_exists(IfExistsOption::Replace)
+        .with_batch_size(batch_size)
+        .finish(&mut df.clone())
+}
+fn main() -> Result<(), color_eyre::eyre::Error> {
+    let var = |name: &str, default: usize| {
+        env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let rows = var("BULK_BENCH_ROWS", 2_000_000);
+    let batch_size = NonZeroUsize::new(var("BULK_BENCH_BATCH", 160_000).max(1)).unwrap();
+    let df = frame(rows)?;
+    println!("{} rows, batches of {} rows", rows, batch_size);
+
+    let start = Instant::now();
+    insert(&SqlWriter::new(temp_db("default"))?, &df, batch_size)?;
+    let default = start.elapsed();
+    println!("default writer:    {:>8.2} s", default.as_secs_f64());
+
+    let start = Instant::now();
+    let sql_write = SqlWriter::new_bulk(
+        temp_db("bulk"),
+        BulkLoadProfile::default().with_vacuum(false),
+    )?;
+    insert(&sql_write, &df, batch_size)?;
+    sql_write.end_bulk_load()?;
+    let bulk = start.elapsed();
+    println!("BulkLoadProfile:   {:>8.2} s", bulk.as_secs_f64());
+    println!(
+        "speedup: {:.1}x",
+        default.as_secs_f64() / bulk.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,85 @@
+// Compares reading a CSV in batches with `LazyFrame::slice`, which scans the
+// file again for every batch, against `CsvBatchReader`, which reads it once.
+//
+//     CSV_BENCH_ROWS=5000000 cargo bench --bench csv_batches
+use db_cov19mx::pl_csv::CsvBatchReader;
+use db_cov19mx::synthetic::SyntheticDataset;
+use polars::prelude::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+fn schema() -> SchemaRef {
+    Arc::new(Schema::from_iter(
+        [
+            "ID_REGISTRO",
+            "MUNICIPIO_RES",
+            "ENTIDAD_UM",
+            "ENTIDAD_NAC",
+            "ENTIDAD_RES",
+        ]
+        .into_iter()
+        .map(|name| Field::new(name.into(), DataType::String)),
+    ))
+}
+fn sliced(path: &Path, batch_size: usize) -> PolarsResult<(usize, Duration)> {
+    let start = Instant::now();
+    let lf = LazyCsvReader::new(path)
+        .with_has_header(true)
+        .with_dtype_overwrite(Some(schema()))
+        .finish()?;
+    let (mut rows, mut offset) = (0, 0);
+    loop {
+        let df = lf.clone().slice(offset, batch_size as IdxSize).collect()?;
+        if df.is_empty() {
+            break;
+        }
+        rows += df.height();
+        offset += df.height() as i64;
+    }
+    Ok((rows, start.elapsed()))
+}
+fn batched(path: &Path, batch_size: usize) -> Result<(usize, Duration), color_eyre::eyre::Error> {
+    let start = Instant::now();
+    let mut rows = 0;
+    for df in CsvBatchReader::new(path)
+        .with_dtype_overwrite(Some(schema()))
+        .with_batch_size(batch_size)
+        .finish()?
+    {
+        rows += df?.height();
+    }
+    Ok((rows, start.elapsed()))
+}
+fn main() -> Result<(), color_eyre::eyre::Error> {
+    let var = |name: &str, default: usize| {
+        env::var(name)
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(default)
+    };
+    let rows = var("CSV_BENCH_ROWS", 2_000_000);
+    let batch_size = var("CSV_BENCH_BATCH", 100_000);
+    let path = env::temp_dir().join(format!("db_cov19mx_bench_{}.csv", rows));
+    if !path.exists() {
+        SyntheticDataset::new(0)
+            .with_rows(rows)
+            .write_csv(2020, &path)?;
+    }
+    let size = fs::metadata(&path)?.len() as f64 / (1024.0 * 1024.0);
+    println!(
+        "{} rows, {:.0} MiB, batches of {} rows",
+        rows, size, batch_size
+    );
+    let (n_batched, t_batched) = batched(&path, batch_size)?;
+    println!("CsvBatchReader:        {:>8.2} s", t_batched.as_secs_f64());
+    let (n_sliced, t_sliced) = sliced(&path, batch_size)?;
+    println!("LazyFrame::slice loop: {:>8.2} s", t_sliced.as_secs_f64());
+    assert_eq!(n_batched, rows);
+    assert_eq!(n_sliced, rows);
+    println!(
+        "speedup: {:.1}x",
+        t_sliced.as_secs_f64() / t_batched.as_secs_f64()
+    );
+    Ok(())
+}
@@ -0,0 +1,31 @@
+use color_eyre::eyre::eyre;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+// Pide detener la carga; se revisa entre lotes para que el lote en curso
+// termine de escribirse y quede registrado su avance
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+    // El primer Ctrl-C cancela el token, el segundo termina el proceso sin esperar
+    pub fn install_ctrlc(&self) -> Result<(), color_eyre::eyre::Error> {
+        let token = self.clone();
+        ctrlc::set_handler(move || {
+            if token.is_cancelled() {
+                std::process::exit(130);
+            }
+            warn!("se termina el lote en curso y se guarda el avance, Ctrl-C otra vez para salir");
+            token.cancel();
+        })
+        .map_err(|e| eyre!("No se pudo instalar el manejador de Ctrl-C: {}", e))
+    }
+}
@@ -0,0 +1,130 @@
+use crate::logging::LogFormat;
+use crate::progress::ProgressMode;
+use clap::{Args, Parser, Subcommand};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+#[derive(Debug, Clone, Parser)]
+#[command(
+    name = "db_cov19mx",
+    version,
+    about = "ETL de los datos abiertos de COVID-19 de la Secretaría de Salud",
+    long_about = "Sin subcomando descarga los datos si faltan, carga los catálogos y los registros"
+)]
+pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        help = "Archivo TOML de configuración, por omisión db_cov19mx.toml si existe"
+    )]
+    pub config: Option<PathBuf>,
+    #[command(flatten)]
+    pub paths: PathArgs,
+    #[arg(long, global = true, help = "Hilos de polars y de la inserción en SQL")]
+    pub threads: Option<usize>,
+    #[arg(
+        long,
+        global = true,
+        help = "Nivel de la bitácora, p. ej. debug o info,db_cov19mx::pl_sql=debug"
+    )]
+    pub log_level: Option<String>,
+    #[arg(long, global = true, value_enum, help = "Formato de la bitácora")]
+    pub log_format: Option<LogFormat>,
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        help = "Avance en barras (stderr) o JSON por línea (stdout)"
+    )]
+    pub progress: Option<ProgressMode>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    #[command(about = "Descarga los ZIP de los años indicados y el diccionario de datos")]
+    Download(YearsArgs),
+    #[command(about = "Descomprime los ZIP descargados")]
+    Extract,
+    #[command(about = "Carga los catálogos y la tabla de países")]
+    Catalogs,
+    #[command(about = "Carga los CSV que no se han cargado")]
+    Load(LoadArgs),
+    #[command(about = "Genera el reporte de calidad de los CSV")]
+    Validate(YearsArgs),
+    #[command(about = "Muestra los archivos cargados y el tamaño de las tablas")]
+    Status,
+}
+// Carpetas y archivos que reemplazan los de la configuración
+#[derive(Debug, Clone, Default, Args)]
+pub struct PathArgs {
+    #[arg(
+        long,
+        global = true,
+        help = "Carpeta de los ZIP de datos, por omisión data_zip"
+    )]
+    pub zip_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Carpeta del ZIP del diccionario, por omisión dicc_zip"
+    )]
+    pub dicc_zip_dir: Option<PathBuf>,
+    #[arg(long, global = true, help = "Carpeta de los CSV, por omisión data_csv")]
+    pub csv_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Carpeta del diccionario de datos, por omisión data_dicc"
+    )]
+    pub dicc_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Carpeta de la base de datos, por omisión DB"
+    )]
+    pub db_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Nombre de la base SQLite, por omisión db_cov19mx.db"
+    )]
+    pub db_file: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Carpeta de la salida Parquet, por omisión data_parquet"
+    )]
+    pub parquet_dir: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Excel de descriptores, por omisión el de --dicc-dir"
+    )]
+    pub descriptors: Option<PathBuf>,
+    #[arg(
+        long,
+        global = true,
+        help = "Excel de catálogos, por omisión el de --dicc-dir"
+    )]
+    pub catalogs_file: Option<PathBuf>,
+}
+#[derive(Debug, Clone, Default, Args)]
+pub struct YearsArgs {
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Años a procesar separados por coma, por omisión todos"
+    )]
+    pub years: Vec<u16>,
+}
+#[derive(Debug, Clone, Default, Args)]
+pub struct LoadArgs {
+    #[command(flatten)]
+    pub years: YearsArgs,
+    #[arg(long, help = "Máximo de registros que se leen del CSV por lote")]
+    pub batch_size: Option<u32>,
+    #[arg(long, help = "Registros por sentencia INSERT")]
+    pub insert_batch_size: Option<NonZeroUsize>,
+    #[arg(long, help = "Memoria máxima en MiB de los lotes en proceso")]
+    pub memory_budget_mb: Option<u64>,
+}
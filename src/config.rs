@@ -0,0 +1,442 @@
+use crate::cli::{Cli, Command};
+use crate::logging::LoggingConfig;
+use crate::output::OutputBackend;
+use crate::pipeline::PipelineConfig;
+use crate::progress::ProgressMode;
+use crate::sentinel::SentinelAction;
+use crate::utils::{DateParseMode, DEFAULT_YEARS, URL_DICC};
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+// Archivo que se busca en la carpeta actual si no se indica otro
+pub const DEFAULT_CONFIG_FILE: &str = "db_cov19mx.toml";
+// Las variables `COV19MX_<SECCION>_<CAMPO>` reemplazan los valores del archivo,
+// p. ej. `COV19MX_WRITER_BATCH_SIZE=500000` o `COV19MX_PATHS_DB_DIR=/datos/DB`
+pub const ENV_PREFIX: &str = "COV19MX_";
+pub const CONFIG_ENV: &str = "COV19MX_CONFIG";
+const SECTIONS: [&str; 8] = [
+    "sources", "paths", "catalogs", "cleaning", "output", "writer", "pipeline", "logging",
+];
+// Definición completa del pipeline, se arma por capas: valores por omisión,
+// archivo TOML, variables de entorno y al final las opciones de la línea de comandos
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EtlConfig {
+    pub sources: SourcesConfig,
+    pub paths: EtlPaths,
+    pub catalogs: CatalogsConfig,
+    pub cleaning: CleaningConfig,
+    pub output: OutputConfig,
+    pub writer: WriterConfig,
+    pub pipeline: PipelineConfig,
+    pub logging: LoggingConfig,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourcesConfig {
+    // Años que se descargan
+    pub years: Vec<u16>,
+    // URL de los históricos, `{year}` se cambia por el año
+    pub url_covid: String,
+    pub url_dicc: String,
+}
+impl Default for SourcesConfig {
+    fn default() -> Self {
+        Self {
+            years: DEFAULT_YEARS.to_vec(),
+            url_covid:
+                "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/{year}/COVID19MEXICO{year}.zip"
+                    .into(),
+            url_dicc: URL_DICC.into(),
+        }
+    }
+}
+impl SourcesConfig {
+    pub fn url_covid(&self, year: u16) -> String {
+        self.url_covid.replace("{year}", &year.to_string())
+    }
+}
+// Carpetas y archivos con que trabaja el ETL
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EtlPaths {
+    pub zip_dir: PathBuf,
+    pub dicc_zip_dir: PathBuf,
+    pub csv_dir: PathBuf,
+    pub dicc_dir: PathBuf,
+    pub db_dir: PathBuf,
+    pub db_file: PathBuf,
+    pub parquet_dir: PathBuf,
+    // Por omisión se buscan en `dicc_dir`
+    pub descriptors: Option<PathBuf>,
+    pub catalogs_file: Option<PathBuf>,
+}
+impl Default for EtlPaths {
+    fn default() -> Self {
+        Self {
+            zip_dir: "data_zip".into(),
+            dicc_zip_dir: "dicc_zip".into(),
+            csv_dir: "data_csv".into(),
+            dicc_dir: "data_dicc".into(),
+            db_dir: "DB".into(),
+            db_file: "db_cov19mx.db".into(),
+            parquet_dir: "data_parquet".into(),
+            descriptors: None,
+            catalogs_file: None,
+        }
+    }
+}
+impl EtlPaths {
+    pub fn db_path(&self) -> PathBuf {
+        self.db_dir.join(&self.db_file)
+    }
+    pub fn countries_path(&self) -> PathBuf {
+        self.db_dir.join("paises.json")
+    }
+    pub fn quality_path(&self) -> PathBuf {
+        self.db_dir.join("quality_report.json")
+    }
+    pub fn summary_path(&self) -> PathBuf {
+        self.db_dir.join("etl_run.json")
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CatalogsConfig {
+    // Columnas que el diccionario no relaciona con un catálogo y la tabla a la que apuntan
+    pub foreign_keys: BTreeMap<String, String>,
+}
+impl Default for CatalogsConfig {
+    fn default() -> Self {
+        Self {
+            foreign_keys: BTreeMap::from([
+                ("PAIS_NACIONALIDAD".into(), "PAISES".into()),
+                ("PAIS_ORIGEN".into(), "PAISES".into()),
+            ]),
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CleaningConfig {
+    // Qué se hace con los códigos "NO APLICA", "SE IGNORA" y "NO ESPECIFICADO"
+    pub sentinel_action: SentinelAction,
+    // Acción de columnas particulares
+    pub sentinel_actions: BTreeMap<String, SentinelAction>,
+    pub date_mode: DateParseMode,
+    // Edad máxima que se considera posible en el reporte de calidad
+    pub max_age: i64,
+}
+impl Default for CleaningConfig {
+    fn default() -> Self {
+        Self {
+            sentinel_action: SentinelAction::Keep,
+            sentinel_actions: BTreeMap::new(),
+            date_mode: DateParseMode::Lenient,
+            max_age: 120,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutputConfig {
+    // Base de datos de los catálogos y COVID19MEXICO: sqlite (la de
+    // `paths.db_file`), postgres o duckdb. La salida Parquet es opcional
+    pub backend: OutputBackend,
+    // URL de conexión de Postgres o archivo de DuckDB
+    pub url: Option<String>,
+    pub parquet: bool,
+    pub parquet_row_group_size: Option<usize>,
+    // Barras en la terminal o JSON por línea en stdout
+    pub progress: ProgressMode,
+}
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self {
+            backend: OutputBackend::Sqlite,
+            url: None,
+            parquet: true,
+            parquet_row_group_size: Some(512 * 1024),
+            progress: ProgressMode::None,
+        }
+    }
+}
+// Opciones del `SqlWriter` de la tabla principal
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WriterConfig {
+    // Máximo de registros que se leen del CSV por lote, el tamaño lo da
+    // `pipeline.memory_budget_mb`
+    pub batch_size: u32,
+    // Registros por sentencia INSERT
+    pub insert_batch_size: NonZeroUsize,
+    pub strict_insert: bool,
+    pub parallel: bool,
+    pub n_threads: Option<usize>,
+}
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 900_000,
+            insert_batch_size: NonZeroUsize::new(160_000).unwrap(),
+            strict_insert: true,
+            parallel: true,
+            n_threads: None,
+        }
+    }
+}
+impl EtlConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, color_eyre::eyre::Error> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .map_err(|e| eyre!("No se pudo leer la configuración {}: {}", path.display(), e))?;
+        Self::from_toml(&text).map_err(|e| eyre!("{}: {}", path.display(), e))
+    }
+    pub fn from_toml(text: &str) -> Result<Self, color_eyre::eyre::Error> {
+        toml::from_str(text).map_err(|e| eyre!("Configuración inválida\n{}", e))
+    }
+    // Archivo indicado, el de `COV19MX_CONFIG` o `db_cov19mx.toml` si existe,
+    // más las variables de entorno
+    pub fn load(path: Option<&Path>) -> Result<Self, color_eyre::eyre::Error> {
+        let path = path
+            .map(|p| p.to_path_buf())
+            .or_else(|| env::var_os(CONFIG_ENV).map(PathBuf::from))
+            .or_else(|| Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|p| p.is_file()));
+        let config = match path {
+            Some(path) => Self::from_file(path)?,
+            None => Self::default(),
+        };
+        config.with_env_vars(env::vars())
+    }
+    pub fn with_env_vars<I, K, V>(self, vars: I) -> Result<Self, color_eyre::eyre::Error>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        let mut table = toml::Table::try_from(&self)?;
+        let mut names = Vec::new();
+        for (name, value) in vars {
+            let name = name.as_ref();
+            let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+                continue;
+            };
+            if name == CONFIG_ENV {
+                continue;
+            }
+            let rest = rest.to_lowercase();
+            let Some((section, key)) = SECTIONS.iter().find_map(|s| {
+                rest.strip_prefix(s)
+                    .and_then(|k| k.strip_prefix('_'))
+                    .map(|k| (*s, k))
+            }) else {
+                return Err(eyre!(
+                    "{}: no corresponde a ninguna sección de la configuración ({})",
+                    name,
+                    SECTIONS.join(", ")
+                ));
+            };
+            let section = table
+                .entry(section)
+                .or_insert_with(|| toml::Table::new().into())
+                .as_table_mut()
+                .ok_or_else(|| eyre!("La sección {} no es una tabla", section))?;
+            let value = env_value(section.get(key), value.as_ref());
+            section.insert(key.to_string(), value);
+            names.push(name.to_string());
+        }
+        if names.is_empty() {
+            return Ok(self);
+        }
+        table
+            .try_into()
+            .map_err(|e| eyre!("Variables de entorno {} inválidas: {}", names.join(", "), e))
+    }
+    // Las opciones dadas en la línea de comandos tienen la última palabra
+    pub fn with_cli(mut self, cli: &Cli) -> Self {
+        let args = &cli.paths;
+        let paths = &mut self.paths;
+        for (arg, path) in [
+            (&args.zip_dir, &mut paths.zip_dir),
+            (&args.dicc_zip_dir, &mut paths.dicc_zip_dir),
+            (&args.csv_dir, &mut paths.csv_dir),
+            (&args.dicc_dir, &mut paths.dicc_dir),
+            (&args.db_dir, &mut paths.db_dir),
+            (&args.db_file, &mut paths.db_file),
+            (&args.parquet_dir, &mut paths.parquet_dir),
+        ] {
+            if let Some(arg) = arg {
+                *path = arg.clone();
+            }
+        }
+        if args.descriptors.is_some() {
+            paths.descriptors = args.descriptors.clone();
+        }
+        if args.catalogs_file.is_some() {
+            paths.catalogs_file = args.catalogs_file.clone();
+        }
+        if cli.threads.is_some() {
+            self.writer.n_threads = cli.threads;
+        }
+        if let Some(level) = &cli.log_level {
+            self.logging.level = level.clone();
+        }
+        if let Some(format) = cli.log_format {
+            self.logging.format = format;
+        }
+        if let Some(progress) = cli.progress {
+            self.output.progress = progress;
+        }
+        match &cli.command {
+            Some(Command::Download(args)) if !args.years.is_empty() => {
+                self.sources.years = args.years.clone();
+            }
+            Some(Command::Load(args)) => {
+                if let Some(n) = args.batch_size {
+                    self.writer.batch_size = n;
+                }
+                if let Some(n) = args.insert_batch_size {
+                    self.writer.insert_batch_size = n;
+                }
+                if let Some(mb) = args.memory_budget_mb {
+                    self.pipeline.memory_budget_mb = mb;
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+    // Revisa lo que no se puede expresar con los tipos, todos los errores juntos
+    pub fn validate(&self) -> Result<(), color_eyre::eyre::Error> {
+        let mut errors = Vec::new();
+        if self.sources.years.is_empty() {
+            errors.push("sources.years: no puede estar vacío".to_string());
+        }
+        for year in &self.sources.years {
+            if *year < DEFAULT_YEARS[0] {
+                errors.push(format!(
+                    "sources.years: no hay datos del año {}, el primero es {}",
+                    year, DEFAULT_YEARS[0]
+                ));
+            }
+        }
+        for (key, url) in [
+            ("sources.url_covid", &self.sources.url_covid),
+            ("sources.url_dicc", &self.sources.url_dicc),
+        ] {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                errors.push(format!("{}: `{}` no es una URL http(s)", key, url));
+            }
+        }
+        if !self.sources.url_covid.contains("{year}") {
+            errors.push("sources.url_covid: falta `{year}` en la URL".to_string());
+        }
+        for (key, path) in [
+            ("paths.zip_dir", &self.paths.zip_dir),
+            ("paths.dicc_zip_dir", &self.paths.dicc_zip_dir),
+            ("paths.csv_dir", &self.paths.csv_dir),
+            ("paths.dicc_dir", &self.paths.dicc_dir),
+            ("paths.db_dir", &self.paths.db_dir),
+            ("paths.db_file", &self.paths.db_file),
+            ("paths.parquet_dir", &self.paths.parquet_dir),
+        ] {
+            if path.as_os_str().is_empty() {
+                errors.push(format!("{}: no puede estar vacío", key));
+            }
+        }
+        for (key, path) in [
+            ("paths.descriptors", &self.paths.descriptors),
+            ("paths.catalogs_file", &self.paths.catalogs_file),
+        ] {
+            if let Some(path) = path.as_ref().filter(|p| !p.is_file()) {
+                errors.push(format!("{}: no existe {}", key, path.display()));
+            }
+        }
+        for (column, table) in &self.catalogs.foreign_keys {
+            if column.is_empty() || table.is_empty() {
+                errors.push(format!(
+                    "catalogs.foreign_keys: `{}` = `{}` necesita columna y tabla",
+                    column, table
+                ));
+            }
+        }
+        if self.cleaning.max_age <= 0 {
+            errors.push(format!(
+                "cleaning.max_age: debe ser mayor que 0, se dio {}",
+                self.cleaning.max_age
+            ));
+        }
+        match (self.output.backend, self.output.url.as_deref()) {
+            (OutputBackend::Sqlite, _) => {}
+            (OutputBackend::Postgres, Some(url))
+                if url.starts_with("postgres://") || url.starts_with("postgresql://") => {}
+            (OutputBackend::Postgres, _) => errors
+                .push("output.url: la salida postgres necesita una URL postgres://".to_string()),
+            (OutputBackend::DuckDb, _) if !cfg!(feature = "duckdb") => errors.push(
+                "output.backend: la salida duckdb necesita compilar con `--features duckdb`"
+                    .to_string(),
+            ),
+            (OutputBackend::DuckDb, Some(path)) if !path.is_empty() => {}
+            (OutputBackend::DuckDb, _) => {
+                errors.push("output.url: la salida duckdb necesita la ruta del archivo".to_string())
+            }
+        }
+        if self.output.parquet_row_group_size == Some(0) {
+            errors.push("output.parquet_row_group_size: debe ser mayor que 0".to_string());
+        }
+        if self.writer.batch_size == 0 {
+            errors.push("writer.batch_size: debe ser mayor que 0".to_string());
+        }
+        if self.writer.n_threads == Some(0) {
+            errors.push("writer.n_threads: debe ser mayor que 0".to_string());
+        }
+        for (key, n) in [
+            ("pipeline.readers", Some(self.pipeline.readers as u64)),
+            ("pipeline.workers", self.pipeline.workers.map(|n| n as u64)),
+            (
+                "pipeline.memory_budget_mb",
+                Some(self.pipeline.memory_budget_mb),
+            ),
+            (
+                "pipeline.channel_capacity",
+                Some(self.pipeline.channel_capacity as u64),
+            ),
+        ] {
+            if n == Some(0) {
+                errors.push(format!("{}: debe ser mayor que 0", key));
+            }
+        }
+        if let Err(e) = self.logging.filter() {
+            errors.push(e.to_string());
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(eyre!("Configuración inválida:\n  {}", errors.join("\n  ")))
+        }
+    }
+    pub fn to_toml(&self) -> Result<String, color_eyre::eyre::Error> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+// Las variables de entorno son texto, se leen como valor TOML salvo que el campo
+// sea texto; las listas se pueden dar sin corchetes: `2021,2022`
+fn env_value(current: Option<&toml::Value>, raw: &str) -> toml::Value {
+    if let Some(toml::Value::String(_)) = current {
+        return toml::Value::String(raw.to_string());
+    }
+    let value = match current {
+        Some(toml::Value::Array(_)) if !raw.trim_start().starts_with('[') => {
+            format!("[{}]", raw)
+        }
+        _ => raw.to_string(),
+    };
+    toml::from_str::<toml::Table>(&format!("v = {}", value))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(raw.to_string()))
+}
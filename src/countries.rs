@@ -0,0 +1,386 @@
+use crate::sentinel::SENTINEL_LABELS;
+use indexmap::IndexMap;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, OnceLock};
+static ISO_COUNTRIES: OnceLock<IsoCountries> = OnceLock::new();
+// Las claves < 1000 quedan libres para los códigos ISO 3166 numéricos
+const FIRST_KEY: u64 = 1_000;
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CountryEntry {
+    clave: u64,
+    pais: String,
+    alias: Vec<String>,
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct IsoCountry {
+    pub numeric: u16,
+    pub alpha2: String,
+    pub alpha3: String,
+    pub name: String,
+    pub continent: String,
+}
+// Tabla ISO 3166-1 con los nombres en español y sus variantes más comunes
+#[derive(Debug, Clone, Default)]
+pub struct IsoCountries {
+    countries: Vec<IsoCountry>,
+    names: HashMap<String, usize>,
+}
+impl IsoCountries {
+    // La tabla que viene con el crate en `data/iso3166_es.csv`
+    pub fn bundled() -> &'static IsoCountries {
+        ISO_COUNTRIES.get_or_init(|| {
+            Self::parse(include_str!("../data/iso3166_es.csv"))
+                .expect("La tabla ISO 3166 del crate no es válida")
+        })
+    }
+    // Formato `numerico;alfa2;alfa3;nombre;continente;alias1|alias2` con encabezado
+    pub fn parse(csv: &str) -> Result<Self, color_eyre::eyre::Error> {
+        let mut table = Self::default();
+        for (n, line) in csv.lines().enumerate().skip(1) {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let fields = line.split(';').collect::<Vec<_>>();
+            if fields.len() != 6 {
+                return Err(color_eyre::eyre::eyre!(
+                    "La línea {} de la tabla ISO 3166 no tiene 6 campos",
+                    n + 1
+                ));
+            }
+            let index = table.countries.len();
+            table.countries.push(IsoCountry {
+                numeric: fields[0].parse()?,
+                alpha2: fields[1].to_string(),
+                alpha3: fields[2].to_string(),
+                name: fields[3].to_string(),
+                continent: fields[4].to_string(),
+            });
+            for name in [fields[3]]
+                .into_iter()
+                .chain(fields[5].split('|').filter(|a| !a.is_empty()))
+            {
+                if let Some(other) = table
+                    .names
+                    .insert(fold_name(name), index)
+                    .filter(|other| *other != index)
+                {
+                    return Err(color_eyre::eyre::eyre!(
+                        "El nombre {} está en {} y en {}",
+                        name,
+                        table.countries[other].alpha3,
+                        fields[2]
+                    ));
+                }
+            }
+        }
+        Ok(table)
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &IsoCountry> {
+        self.countries.iter()
+    }
+    pub fn by_alpha3(&self, alpha3: &str) -> Option<&IsoCountry> {
+        self.countries.iter().find(|c| c.alpha3 == alpha3)
+    }
+    // Busca primero el nombre o alias exacto (sin acentos ni signos) y luego el
+    // más parecido, que solo se acepta si está cerca y no hay empate con otro país
+    pub fn lookup(&self, name: &str) -> Option<&IsoCountry> {
+        let folded = fold_name(name);
+        if folded.is_empty() {
+            return None;
+        }
+        if let Some(index) = self.names.get(&folded) {
+            return Some(&self.countries[*index]);
+        }
+        let max_distance = folded.chars().count() / 5;
+        let mut best: Option<(usize, usize)> = None;
+        let mut tie = false;
+        for (candidate, index) in &self.names {
+            let distance = levenshtein(&folded, candidate);
+            match best {
+                Some((d, i)) if distance == d && *index != i => tie = true,
+                Some((d, _)) if distance >= d => {}
+                _ => {
+                    best = Some((distance, *index));
+                    tie = false;
+                }
+            }
+        }
+        match best {
+            Some((distance, index)) if distance <= max_distance && !tie => {
+                Some(&self.countries[index])
+            }
+            _ => None,
+        }
+    }
+}
+// Mayúsculas sin acentos y con los signos cambiados por espacios
+pub fn fold_name(name: &str) -> String {
+    name.to_uppercase()
+        .chars()
+        .map(|c| match c {
+            'Á' | 'À' | 'Â' | 'Ä' | 'Å' => 'A',
+            'É' | 'È' | 'Ê' | 'Ë' => 'E',
+            'Í' | 'Ì' | 'Î' | 'Ï' => 'I',
+            'Ó' | 'Ò' | 'Ô' | 'Ö' => 'O',
+            'Ú' | 'Ù' | 'Û' | 'Ü' => 'U',
+            'Ñ' => 'N',
+            'Ç' => 'C',
+            c if c.is_alphanumeric() => c,
+            _ => ' ',
+        })
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let current = row[j + 1];
+            row[j + 1] = if ca == *cb {
+                previous
+            } else {
+                1 + previous.min(row[j]).min(row[j + 1])
+            };
+            previous = current;
+        }
+    }
+    row[b.len()]
+}
+// Diccionario persistente de países, cada país tiene una clave fija y todas
+// las formas en que viene escrito apuntan a ella
+#[derive(Debug, Clone, PartialEq)]
+pub struct CountryDictionary {
+    countries: IndexMap<u64, String>,
+    spellings: IndexMap<String, u64>,
+    iso: HashMap<u64, IsoCountry>,
+    next_key: u64,
+}
+impl Default for CountryDictionary {
+    fn default() -> Self {
+        Self {
+            countries: IndexMap::new(),
+            spellings: IndexMap::new(),
+            iso: HashMap::new(),
+            next_key: FIRST_KEY,
+        }
+    }
+}
+impl CountryDictionary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn normalize(name: &str) -> String {
+        name.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .to_uppercase()
+    }
+    // Carga el diccionario guardado, si el archivo no existe empieza vacío
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, color_eyre::eyre::Error> {
+        let mut dictionary = Self::new();
+        if !path.as_ref().exists() {
+            return Ok(dictionary);
+        }
+        let entries: Vec<CountryEntry> = serde_json::from_reader(File::open(path)?)?;
+        for entry in entries {
+            dictionary.insert(&entry.pais, entry.clave)?;
+            for alias in &entry.alias {
+                dictionary.add_alias(alias, &entry.pais)?;
+            }
+        }
+        Ok(dictionary)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), color_eyre::eyre::Error> {
+        let entries = self
+            .countries
+            .iter()
+            .map(|(key, name)| CountryEntry {
+                clave: *key,
+                pais: name.clone(),
+                alias: self
+                    .spellings
+                    .iter()
+                    .filter(|(spelling, k)| *k == key && *spelling != name)
+                    .map(|(spelling, _)| spelling.clone())
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        serde_json::to_writer_pretty(File::create(path)?, &entries)?;
+        Ok(())
+    }
+    pub fn len(&self) -> usize {
+        self.countries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.countries.is_empty()
+    }
+    pub fn key(&self, name: &str) -> Option<u64> {
+        self.spellings.get(&Self::normalize(name)).copied()
+    }
+    pub fn name(&self, key: u64) -> Option<&String> {
+        self.countries.get(&key)
+    }
+    // Registra un país con una clave dada, falla si el nombre ya tiene otra
+    // clave o si la clave ya es de otro país
+    pub fn insert(&mut self, name: &str, key: u64) -> Result<(), color_eyre::eyre::Error> {
+        let name = Self::normalize(name);
+        if let Some(old) = self.spellings.get(&name) {
+            if *old != key {
+                return Err(color_eyre::eyre::eyre!(
+                    "El país {} ya tiene la clave {}, no se le puede asignar {}",
+                    name,
+                    old,
+                    key
+                ));
+            }
+            return Ok(());
+        }
+        if let Some(other) = self.countries.get(&key) {
+            return Err(color_eyre::eyre::eyre!(
+                "La clave {} ya es del país {}, no se le puede asignar a {}",
+                key,
+                other,
+                name
+            ));
+        }
+        if let Some(iso) = IsoCountries::bundled().lookup(&name) {
+            self.iso.insert(key, iso.clone());
+        }
+        self.countries.insert(key, name.clone());
+        self.spellings.insert(name, key);
+        if key >= FIRST_KEY {
+            self.next_key = self.next_key.max(key + 1);
+        }
+        Ok(())
+    }
+    // Devuelve la clave del país, si no existe y se reconoce en ISO 3166 se
+    // usa su código numérico o se agrega como otra forma de escribir el país
+    // que ya lo tiene, si no se reconoce se le asigna la siguiente clave
+    pub fn get_or_insert(&mut self, name: &str) -> Result<u64, color_eyre::eyre::Error> {
+        if let Some(key) = self.key(name) {
+            return Ok(key);
+        }
+        match IsoCountries::bundled().lookup(name) {
+            Some(iso) => {
+                let existing = self
+                    .iso
+                    .iter()
+                    .find(|(_, other)| other.alpha3 == iso.alpha3)
+                    .map(|(key, _)| *key);
+                match existing {
+                    Some(key) => {
+                        self.spellings.insert(Self::normalize(name), key);
+                        Ok(key)
+                    }
+                    None => {
+                        let key = iso.numeric as u64;
+                        self.insert(name, key)?;
+                        Ok(key)
+                    }
+                }
+            }
+            None => {
+                let key = self.next_key;
+                self.insert(name, key)?;
+                Ok(key)
+            }
+        }
+    }
+    pub fn iso(&self, key: u64) -> Option<&IsoCountry> {
+        self.iso.get(&key)
+    }
+    // Países que no se reconocieron en ISO 3166, sin contar "NO APLICA", "SE IGNORA"...
+    pub fn unmatched(&self) -> Vec<&String> {
+        self.countries
+            .iter()
+            .filter(|(key, name)| {
+                !self.iso.contains_key(key) && !SENTINEL_LABELS.contains(&name.as_str())
+            })
+            .map(|(_, name)| name)
+            .collect()
+    }
+    // Otra forma de escribir un país que ya está en el diccionario
+    pub fn add_alias(&mut self, alias: &str, name: &str) -> Result<(), color_eyre::eyre::Error> {
+        let key = self
+            .key(name)
+            .ok_or_else(|| color_eyre::eyre::eyre!("El país {} no está en el diccionario", name))?;
+        let alias = Self::normalize(alias);
+        match self.spellings.get(&alias) {
+            Some(old) if *old != key => Err(color_eyre::eyre::eyre!(
+                "{} ya apunta a la clave {}, no se puede usar como alias de {}",
+                alias,
+                old,
+                name
+            )),
+            _ => {
+                self.spellings.insert(alias, key);
+                Ok(())
+            }
+        }
+    }
+    // Catálogo PAISES con una fila por país y sus códigos ISO cuando se reconoció
+    pub fn frame(&self) -> PolarsResult<DataFrame> {
+        let iso = self
+            .countries
+            .keys()
+            .map(|key| self.iso.get(key))
+            .collect::<Vec<_>>();
+        df!(
+            "CLAVE" => self.countries.keys().copied().collect::<Vec<_>>(),
+            "PAIS" => self.countries.values().cloned().collect::<Vec<_>>(),
+            "ISO_ALFA2" => iso.iter().map(|i| i.map(|i| i.alpha2.clone())).collect::<Vec<_>>(),
+            "ISO_ALFA3" => iso.iter().map(|i| i.map(|i| i.alpha3.clone())).collect::<Vec<_>>(),
+            "ISO_NUMERICO" => iso.iter().map(|i| i.map(|i| i.numeric as u64)).collect::<Vec<_>>(),
+            "CONTINENTE" => iso.iter().map(|i| i.map(|i| i.continent.clone())).collect::<Vec<_>>(),
+        )
+    }
+    // Tabla de mapeo de cada forma en que viene escrito el país a su clave
+    pub fn mapping_frame(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "NOMBRE_ORIGINAL" => self.spellings.keys().cloned().collect::<Vec<_>>(),
+            "CLAVE" => self.spellings.values().copied().collect::<Vec<_>>(),
+        )
+    }
+    // Cambia los nombres de `exp` por su clave, un nombre que no esté en el
+    // diccionario es un error
+    pub fn key_expr(&self, exp: Expr) -> Expr {
+        let spellings: Arc<HashMap<String, u64>> = Arc::new(
+            self.spellings
+                .iter()
+                .map(|(name, key)| (name.clone(), *key))
+                .collect(),
+        );
+        exp.map(
+            move |c: Column| -> PolarsResult<Option<Column>> {
+                let out = c
+                    .str()?
+                    .into_iter()
+                    .map(|name| {
+                        name.map(|name| {
+                            spellings
+                                .get(&CountryDictionary::normalize(name))
+                                .copied()
+                                .ok_or_else(|| {
+                                    polars_err!(
+                                        ComputeError: "El país {} no está en el diccionario", name
+                                    )
+                                })
+                        })
+                        .transpose()
+                    })
+                    .collect::<PolarsResult<UInt64Chunked>>()?;
+                Ok(Some(out.with_name(c.name().clone()).into_column()))
+            },
+            GetOutput::from_type(DataType::UInt64),
+        )
+    }
+}
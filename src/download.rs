@@ -1,12 +1,36 @@
 // use crate::my_errors::MyError;
+use crate::progress::{NoProgress, ProgressObserver, ProgressStage};
 use color_eyre::eyre::Ok;
 use reqwest;
-use std::fs::write;
+use std::fs::File;
+use std::io::Write;
 use std::path::PathBuf;
+use tracing::{info, instrument, Span};
 
 // Estructura para descargar archivos de un link en un directorio
 pub async fn download_file(url: &str, path: &PathBuf) -> Result<(), color_eyre::eyre::Error> {
-    let bts = reqwest::get(url).await?.bytes().await?;
-    write(path, &bts)?;
+    download_file_with_progress(url, path, &NoProgress).await
+}
+// El archivo se escribe conforme llegan los bytes y se reportan a `progress`
+#[instrument(skip(path, progress), fields(bytes))]
+pub async fn download_file_with_progress(
+    url: &str,
+    path: &PathBuf,
+    progress: &dyn ProgressObserver,
+) -> Result<(), color_eyre::eyre::Error> {
+    // Un 404 o un error del servidor no se guarda como si fuera el archivo
+    let mut response = reqwest::get(url).await?.error_for_status()?;
+    progress.start(ProgressStage::Download, url, response.content_length());
+    let mut file = File::create(path)?;
+    let mut bytes = 0;
+    while let Some(chunk) = response.chunk().await? {
+        file.write_all(&chunk)?;
+        bytes += chunk.len();
+        progress.advance(ProgressStage::Download, url, chunk.len() as u64);
+    }
+    file.flush()?;
+    progress.finish(ProgressStage::Download, url);
+    Span::current().record("bytes", bytes);
+    info!(archivo = %path.display(), "descargado");
     Ok(())
 }
@@ -0,0 +1,688 @@
+use crate::pl_sql::{IfExistsOption, SqliteDataType, SqliteSchema, WriteOptions};
+#[cfg(feature = "duckdb")]
+use crate::pl_sql::{MigrateOption, SqlBackend, SqlWriter};
+#[cfg(feature = "duckdb")]
+use crate::progress::ProgressStage;
+#[cfg(feature = "duckdb")]
+use futures::future::BoxFuture;
+use polars::prelude::*;
+#[cfg(feature = "duckdb")]
+use std::path::{Path, PathBuf};
+#[derive(Debug, Clone, PartialEq)]
+pub enum DuckDbDataType {
+    BIGINT,
+    DOUBLE,
+    VARCHAR,
+    BLOB,
+    BOOLEAN,
+    DATE,
+    TIMESTAMP,
+    DECIMAL(Option<usize>, Option<usize>),
+}
+impl std::fmt::Display for DuckDbDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DuckDbDataType::BIGINT => write!(f, "BIGINT"),
+            DuckDbDataType::DOUBLE => write!(f, "DOUBLE"),
+            DuckDbDataType::VARCHAR => write!(f, "VARCHAR"),
+            DuckDbDataType::BLOB => write!(f, "BLOB"),
+            DuckDbDataType::BOOLEAN => write!(f, "BOOLEAN"),
+            DuckDbDataType::DATE => write!(f, "DATE"),
+            DuckDbDataType::TIMESTAMP => write!(f, "TIMESTAMP"),
+            DuckDbDataType::DECIMAL(p, s) => match (p, s) {
+                (Some(p), Some(s)) => write!(f, "DECIMAL({}, {})", p, s),
+                (Some(p), None) => write!(f, "DECIMAL({})", p),
+                _ => write!(f, "DOUBLE"),
+            },
+        }
+    }
+}
+impl DuckDbDataType {
+    pub fn from_sqlite_type(type_sql: &SqliteDataType) -> Self {
+        match type_sql {
+            SqliteDataType::INTEGER => DuckDbDataType::BIGINT,
+            SqliteDataType::REAL => DuckDbDataType::DOUBLE,
+            SqliteDataType::TEXT => DuckDbDataType::VARCHAR,
+            SqliteDataType::BLOB => DuckDbDataType::BLOB,
+            SqliteDataType::NUMERIC(p, s) => DuckDbDataType::DECIMAL(*p, *s),
+        }
+    }
+    pub fn from_column(data_type: Option<&DataType>, type_sql: &SqliteDataType) -> Self {
+        match (data_type, type_sql) {
+            (Some(DataType::Date), SqliteDataType::TEXT | SqliteDataType::REAL) => {
+                DuckDbDataType::DATE
+            }
+            (Some(DataType::Datetime(_, _)), SqliteDataType::TEXT | SqliteDataType::REAL) => {
+                DuckDbDataType::TIMESTAMP
+            }
+            (Some(DataType::Boolean), SqliteDataType::INTEGER) => DuckDbDataType::BOOLEAN,
+            _ => DuckDbDataType::from_sqlite_type(type_sql),
+        }
+    }
+}
+// DuckDB has no AUTOINCREMENT, the index column takes its value from a sequence
+pub fn create_table_duckdb<T: Into<String>>(
+    schema: &SqliteSchema,
+    df_schema: &Schema,
+    table_name: T,
+    foreign_keys: bool,
+) -> String {
+    let table_name = table_name.into();
+    let mut sequences = Vec::new();
+    let mut col_definitions = Vec::new();
+    let mut constraints = Vec::new();
+    for (column, options) in schema.iter_fields() {
+        let type_duck = DuckDbDataType::from_column(df_schema.get(column), options.type_sql());
+        let sequence = format!("{}_{}_seq", table_name, column);
+        col_definitions.push(options.build_col_def_as(
+            column,
+            &type_duck.to_string(),
+            &format!("DEFAULT nextval('{}')", sequence),
+        ));
+        if options.auto_increment() {
+            sequences.push(format!("CREATE SEQUENCE IF NOT EXISTS {};\n", sequence));
+        }
+        if let Some((table, parent)) = options.foreign_key_ref() {
+            if foreign_keys {
+                constraints.push(format!(
+                    "FOREIGN KEY ({}) REFERENCES {}({})",
+                    column, table, parent
+                ));
+            }
+        }
+    }
+    col_definitions.extend(constraints);
+    format!(
+        "{}CREATE TABLE IF NOT EXISTS {} (\n    {}\n);",
+        sequences.concat(),
+        table_name,
+        col_definitions.join(",\n    ")
+    )
+}
+// Statements of one `finish`: the frame is appended to a staging table with
+// its own columns and moved with a single INSERT, so defaults, sequences and
+// ON CONFLICT apply as with any other insert
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuckDbPlan {
+    // Run before appending, DROP/CREATE of the table and the staging table
+    pub setup: Vec<String>,
+    // Table that receives the Appender rows
+    pub stage: String,
+    pub merge: String,
+    pub cleanup: String,
+}
+pub fn duckdb_plan(df_schema: &Schema, options: &WriteOptions) -> DuckDbPlan {
+    let table_name = &options.table_name;
+    let stage = format!("{}__stage", table_name);
+    let mut setup = Vec::new();
+    match options.if_exists {
+        IfExistsOption::Append => {}
+        IfExistsOption::Replace => {
+            setup.push(format!("DROP TABLE IF EXISTS {};", table_name));
+        }
+        IfExistsOption::Fail => {
+            setup.push(format!(
+                "SELECT error('Table {} already exists') FROM information_schema.tables \
+                 WHERE lower(table_name) = lower('{}');",
+                table_name, table_name
+            ));
+        }
+    }
+    setup.push(create_table_duckdb(
+        &options.schema,
+        df_schema,
+        table_name,
+        options.foreign_keys,
+    ));
+    let names = df_schema
+        .iter_names()
+        .map(|c| c.as_str())
+        .collect::<Vec<_>>();
+    let columns = names.join(",");
+    setup.push(format!("DROP TABLE IF EXISTS {};", stage));
+    setup.push(format!(
+        "CREATE TABLE {} AS SELECT {} FROM {} LIMIT 0;",
+        stage, columns, table_name
+    ));
+    let on_conflict = options.on_conflict(&names);
+    // DuckDB does not update the same row twice in one statement, only the
+    // last row of every key is kept
+    let select = match &options.upsert_key {
+        Some(key) => format!(
+            "SELECT DISTINCT ON ({}) {} FROM {} ORDER BY {}, rowid DESC",
+            key, columns, stage, key
+        ),
+        None => format!("SELECT {} FROM {}", columns, stage),
+    };
+    let merge = format!(
+        "INSERT {}INTO {} ({}) {}{};",
+        if options.strict_insert || on_conflict.is_some() {
+            ""
+        } else {
+            "OR IGNORE "
+        },
+        table_name,
+        columns,
+        select,
+        on_conflict.map(|c| format!(" {}", c)).unwrap_or_default()
+    );
+    DuckDbPlan {
+        setup,
+        cleanup: format!("DROP TABLE {};", stage),
+        stage,
+        merge,
+    }
+}
+// DuckDB release the backend is written against. `ffi` mirrors the
+// `duckdb_result` struct of its `duckdb.h` and reads values with
+// `duckdb_value_varchar`/`duckdb_value_is_null`, deprecated since then, so a
+// library of any other release is refused
+pub const DUCKDB_VERSION: &str = "v1.1";
+pub fn duckdb_version_supported(version: &str) -> bool {
+    version == DUCKDB_VERSION || version.starts_with(&format!("{}.", DUCKDB_VERSION))
+}
+// Writes in-process through the Appender of the DuckDB C API. The library is
+// loaded at runtime, `DUCKDB_LIB` or `with_library` point to it
+#[cfg(feature = "duckdb")]
+#[derive(Clone)]
+pub struct DuckDbBackend {
+    db_path: PathBuf,
+    library: PathBuf,
+}
+#[cfg(feature = "duckdb")]
+impl DuckDbBackend {
+    pub fn new<P: AsRef<Path>>(db_path: P) -> Self {
+        Self {
+            db_path: db_path.as_ref().to_path_buf(),
+            library: std::env::var_os("DUCKDB_LIB")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from(libloading::library_filename("duckdb"))),
+        }
+    }
+    pub fn with_library<P: AsRef<Path>>(mut self, library: P) -> Self {
+        self.library = library.as_ref().to_path_buf();
+        self
+    }
+    pub fn db_path(&self) -> &Path {
+        &self.db_path
+    }
+    pub fn library(&self) -> &Path {
+        &self.library
+    }
+    // `duckdb_library_version` of the library, fails if it can not be loaded
+    pub fn version(&self) -> Result<String, color_eyre::eyre::Error> {
+        ffi::library_version(&self.library)
+    }
+    // First column of the rows returned by `sql`, as text
+    pub fn query_column(&self, sql: &str) -> Result<Vec<Option<String>>, color_eyre::eyre::Error> {
+        ffi::Session::open(&self.library, &self.db_path)?.query_column(sql)
+    }
+    async fn write_df(
+        &self,
+        df: &mut DataFrame,
+        options: &WriteOptions,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        if matches!(options.if_exists, IfExistsOption::Append)
+            && options.migrate != MigrateOption::Ignore
+        {
+            return Err(color_eyre::eyre::eyre!(
+                "Schema migration is only supported with SQLite"
+            ));
+        }
+        let plan = duckdb_plan(df.schema(), options);
+        let session = ffi::Session::open(&self.library, &self.db_path)?;
+        session.execute("BEGIN TRANSACTION")?;
+        match Self::append(&session, df, options, &plan) {
+            Ok(written) => {
+                session.execute("COMMIT")?;
+                Ok(written)
+            }
+            Err(e) => {
+                // The error of the write is the one reported
+                let _ = session.execute("ROLLBACK");
+                Err(e)
+            }
+        }
+    }
+    fn append(
+        session: &ffi::Session,
+        df: &mut DataFrame,
+        options: &WriteOptions,
+        plan: &DuckDbPlan,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        for statement in &plan.setup {
+            session.execute(statement)?;
+        }
+        df.as_single_chunk_par();
+        let columns = df
+            .get_columns()
+            .iter()
+            .map(AppendColumn::new)
+            .collect::<PolarsResult<Vec<_>>>()?;
+        let table_name = &options.table_name;
+        let batch_size: usize = options.batch_size.into();
+        let progress = options.progress();
+        progress.start(ProgressStage::Insert, table_name, Some(df.height() as u64));
+        let mut appender = session.appender(&plan.stage)?;
+        for row in 0..df.height() {
+            for column in &columns {
+                column.append(&mut appender, row)?;
+            }
+            appender.end_row()?;
+            if (row + 1) % batch_size == 0 || row + 1 == df.height() {
+                appender.flush()?;
+                let done = row % batch_size + 1;
+                progress.advance(ProgressStage::Insert, table_name, done as u64);
+            }
+        }
+        appender.close()?;
+        let written = session.execute(&plan.merge)?;
+        session.execute(&plan.cleanup)?;
+        progress.finish(ProgressStage::Insert, table_name);
+        Ok(written)
+    }
+}
+#[cfg(feature = "duckdb")]
+impl SqlBackend for DuckDbBackend {
+    fn write<'a>(
+        &'a self,
+        df: &'a mut DataFrame,
+        options: &'a WriteOptions,
+    ) -> BoxFuture<'a, Result<u64, color_eyre::eyre::Error>> {
+        Box::pin(self.write_df(df, options))
+    }
+}
+#[cfg(feature = "duckdb")]
+impl SqlWriter<DuckDbBackend> {
+    pub fn new_duckdb<P: AsRef<Path>>(db_path: P) -> Self {
+        SqlWriter::with_backend(DuckDbBackend::new(db_path)).n_threads(1)
+    }
+}
+// Values of one column with the physical type the Appender takes
+#[cfg(feature = "duckdb")]
+enum AppendColumn {
+    Bool(BooleanChunked),
+    Int(Int64Chunked),
+    UInt(UInt64Chunked),
+    Float(Float64Chunked),
+    // Days since the unix epoch, the same as `duckdb_date`
+    Date(Int32Chunked),
+    // Microseconds since the unix epoch, the same as `duckdb_timestamp`
+    Timestamp(Int64Chunked),
+    Binary(BinaryChunked),
+    // Anything else goes as text and DuckDB casts it to the column type
+    Text(StringChunked),
+}
+#[cfg(feature = "duckdb")]
+impl AppendColumn {
+    fn new(column: &Column) -> PolarsResult<Self> {
+        let series = column.as_materialized_series();
+        Ok(match series.dtype() {
+            DataType::Boolean => AppendColumn::Bool(series.bool()?.clone()),
+            DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::Int64 => {
+                AppendColumn::Int(series.cast(&DataType::Int64)?.i64()?.clone())
+            }
+            DataType::UInt8 | DataType::UInt16 | DataType::UInt32 | DataType::UInt64 => {
+                AppendColumn::UInt(series.cast(&DataType::UInt64)?.u64()?.clone())
+            }
+            DataType::Float32 | DataType::Float64 => {
+                AppendColumn::Float(series.cast(&DataType::Float64)?.f64()?.clone())
+            }
+            DataType::Date => AppendColumn::Date(series.date()?.physical().clone()),
+            DataType::Datetime(_, _) => AppendColumn::Timestamp(
+                series
+                    .cast(&DataType::Datetime(TimeUnit::Microseconds, None))?
+                    .datetime()?
+                    .physical()
+                    .clone(),
+            ),
+            DataType::Binary => AppendColumn::Binary(series.binary()?.clone()),
+            _ => AppendColumn::Text(series.cast(&DataType::String)?.str()?.clone()),
+        })
+    }
+    fn append(
+        &self,
+        appender: &mut ffi::TableAppender<'_>,
+        row: usize,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        match self {
+            AppendColumn::Bool(c) => appender.append(c.get(row), ffi::Value::Bool),
+            AppendColumn::Int(c) => appender.append(c.get(row), ffi::Value::Int),
+            AppendColumn::UInt(c) => appender.append(c.get(row), ffi::Value::UInt),
+            AppendColumn::Float(c) => appender.append(c.get(row), ffi::Value::Float),
+            AppendColumn::Date(c) => appender.append(c.get(row), ffi::Value::Date),
+            AppendColumn::Timestamp(c) => appender.append(c.get(row), ffi::Value::Timestamp),
+            AppendColumn::Binary(c) => appender.append(c.get(row), ffi::Value::Blob),
+            AppendColumn::Text(c) => appender.append(c.get(row), ffi::Value::Text),
+        }
+    }
+}
+// The few functions of the DuckDB C API the backend needs, see `duckdb.h`
+#[cfg(feature = "duckdb")]
+mod ffi {
+    use libloading::Library;
+    use std::ffi::{c_char, c_void, CStr, CString};
+    use std::path::Path;
+    type Database = *mut c_void;
+    type Connection = *mut c_void;
+    type Appender = *mut c_void;
+    // `DuckDBSuccess` is 0
+    type State = i32;
+    #[repr(C)]
+    struct QueryResult {
+        _column_count: u64,
+        _row_count: u64,
+        _rows_changed: u64,
+        _columns: *mut c_void,
+        _error_message: *mut c_char,
+        _internal_data: *mut c_void,
+    }
+    #[repr(C)]
+    struct Date {
+        _days: i32,
+    }
+    #[repr(C)]
+    struct Timestamp {
+        _micros: i64,
+    }
+    struct Api {
+        open_ext: unsafe extern "C" fn(
+            *const c_char,
+            *mut Database,
+            *mut c_void,
+            *mut *mut c_char,
+        ) -> State,
+        close: unsafe extern "C" fn(*mut Database),
+        connect: unsafe extern "C" fn(Database, *mut Connection) -> State,
+        disconnect: unsafe extern "C" fn(*mut Connection),
+        query: unsafe extern "C" fn(Connection, *const c_char, *mut QueryResult) -> State,
+        result_error: unsafe extern "C" fn(*mut QueryResult) -> *const c_char,
+        rows_changed: unsafe extern "C" fn(*mut QueryResult) -> u64,
+        row_count: unsafe extern "C" fn(*mut QueryResult) -> u64,
+        value_is_null: unsafe extern "C" fn(*mut QueryResult, u64, u64) -> bool,
+        value_varchar: unsafe extern "C" fn(*mut QueryResult, u64, u64) -> *mut c_char,
+        destroy_result: unsafe extern "C" fn(*mut QueryResult),
+        free: unsafe extern "C" fn(*mut c_void),
+        appender_create:
+            unsafe extern "C" fn(Connection, *const c_char, *const c_char, *mut Appender) -> State,
+        appender_error: unsafe extern "C" fn(Appender) -> *const c_char,
+        appender_end_row: unsafe extern "C" fn(Appender) -> State,
+        appender_flush: unsafe extern "C" fn(Appender) -> State,
+        appender_close: unsafe extern "C" fn(Appender) -> State,
+        appender_destroy: unsafe extern "C" fn(*mut Appender) -> State,
+        append_null: unsafe extern "C" fn(Appender) -> State,
+        append_bool: unsafe extern "C" fn(Appender, bool) -> State,
+        append_int64: unsafe extern "C" fn(Appender, i64) -> State,
+        append_uint64: unsafe extern "C" fn(Appender, u64) -> State,
+        append_double: unsafe extern "C" fn(Appender, f64) -> State,
+        append_date: unsafe extern "C" fn(Appender, Date) -> State,
+        append_timestamp: unsafe extern "C" fn(Appender, Timestamp) -> State,
+        append_varchar_length: unsafe extern "C" fn(Appender, *const c_char, u64) -> State,
+        append_blob: unsafe extern "C" fn(Appender, *const c_void, u64) -> State,
+        // Last so the functions are dropped before the library
+        _library: Library,
+    }
+    fn open_library(library: &Path) -> Result<Library, color_eyre::eyre::Error> {
+        // SAFETY: loading runs no code besides the library initializers
+        unsafe { Library::new(library) }
+            .map_err(|e| color_eyre::eyre::eyre!("Could not load {}: {}", library.display(), e))
+    }
+    pub fn library_version(library: &Path) -> Result<String, color_eyre::eyre::Error> {
+        let lib = open_library(library)?;
+        // SAFETY: `duckdb_library_version` takes nothing and returns a static string
+        unsafe {
+            let version: libloading::Symbol<unsafe extern "C" fn() -> *const c_char> =
+                lib.get(b"duckdb_library_version")?;
+            Ok(message(version(), ""))
+        }
+    }
+    impl Api {
+        fn load(library: &Path) -> Result<Self, color_eyre::eyre::Error> {
+            let version = library_version(library)?;
+            if !super::duckdb_version_supported(&version) {
+                return Err(color_eyre::eyre::eyre!(
+                    "{} is DuckDB {}, the backend needs {}.x",
+                    library.display(),
+                    version,
+                    super::DUCKDB_VERSION
+                ));
+            }
+            // SAFETY: the signatures match the `duckdb.h` of `DUCKDB_VERSION`
+            unsafe {
+                let lib = open_library(library)?;
+                Ok(Self {
+                    open_ext: *lib.get(b"duckdb_open_ext")?,
+                    close: *lib.get(b"duckdb_close")?,
+                    connect: *lib.get(b"duckdb_connect")?,
+                    disconnect: *lib.get(b"duckdb_disconnect")?,
+                    query: *lib.get(b"duckdb_query")?,
+                    result_error: *lib.get(b"duckdb_result_error")?,
+                    rows_changed: *lib.get(b"duckdb_rows_changed")?,
+                    row_count: *lib.get(b"duckdb_row_count")?,
+                    value_is_null: *lib.get(b"duckdb_value_is_null")?,
+                    value_varchar: *lib.get(b"duckdb_value_varchar")?,
+                    destroy_result: *lib.get(b"duckdb_destroy_result")?,
+                    free: *lib.get(b"duckdb_free")?,
+                    appender_create: *lib.get(b"duckdb_appender_create")?,
+                    appender_error: *lib.get(b"duckdb_appender_error")?,
+                    appender_end_row: *lib.get(b"duckdb_appender_end_row")?,
+                    appender_flush: *lib.get(b"duckdb_appender_flush")?,
+                    appender_close: *lib.get(b"duckdb_appender_close")?,
+                    appender_destroy: *lib.get(b"duckdb_appender_destroy")?,
+                    append_null: *lib.get(b"duckdb_append_null")?,
+                    append_bool: *lib.get(b"duckdb_append_bool")?,
+                    append_int64: *lib.get(b"duckdb_append_int64")?,
+                    append_uint64: *lib.get(b"duckdb_append_uint64")?,
+                    append_double: *lib.get(b"duckdb_append_double")?,
+                    append_date: *lib.get(b"duckdb_append_date")?,
+                    append_timestamp: *lib.get(b"duckdb_append_timestamp")?,
+                    append_varchar_length: *lib.get(b"duckdb_append_varchar_length")?,
+                    append_blob: *lib.get(b"duckdb_append_blob")?,
+                    _library: lib,
+                })
+            }
+        }
+    }
+    fn message(ptr: *const c_char, default: &str) -> String {
+        if ptr.is_null() {
+            default.to_string()
+        } else {
+            // SAFETY: DuckDB returns NUL terminated strings
+            unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+    // Database and connection, closed on drop
+    pub struct Session {
+        api: Api,
+        db: Database,
+        conn: Connection,
+    }
+    impl Session {
+        pub fn open(library: &Path, path: &Path) -> Result<Self, color_eyre::eyre::Error> {
+            let api = Api::load(library)?;
+            let c_path = CString::new(path.to_string_lossy().as_bytes())?;
+            let mut db: Database = std::ptr::null_mut();
+            let mut error: *mut c_char = std::ptr::null_mut();
+            // SAFETY: the out pointers are valid and the error is freed with `duckdb_free`
+            unsafe {
+                if (api.open_ext)(c_path.as_ptr(), &mut db, std::ptr::null_mut(), &mut error) != 0 {
+                    let msg = message(error, "unknown error");
+                    (api.free)(error as *mut c_void);
+                    return Err(color_eyre::eyre::eyre!(
+                        "Could not open {}: {}",
+                        path.display(),
+                        msg
+                    ));
+                }
+            }
+            let mut conn: Connection = std::ptr::null_mut();
+            // SAFETY: `db` was opened above
+            if unsafe { (api.connect)(db, &mut conn) } != 0 {
+                unsafe { (api.close)(&mut db) };
+                return Err(color_eyre::eyre::eyre!(
+                    "Could not connect to {}",
+                    path.display()
+                ));
+            }
+            Ok(Self { api, db, conn })
+        }
+        // Runs one or more statements, returns the rows changed by the last one
+        pub fn execute(&self, sql: &str) -> Result<u64, color_eyre::eyre::Error> {
+            // SAFETY: the result is the one `duckdb_query` filled
+            self.query(sql, |api, result| unsafe { (api.rows_changed)(result) })
+        }
+        // First column of every row as text, NULL as `None`
+        pub fn query_column(
+            &self,
+            sql: &str,
+        ) -> Result<Vec<Option<String>>, color_eyre::eyre::Error> {
+            self.query(sql, |api, result| {
+                // SAFETY: the row and column are in range and every value is
+                // freed with `duckdb_free`
+                unsafe {
+                    (0..(api.row_count)(result))
+                        .map(|row| {
+                            if (api.value_is_null)(result, 0, row) {
+                                return None;
+                            }
+                            let ptr = (api.value_varchar)(result, 0, row);
+                            let value = message(ptr, "");
+                            (api.free)(ptr as *mut c_void);
+                            Some(value)
+                        })
+                        .collect()
+                }
+            })
+        }
+        fn query<T>(
+            &self,
+            sql: &str,
+            read: impl FnOnce(&Api, &mut QueryResult) -> T,
+        ) -> Result<T, color_eyre::eyre::Error> {
+            let c_sql = CString::new(sql)?;
+            // SAFETY: a zeroed `duckdb_result` is what `duckdb_query` expects
+            let mut result: QueryResult = unsafe { std::mem::zeroed() };
+            unsafe {
+                let state = (self.api.query)(self.conn, c_sql.as_ptr(), &mut result);
+                let res = if state != 0 {
+                    Err(color_eyre::eyre::eyre!(
+                        "DuckDB failed: {}",
+                        message((self.api.result_error)(&mut result), "unknown error")
+                    ))
+                } else {
+                    Ok(read(&self.api, &mut result))
+                };
+                (self.api.destroy_result)(&mut result);
+                res
+            }
+        }
+        pub fn appender(&self, table: &str) -> Result<TableAppender<'_>, color_eyre::eyre::Error> {
+            let c_table = CString::new(table)?;
+            let mut handle: Appender = std::ptr::null_mut();
+            // SAFETY: the out pointer is valid, the appender is destroyed on
+            // drop even when creating it fails
+            let state = unsafe {
+                (self.api.appender_create)(
+                    self.conn,
+                    std::ptr::null(),
+                    c_table.as_ptr(),
+                    &mut handle,
+                )
+            };
+            let appender = TableAppender {
+                api: &self.api,
+                handle,
+            };
+            appender.check(state)?;
+            Ok(appender)
+        }
+    }
+    impl Drop for Session {
+        fn drop(&mut self) {
+            // SAFETY: both handles were opened in `open`
+            unsafe {
+                (self.api.disconnect)(&mut self.conn);
+                (self.api.close)(&mut self.db);
+            }
+        }
+    }
+    pub enum Value<'a> {
+        Bool(bool),
+        Int(i64),
+        UInt(u64),
+        Float(f64),
+        Date(i32),
+        Timestamp(i64),
+        Blob(&'a [u8]),
+        Text(&'a str),
+    }
+    pub struct TableAppender<'a> {
+        api: &'a Api,
+        handle: Appender,
+    }
+    impl TableAppender<'_> {
+        fn check(&self, state: State) -> Result<(), color_eyre::eyre::Error> {
+            if state == 0 {
+                return Ok(());
+            }
+            // SAFETY: the handle is valid or null, the error is owned by it
+            let msg = if self.handle.is_null() {
+                "unknown error".to_string()
+            } else {
+                message(
+                    unsafe { (self.api.appender_error)(self.handle) },
+                    "unknown error",
+                )
+            };
+            Err(color_eyre::eyre::eyre!("DuckDB appender failed: {}", msg))
+        }
+        pub fn append<'v, T>(
+            &mut self,
+            value: Option<T>,
+            to_value: impl Fn(T) -> Value<'v>,
+        ) -> Result<(), color_eyre::eyre::Error> {
+            let api = self.api;
+            let h = self.handle;
+            // SAFETY: the slices outlive the call and their length is passed along
+            let state = unsafe {
+                match value.map(to_value) {
+                    None => (api.append_null)(h),
+                    Some(Value::Bool(v)) => (api.append_bool)(h, v),
+                    Some(Value::Int(v)) => (api.append_int64)(h, v),
+                    Some(Value::UInt(v)) => (api.append_uint64)(h, v),
+                    Some(Value::Float(v)) => (api.append_double)(h, v),
+                    Some(Value::Date(days)) => (api.append_date)(h, Date { _days: days }),
+                    Some(Value::Timestamp(micros)) => {
+                        (api.append_timestamp)(h, Timestamp { _micros: micros })
+                    }
+                    Some(Value::Blob(v)) => {
+                        (api.append_blob)(h, v.as_ptr() as *const c_void, v.len() as u64)
+                    }
+                    Some(Value::Text(v)) => {
+                        (api.append_varchar_length)(h, v.as_ptr() as *const c_char, v.len() as u64)
+                    }
+                }
+            };
+            self.check(state)
+        }
+        pub fn end_row(&mut self) -> Result<(), color_eyre::eyre::Error> {
+            self.check(unsafe { (self.api.appender_end_row)(self.handle) })
+        }
+        pub fn flush(&mut self) -> Result<(), color_eyre::eyre::Error> {
+            self.check(unsafe { (self.api.appender_flush)(self.handle) })
+        }
+        pub fn close(&mut self) -> Result<(), color_eyre::eyre::Error> {
+            self.check(unsafe { (self.api.appender_close)(self.handle) })
+        }
+    }
+    impl Drop for TableAppender<'_> {
+        fn drop(&mut self) {
+            // SAFETY: `duckdb_appender_destroy` also takes a null handle
+            unsafe {
+                (self.api.appender_destroy)(&mut self.handle);
+            }
+        }
+    }
+}
@@ -0,0 +1,1002 @@
+use crate::cancel::CancelToken;
+use crate::config::{EtlConfig, EtlPaths};
+use crate::countries::CountryDictionary;
+use crate::download::download_file_with_progress;
+use crate::ledger::{release_date, IngestLedger, IngestStatus, LedgerEntry};
+use crate::output::OutputSql;
+use crate::pipeline::Pipeline;
+use crate::pl_csv::CsvBatchReader;
+use crate::pl_parquet::ParquetExport;
+use crate::pl_sql::*;
+use crate::progress::{NoProgress, ProgressObserver, ProgressStage, SharedProgress};
+use crate::quality::{QualityCheck, QualityReport, RecordId};
+use crate::quarantine::{Quarantine, REJECTED_TABLE};
+use crate::sentinel::SentinelRules;
+use crate::summary::{FileSummary, RunRecorder, RunSummary, RUNS_TABLE, RUN_FILES_TABLE};
+use crate::unzip::extract_zip_with_progress;
+use crate::utils::*;
+use futures::future::join;
+use polars::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{field, info, info_span, warn, Instrument};
+// Pasos del ETL, cada uno se puede correr por separado desde la línea de comandos
+#[derive(Debug, Clone, Default)]
+pub struct Etl {
+    config: EtlConfig,
+    progress: Option<SharedProgress>,
+    cancel: CancelToken,
+}
+impl Etl {
+    pub fn new(config: EtlConfig) -> Self {
+        Self {
+            progress: config.output.progress.observer(),
+            config,
+            cancel: CancelToken::default(),
+        }
+    }
+    // Con el token cancelado `load` termina el lote en curso y se detiene
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+    // Reemplaza el observador que corresponde a `output.progress`
+    pub fn with_progress(mut self, progress: Option<SharedProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+    fn progress(&self) -> &dyn ProgressObserver {
+        self.progress.as_deref().unwrap_or(&NoProgress)
+    }
+    pub fn with_threads(mut self, n_threads: Option<usize>) -> Self {
+        self.config.writer.n_threads = n_threads;
+        self
+    }
+    pub fn config(&self) -> &EtlConfig {
+        &self.config
+    }
+    pub fn paths(&self) -> &EtlPaths {
+        &self.config.paths
+    }
+    // Si faltan los CSV o el diccionario se descargan y descomprimen los años configurados
+    pub fn ensure_data(&self) -> Result<(), color_eyre::eyre::Error> {
+        let missing =
+            |dir: &Path| -> std::io::Result<bool> { Ok(!dir.is_dir() || is_dir_empty(dir)?) };
+        if missing(&self.config.paths.csv_dir)? || missing(&self.config.paths.dicc_dir)? {
+            self.download(&[])?;
+            self.extract()?;
+        }
+        Ok(())
+    }
+    pub fn download(&self, years: &[u16]) -> Result<(), color_eyre::eyre::Error> {
+        let sources = &self.config.sources;
+        let years = if years.is_empty() {
+            &sources.years[..]
+        } else {
+            years
+        };
+        let urls = years
+            .iter()
+            .map(|y| sources.url_covid(*y))
+            .collect::<Vec<_>>();
+        fs::create_dir_all(&self.config.paths.dicc_zip_dir)?;
+        let path_dicc = self
+            .config
+            .paths
+            .dicc_zip_dir
+            .join(Path::new(&sources.url_dicc).file_name().unwrap_or_default());
+        let span = info_span!("download", years = ?years);
+        let (data, dicc) = block_on(
+            join(
+                download_urls_with_progress(
+                    urls.iter().map(|u| u.as_str()).collect(),
+                    &self.config.paths.zip_dir,
+                    self.progress(),
+                ),
+                download_file_with_progress(&sources.url_dicc, &path_dicc, self.progress()),
+            )
+            .instrument(span),
+        )?;
+        data?;
+        dicc?;
+        Ok(())
+    }
+    pub fn extract(&self) -> Result<(), color_eyre::eyre::Error> {
+        let zips = zip_files(&self.config.paths.zip_dir)?;
+        let _span = info_span!("extract", zips = zips.len()).entered();
+        unzip_data_with_progress(
+            zips.iter().filter_map(|z| z.to_str()).collect(),
+            &self.config.paths.csv_dir,
+            self.progress(),
+        )?;
+        for zip in zip_files(&self.config.paths.dicc_zip_dir)? {
+            extract_zip_with_progress(
+                &zip.to_string_lossy(),
+                &self.config.paths.dicc_dir,
+                self.progress(),
+            )?;
+        }
+        Ok(())
+    }
+    // CSV de `csv_dir` ordenados por nombre, si hay años solo los que los llevan en el nombre
+    pub fn csv_files(&self, years: &[u16]) -> Result<Vec<PathBuf>, color_eyre::eyre::Error> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.config.paths.csv_dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if path.is_file()
+                && (years.is_empty() || years.iter().any(|y| name.contains(&y.to_string())))
+            {
+                files.push(path);
+            }
+        }
+        files.sort();
+        Ok(files)
+    }
+    fn dicc_file(
+        &self,
+        given: &Option<PathBuf>,
+        word: &str,
+    ) -> Result<PathBuf, color_eyre::eyre::Error> {
+        if let Some(path) = given {
+            return Ok(path.clone());
+        }
+        for entry in fs::read_dir(&self.config.paths.dicc_dir)? {
+            let path = entry?.path();
+            let name = path
+                .file_name()
+                .map(|f| f.to_string_lossy().to_string())
+                .unwrap_or_default();
+            if name.contains(word) && name.ends_with(".xlsx") {
+                return Ok(path);
+            }
+        }
+        Err(color_eyre::eyre::eyre!(
+            "No se encontró el archivo de {} en {}",
+            word,
+            self.config.paths.dicc_dir.display()
+        ))
+    }
+    pub fn descriptors(&self) -> Result<PathBuf, color_eyre::eyre::Error> {
+        self.dicc_file(&self.config.paths.descriptors, "Descriptores")
+    }
+    pub fn catalogs_file(&self) -> Result<PathBuf, color_eyre::eyre::Error> {
+        self.dicc_file(&self.config.paths.catalogs_file, "Catalogos")
+    }
+    fn scan_csv(
+        &self,
+        files: Vec<PathBuf>,
+        schema: &SchemaRef,
+    ) -> Result<LazyFrame, color_eyre::eyre::Error> {
+        Ok(LazyCsvReader::new_paths(files.into())
+            .with_has_header(true)
+            .with_dtype_overwrite(Some(schema.clone()))
+            .finish()?)
+    }
+    fn writer(&self) -> Result<SqlWriter, color_eyre::eyre::Error> {
+        fs::create_dir_all(&self.config.paths.db_dir)?;
+        let writer = SqlWriter::new_bulk(self.config.paths.db_path(), BulkLoadProfile::default())?
+            .with_progress(self.progress.clone());
+        Ok(match self.config.writer.n_threads {
+            Some(n) => writer.n_threads(n),
+            None => writer,
+        })
+    }
+    // Catálogos y COVID19MEXICO van a `output.backend`, con SQLite es la
+    // misma base y conexión de `writer`
+    fn output(
+        &self,
+        sql_write: &SqlWriter,
+    ) -> Result<SqlWriter<OutputSql>, color_eyre::eyre::Error> {
+        let output = OutputSql::connect(
+            self.config.output.backend,
+            self.config.output.url.as_deref(),
+            sql_write.backend(),
+        )?;
+        let n_threads = match self.config.writer.n_threads {
+            Some(n) => n,
+            None if output.is_sqlite() => 1,
+            None => num_cpus::get(),
+        };
+        Ok(SqlWriter::with_backend(output)
+            .with_progress(self.progress.clone())
+            .n_threads(n_threads))
+    }
+    // Además de SQLite dejamos la data limpia en Parquet particionada por
+    // año/mes de ingreso y entidad, con los catálogos en archivos aparte
+    fn parquet(&self) -> Result<Option<ParquetExport>, color_eyre::eyre::Error> {
+        if !self.config.output.parquet {
+            return Ok(None);
+        }
+        let sources = if self.config.paths.zip_dir.is_dir() {
+            zip_files(&self.config.paths.zip_dir)?
+        } else {
+            Vec::new()
+        };
+        let descriptors = self.descriptors()?;
+        Ok(Some(
+            ParquetExport::new(&self.config.paths.parquet_dir)
+                .with_row_group_size(self.config.output.parquet_row_group_size)
+                .with_dictionary_version(
+                    descriptors
+                        .file_name()
+                        .and_then(|f| f.to_str())
+                        .and_then(|f| f.split_whitespace().next()),
+                )
+                .with_sources(sources),
+        ))
+    }
+    // Como la columna de los países vienen por nombre se hace una tabla con la
+    // que se puede relacionar, las claves se guardan en un diccionario para que
+    // sean las mismas entre corridas
+    fn countries(&self, lf: &LazyFrame) -> Result<CountryDictionary, color_eyre::eyre::Error> {
+        fs::create_dir_all(&self.config.paths.db_dir)?;
+        let path = self.config.paths.countries_path();
+        let mut countries = CountryDictionary::load(&path)?;
+        get_unique_contry(lf, &mut countries)?;
+        countries.save(&path)?;
+        let unmatched = countries.unmatched();
+        if !unmatched.is_empty() {
+            warn!(
+                paises = unmatched.len(),
+                "países sin código ISO 3166:\n{}",
+                unmatched
+                    .iter()
+                    .map(|p| p.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(countries)
+    }
+    // Tablas del Excel de catálogos más PAISES y PAISES_MAPEO
+    fn catalog_tables(
+        &self,
+        countries: &CountryDictionary,
+    ) -> Result<HashMap<String, DataFrame>, color_eyre::eyre::Error> {
+        let mut tables = HashMap::new();
+        for (table_name, df) in get_df_cat(self.catalogs_file()?)? {
+            let df = df
+                .clone()
+                .lazy()
+                .with_columns(
+                    df.get_columns()
+                        .iter()
+                        .filter(|s| s.dtype() == &DataType::String)
+                        .map(trim_cols)
+                        .collect::<Vec<_>>(),
+                )
+                .collect()?;
+            tables.insert(table_name, df);
+        }
+        tables.insert("PAISES".into(), countries.frame()?);
+        tables.insert("PAISES_MAPEO".into(), countries.mapping_frame()?);
+        Ok(tables)
+    }
+    fn write_countries(
+        &self,
+        sql_write: &SqlWriter<OutputSql>,
+        tables: &HashMap<String, DataFrame>,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        let catalog = |table: &str| {
+            SqliteColOption::default()
+                .with_type_sql(SqliteDataType::INTEGER)
+                .foreign_key(table, "CLAVE")
+        };
+        // PAISES ya puede tener registros que apuntan a sus claves, por eso se
+        // actualiza en lugar de reemplazarse
+        for (table_name, schema, upsert_key) in [
+            (
+                "PAISES",
+                SqliteSchema::new("CLAVE", catalog_key()),
+                Some("CLAVE"),
+            ),
+            // Cada forma en que viene escrito un país y la clave que le toca
+            (
+                "PAISES_MAPEO",
+                SqliteSchema::new("CLAVE", catalog("PAISES")),
+                None,
+            ),
+        ] {
+            if let Some(df) = tables.get(table_name) {
+                sql_write
+                    .clone()
+                    .with_schema(Some(schema))
+                    .with_table(Some(table_name))
+                    .with_index(false)
+                    .if_exists(if upsert_key.is_some() {
+                        IfExistsOption::Append
+                    } else {
+                        IfExistsOption::Replace
+                    })
+                    .with_upsert_key(upsert_key)
+                    .with_foreign_keys(sql_write.backend().foreign_keys())
+                    .finish(&mut df.clone())?;
+            }
+        }
+        Ok(())
+    }
+    pub fn catalogs(&self) -> Result<(), color_eyre::eyre::Error> {
+        let span = info_span!("catalogs", tables = field::Empty).entered();
+        let schema = get_schema_pl(self.descriptors()?)?;
+        let lf = self.scan_csv(self.csv_files(&[])?, &schema)?;
+        let countries = self.countries(&lf)?;
+        let tables = self.catalog_tables(&countries)?;
+        span.record("tables", tables.len());
+        let sql_write = self.writer()?;
+        let output = self.output(&sql_write)?;
+        for (table_name, df) in &tables {
+            if table_name == "PAISES" || table_name == "PAISES_MAPEO" {
+                continue;
+            }
+            // Se actualizan por clave para no romper las llaves foráneas de
+            // COVID19MEXICO cuando se vuelven a cargar después de `load`
+            output
+                .clone()
+                .with_schema(Some(SqliteSchema::new("CLAVE", catalog_key())))
+                .with_table(Some(table_name))
+                .with_index(false)
+                .if_exists(IfExistsOption::Append)
+                .with_upsert_key(Some("CLAVE"))
+                .finish(&mut df.clone())?;
+        }
+        self.write_countries(&output, &tables)?;
+        if let Some(parquet) = self.parquet()? {
+            parquet.write_catalogs(&tables)?;
+        }
+        sql_write.end_bulk_load()?;
+        Ok(())
+    }
+    pub fn schema_sql(&self) -> Result<SqliteSchema, color_eyre::eyre::Error> {
+        let mut schema_sql = get_schema_sql(self.descriptors()?)?;
+        for (column, table) in &self.config.catalogs.foreign_keys {
+            schema_sql.with_column(
+                column,
+                SqliteColOption::default()
+                    .with_type_sql(SqliteDataType::INTEGER)
+                    .foreign_key(table.as_str(), "CLAVE"),
+            );
+        }
+        Ok(schema_sql)
+    }
+    fn write_quality(
+        &self,
+        sql_write: &SqlWriter,
+        report: &QualityReport,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        report.write_json(self.config.paths.quality_path())?;
+        sql_write
+            .clone()
+            .with_table(Some("QUALITY_REPORT"))
+            .with_index(false)
+            .if_exists(IfExistsOption::Append)
+            .finish(&mut report.to_frame()?)?;
+        info!(
+            archivo = %self.config.paths.quality_path().display(),
+            "reporte de calidad\n{}",
+            report
+        );
+        Ok(())
+    }
+    fn quality_check(
+        &self,
+        tables: &HashMap<String, DataFrame>,
+        schema_sql: &SqliteSchema,
+    ) -> Result<QualityCheck, color_eyre::eyre::Error> {
+        QualityCheck::new()
+            .with_max_age(self.config.cleaning.max_age)
+            .with_catalogs(tables, schema_sql)
+    }
+    pub fn validate(&self, years: &[u16]) -> Result<QualityReport, color_eyre::eyre::Error> {
+        let schema = get_schema_pl(self.descriptors()?)?;
+        let files = self.csv_files(years)?;
+        let countries = self.countries(&self.scan_csv(files.clone(), &schema)?)?;
+        let tables = self.catalog_tables(&countries)?;
+        let quality = self.quality_check(&tables, &self.schema_sql()?)?;
+        let mut report = QualityReport::default();
+        for file in files {
+            let _span = info_span!("validate_file", archivo = %file_name(&file)).entered();
+            let lf = self.scan_csv(vec![file.clone()], &schema)?;
+            report.extend(quality.check(clean_data_covid(lf, &countries), &file_name(&file))?);
+        }
+        let sql_write = self.writer()?;
+        self.write_quality(&sql_write, &report)?;
+        sql_write.end_bulk_load()?;
+        Ok(report)
+    }
+    pub fn load(&self, years: &[u16]) -> Result<RunSummary, color_eyre::eyre::Error> {
+        let _span = info_span!("load").entered();
+        let mut run = RunRecorder::start(self.config.paths.db_path());
+        let prepare = Instant::now();
+        let cleaning = &self.config.cleaning;
+        let writer = &self.config.writer;
+        let schema = get_schema_pl(self.descriptors()?)?;
+        let files_data = self.csv_files(years)?;
+        let countries = self.countries(&self.scan_csv(files_data.clone(), &schema)?)?;
+        let tables_cat = self.catalog_tables(&countries)?;
+        let sql_write = self.writer()?;
+        let output = self.output(&sql_write)?;
+        // Los países nuevos de estos archivos tienen que estar en PAISES
+        self.write_countries(&output, &tables_cat)?;
+        let parquet = self.parquet()?;
+        let schema_sql = self.schema_sql()?;
+        // Si se exigen las llaves foráneas los catálogos tienen que existir antes
+        // que COVID19MEXICO, y un valor fuera de catálogo hace fallar el lote
+        if output.backend().foreign_keys() {
+            for (_, options) in schema_sql.iter_fields() {
+                let Some((table, _)) = options.foreign_key_ref() else {
+                    continue;
+                };
+                if output.backend().count(table)?.is_none() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Falta el catálogo {} en la salida {}, se cargan con `catalogs`",
+                        table,
+                        self.config.output.backend.name()
+                    ));
+                }
+            }
+        }
+        // Los códigos "NO APLICA", "SE IGNORA" y "NO ESPECIFICADO" de cada columna
+        // salen de su catálogo, qué se hace con ellos viene de la configuración
+        let mut sentinels = SentinelRules::from_catalogs(&tables_cat, &schema_sql)?
+            .with_default_action(cleaning.sentinel_action);
+        for (column, action) in &cleaning.sentinel_actions {
+            sentinels = sentinels.with_action(column, *action);
+        }
+        // Reporte de calidad de cada archivo por fecha de publicación
+        let quality = self.quality_check(&tables_cat, &schema_sql)?;
+        let mut quality_report = QualityReport::default();
+        // Los MUNICIPIO_RES que no están en su catálogo se rechazan en lugar de
+        // cargarse y solo reportarse al final, se revisa la clave que arma la limpieza
+        let mut batch_quarantine = Quarantine::default();
+        if let Some((table, parent)) = schema_sql
+            .get("MUNICIPIO_RES")
+            .and_then(|options| options.foreign_key_ref())
+        {
+            if let Some(catalog) = tables_cat.get(table) {
+                let codes = catalog
+                    .column(parent)?
+                    .cast(&DataType::UInt64)?
+                    .cast(&DataType::String)?;
+                let codes = codes.str()?.into_iter().flatten().map(|c| c.to_string());
+                batch_quarantine =
+                    batch_quarantine.with_catalog(municipio_res(), Arc::new(codes.collect()));
+            }
+        }
+        run.add_stage("preparacion", prepare.elapsed());
+        // Con el modo por omisión las fechas inválidas quedan como NULL y solo se reportan
+        let to_dates = |df: DataFrame| -> color_eyre::Result<DataFrame> {
+            let (df, report) = parse_dates(df, &FECHA_COLUMNS, cleaning.date_mode)?;
+            if !report.is_empty() {
+                warn!(fechas = report.n_errors(), "fechas inválidas:\n{}", report);
+            }
+            Ok(df)
+        };
+        // Los archivos que ya se cargaron con el mismo contenido o que traen una
+        // publicación anterior a la cargada se omiten
+        let mut ledger = IngestLedger::open(&sql_write)?;
+        let mut planned = Vec::new();
+        for file in files_data {
+            if self.cancel.is_cancelled() {
+                break;
+            }
+            let lf = self.scan_csv(vec![file.clone()], &schema)?;
+            let file_name = file_name(&file);
+            let sha256 = file_sha256(&file)?;
+            if ledger.is_loaded(&sha256) {
+                info!(archivo = %file_name, "ya se cargó, se omite");
+                run.skip_file(file_name);
+                continue;
+            }
+            let release = release_date(&lf)?;
+            if let (Some(last), Some(new)) = (ledger.last_release(&file_name), release.as_deref()) {
+                if new < last {
+                    info!(
+                        archivo = %file_name,
+                        cargada = last,
+                        "trae una publicación anterior a la cargada, se omite"
+                    );
+                    run.skip_file(file_name);
+                    continue;
+                }
+            }
+            let mut entry = LedgerEntry::new(file_name.clone(), sha256.clone(), release);
+            if let Some(previous) = ledger.checkpoint(&sha256) {
+                info!(
+                    archivo = %file_name,
+                    avance = previous.avance,
+                    "se reanuda desde el último lote confirmado"
+                );
+                entry = entry.with_checkpoint(previous.avance, previous.registros);
+            }
+            ledger.record(entry.clone())?;
+            // Al reanudar ya están las particiones de los lotes confirmados
+            if let (Some(parquet), 0) = (&parquet, entry.avance) {
+                parquet.remove_source(&source_name(&file_name))?;
+            }
+            planned.push((file, lf, entry));
+        }
+
+        // Lectura y limpieza en paralelo por archivo y por lote, la escritura en
+        // este hilo; cada archivo se escribe en orden para que su avance sea continuo
+        let pipeline = Pipeline::new(&self.config.pipeline);
+        let read = |i: usize, tx: &SyncSender<FileEvent>| -> color_eyre::Result<()> {
+            let (file, lf, entry) = &planned[i];
+            let _span = info_span!("read_file", archivo = %entry.archivo).entered();
+            // Contar las filas no parsea el CSV, solo da el total del avance
+            let registros = lf.clone().select([len()]).collect()?;
+            let start = FileEvent::Start {
+                file: i,
+                registros: registros
+                    .column("len")?
+                    .cast(&DataType::UInt64)?
+                    .u64()?
+                    .get(0),
+            };
+            if tx.send(start).is_err() {
+                return Ok(());
+            }
+            // Tamaño de una fila para dimensionar los lotes con el presupuesto
+            let sample = lf.clone().slice(0, 1_000).collect()?;
+            let bytes_per_row = (sample.estimated_size() / sample.height().max(1)) as u64;
+            let n = pipeline
+                .batch_rows(bytes_per_row)
+                .min(writer.batch_size as usize);
+            let bytes = n as u64 * bytes_per_row;
+            // Cada lote se lee una sola vez, al reanudar se salta lo confirmado
+            let mut batches = CsvBatchReader::new(file)
+                .with_dtype_overwrite(Some(schema.clone()))
+                .with_skip_rows(entry.avance as usize)
+                .with_batch_size(n)
+                .finish()?;
+            let mut seq = 0;
+            loop {
+                if !pipeline.budget().acquire(bytes) {
+                    return Ok(());
+                }
+                let lectura = Instant::now();
+                let offset = batches.offset();
+                let Some(df) = batches.next().transpose()? else {
+                    pipeline.budget().release(bytes);
+                    break;
+                };
+                let batch = LoadBatch {
+                    file: i,
+                    seq,
+                    offset,
+                    bytes,
+                    read: df.height() as u64,
+                    raw: DataFrame::default(),
+                    df,
+                    rejected: DataFrame::default(),
+                    quarantine: batch_quarantine.clone(),
+                    quality: QualityReport::default(),
+                    ids: Vec::new(),
+                    lectura: lectura.elapsed(),
+                    limpieza: Duration::ZERO,
+                    validacion: Duration::ZERO,
+                };
+                if tx.send(FileEvent::Batch(Box::new(batch))).is_err() {
+                    return Ok(());
+                }
+                seq += 1;
+            }
+            let _ = tx.send(FileEvent::Done {
+                file: i,
+                batches: seq,
+            });
+            Ok(())
+        };
+        //limpiamos la data cambiando las columnas de los países por sus hashmap y ademas hacemos unos
+        // cambios en la columna de las entidades que nos permitan mapear bien las dos tablas
+        let clean = |event: FileEvent| -> color_eyre::Result<FileEvent> {
+            let FileEvent::Batch(mut batch) = event else {
+                return Ok(event);
+            };
+            let archivo = &planned[batch.file].2.archivo;
+            // El reporte de calidad se arma con los lotes, así el archivo se lee
+            // una sola vez y al reanudar solo se cuenta lo que falta. Los
+            // ID_REGISTRO repetidos entre lotes los suma el escritor con `ids`
+            let validacion = Instant::now();
+            batch.quality = quality.check(
+                clean_data_covid(batch.df.clone().lazy(), &countries),
+                archivo,
+            )?;
+            batch.ids = quality.ids(batch.df.clone().lazy())?;
+            batch.validacion = validacion.elapsed();
+            let limpieza = Instant::now();
+            // Las claves nulas o repetidas en el lote y los municipios fuera de
+            // catálogo se separan antes de limpiar
+            let (df, rejected) =
+                batch
+                    .quarantine
+                    .split(batch.df, &HashSet::new(), archivo, batch.offset)?;
+            batch.raw = df.clone();
+            let df = sentinels
+                .apply(clean_data_covid(df.lazy(), &countries))?
+                .collect()?;
+            batch.df = to_dates(df)?;
+            batch.rejected = rejected;
+            batch.limpieza = limpieza.elapsed();
+            Ok(FileEvent::Batch(batch))
+        };
+        // Los registros que no se pueden insertar se guardan con su motivo en
+        // COVID19MEXICO_RECHAZADOS
+        let mut quarantine = Quarantine::default();
+        let mut loading = planned
+            .iter()
+            .map(|(_, _, entry)| FileLoad::new(entry.clone()))
+            .collect::<Vec<_>>();
+        let write_rejected = |rejected: &mut DataFrame| -> color_eyre::Result<()> {
+            if rejected.height() > 0 {
+                sql_write
+                    .clone()
+                    .with_table(Some(REJECTED_TABLE))
+                    .if_exists(IfExistsOption::Append)
+                    .with_migrate(MigrateOption::AddColumns)
+                    .with_index(false)
+                    .finish(rejected)?;
+            }
+            Ok(())
+        };
+        let write = |event: FileEvent| -> color_eyre::Result<bool> {
+            let i = match event {
+                FileEvent::Start { file, registros } => {
+                    let state = &mut loading[file];
+                    self.progress()
+                        .start(ProgressStage::Load, &state.entry.archivo, registros);
+                    self.progress().advance(
+                        ProgressStage::Load,
+                        &state.entry.archivo,
+                        state.entry.avance as u64,
+                    );
+                    state.started = Some(Instant::now());
+                    file
+                }
+                FileEvent::Batch(batch) => {
+                    let file = batch.file;
+                    loading[file].pending.insert(batch.seq, *batch);
+                    file
+                }
+                FileEvent::Done { file, batches } => {
+                    loading[file].batches = Some(batches);
+                    file
+                }
+            };
+            let state = &mut loading[i];
+            let file = state.entry.archivo.clone();
+            while state.started.is_some() {
+                let Some(mut batch) = state.pending.remove(&state.next) else {
+                    break;
+                };
+                let span = info_span!(
+                    "batch",
+                    archivo = %file,
+                    offset = batch.offset,
+                    rows = field::Empty
+                )
+                .entered();
+                run.add_stage("lectura", batch.lectura);
+                run.add_stage("limpieza", batch.limpieza);
+                run.add_stage("validacion", batch.validacion);
+                quality_report.merge(batch.quality);
+                let mut repetidos = BTreeMap::new();
+                for id in std::mem::take(&mut batch.ids) {
+                    let release = id.0.clone();
+                    if !state.ids.insert(id) {
+                        *repetidos.entry(release).or_default() += 1;
+                    }
+                }
+                quality_report.merge(quality.duplicated(&file, &repetidos));
+                run.observe_batch(&batch.df);
+                // Solo se rechazan las claves que ya están con una publicación más
+                // reciente, las demás se actualizan con la nueva. Se revisa aquí para
+                // ver lo que escribieron los lotes anteriores; se guardan sin limpiar
+                let validacion = Instant::now();
+                let existing = match &state.entry.fecha_actualizacion {
+                    Some(release) => output.backend().newer_keys(
+                        "COVID19MEXICO",
+                        quarantine.key_column(),
+                        batch.raw.column(quarantine.key_column())?,
+                        release,
+                    )?,
+                    None => Default::default(),
+                };
+                let (mut df, mut rejected) =
+                    quarantine.split_clean(batch.raw, batch.df, &existing, &file, batch.offset)?;
+                quarantine.merge(&batch.quarantine);
+                write_rejected(&mut batch.rejected)?;
+                write_rejected(&mut rejected)?;
+                run.add_stage("validacion", validacion.elapsed());
+                span.record("rows", df.height());
+                let summary = &mut state.summary;
+                summary.leidos += batch.read;
+                summary.rechazados += (batch.rejected.height() + rejected.height()) as u64;
+                summary.limpios += df.height() as u64;
+                if let Some(parquet) = &parquet {
+                    run.time("parquet", || {
+                        parquet.write_partitions(&df, &source_name(&file))
+                    })?;
+                }
+                let insert = Instant::now();
+                summary.insertados += output
+                    .clone()
+                    .with_schema(Some(schema_sql.clone()))
+                    .with_table(Some("COVID19MEXICO".to_string()))
+                    .with_batch_size(writer.insert_batch_size)
+                    .with_parallel(writer.parallel)
+                    .if_exists(IfExistsOption::Append)
+                    .with_migrate(output.backend().migrate())
+                    .with_strict_insert(writer.strict_insert)
+                    .with_upsert_key(Some("ID_REGISTRO"))
+                    .with_foreign_keys(output.backend().foreign_keys())
+                    .with_index(false)
+                    .finish(&mut df)?;
+                run.add_stage("insercion", insert.elapsed());
+                // Después de cada lote se guarda en la bitácora el offset del siguiente,
+                // así una carga interrumpida se reanuda sin volver a escribir lo confirmado
+                let registros = state.entry.registros + df.height() as i64;
+                state.entry = state
+                    .entry
+                    .clone()
+                    .with_checkpoint(batch.offset + batch.read as i64, registros);
+                ledger.record(state.entry.clone())?;
+                pipeline.budget().release(batch.bytes);
+                self.progress()
+                    .advance(ProgressStage::Load, &file, batch.read);
+                state.next += 1;
+                drop(span);
+                if self.cancel.is_cancelled() {
+                    warn!(archivo = %file, avance = state.entry.avance, "carga cancelada");
+                    return Ok(false);
+                }
+            }
+            if state.batches == Some(state.next) && !state.done {
+                state.done = true;
+                state.ids = HashSet::new();
+                state.summary.segundos = state
+                    .started
+                    .map(|s| s.elapsed().as_secs_f64())
+                    .unwrap_or_default();
+                let registros = state.entry.registros;
+                ledger.record(
+                    state
+                        .entry
+                        .clone()
+                        .with_status(IngestStatus::Loaded, registros),
+                )?;
+                self.progress().finish(ProgressStage::Load, &file);
+                info!(archivo = %file, registros, "archivo cargado");
+                run.add_file(state.summary.clone());
+            }
+            Ok(true)
+        };
+        let loaded = pipeline.run(planned.len(), read, clean, write);
+        // Los archivos sin terminar conservan el avance del último lote confirmado
+        for state in loading.into_iter().filter(|s| !s.done) {
+            let status = match &loaded {
+                Ok(_) => {
+                    run.cancel();
+                    IngestStatus::Cancelled
+                }
+                Err(_) => IngestStatus::Failed,
+            };
+            let registros = state.entry.registros;
+            ledger.record(state.entry.with_status(status, registros))?;
+            if state.started.is_some() {
+                self.progress()
+                    .finish(ProgressStage::Load, &state.summary.archivo);
+                run.add_file(state.summary);
+            }
+        }
+        loaded?;
+        let validation = Instant::now();
+        self.write_quality(&sql_write, &quality_report)?;
+        // Los registros se cargan sin exigir las llaves foráneas, al final se
+        // reportan los valores que no existen en los catálogos. Solo SQLite
+        // tiene `foreign_key_check`
+        if output.backend().is_sqlite() {
+            let report = sql_write
+                .clone()
+                .with_table(Some("COVID19MEXICO"))
+                .check_foreign_keys()?;
+            if !report.is_empty() {
+                warn!(
+                    registros = report.n_rows(),
+                    "registros con valores fuera de catálogo:\n{}", report
+                );
+            }
+        }
+        if !quarantine.is_empty() {
+            warn!(
+                registros = quarantine.n_rows(),
+                tabla = REJECTED_TABLE,
+                "registros rechazados:\n{}",
+                quarantine
+            );
+        }
+        run.add_stage("validacion", validation.elapsed());
+        let close = Instant::now();
+        sql_write.end_bulk_load()?;
+        if let Some(parquet) = &parquet {
+            parquet.write_manifest()?;
+        }
+        run.add_stage("cierre", close.elapsed());
+        let summary = run.finish(self.config.paths.db_path());
+        self.write_summary(&sql_write, &summary)?;
+        Ok(summary)
+    }
+    fn write_summary(
+        &self,
+        sql_write: &SqlWriter,
+        summary: &RunSummary,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        summary.write_json(self.config.paths.summary_path())?;
+        let append = |table: &str, df: &mut DataFrame| {
+            sql_write
+                .clone()
+                .with_table(Some(table))
+                .with_index(false)
+                .if_exists(IfExistsOption::Append)
+                .with_migrate(MigrateOption::AddColumns)
+                .finish(df)
+        };
+        append(RUNS_TABLE, &mut summary.to_frame()?)?;
+        if !summary.archivos.is_empty() {
+            append(RUN_FILES_TABLE, &mut summary.files_frame()?)?;
+        }
+        info!(
+            archivo = %self.config.paths.summary_path().display(),
+            insertados = summary.insertados(),
+            segundos = summary.segundos,
+            "resumen de la corrida guardado"
+        );
+        Ok(())
+    }
+    pub fn status(&self) -> Result<EtlStatus, color_eyre::eyre::Error> {
+        let mut status = EtlStatus::default();
+        if !self.config.paths.db_path().exists() {
+            return Ok(status);
+        }
+        let sql_write = SqlWriter::new(self.config.paths.db_path())?;
+        let control = OutputSql::Sqlite(sql_write.backend().clone());
+        let output = self.output(&sql_write)?;
+        for (table, sql) in [
+            ("COVID19MEXICO", output.backend()),
+            (REJECTED_TABLE, &control),
+        ] {
+            if let Some(n) = sql.count(table)? {
+                status.tables.push((table.to_string(), n));
+            }
+        }
+        status.files = IngestLedger::open(&sql_write)?.entries().to_vec();
+        Ok(status)
+    }
+}
+// Archivos cargados y registros por tabla
+#[derive(Debug, Clone, Default)]
+pub struct EtlStatus {
+    pub files: Vec<LedgerEntry>,
+    pub tables: Vec<(String, i64)>,
+}
+impl std::fmt::Display for EtlStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.files.is_empty() && self.tables.is_empty() {
+            return writeln!(f, "No se ha cargado ningún archivo");
+        }
+        for e in &self.files {
+            write!(
+                f,
+                "{} {} {} registros, {} ({})",
+                e.archivo,
+                e.fecha_actualizacion.as_deref().unwrap_or("-"),
+                e.registros,
+                e.estado.code(),
+                e.actualizado_en
+            )?;
+            if e.estado != IngestStatus::Loaded && e.avance > 0 {
+                write!(f, ", se reanuda en la fila {}", e.avance)?;
+            }
+            writeln!(f)?;
+        }
+        for (table, n) in &self.tables {
+            writeln!(f, "{}: {} registros", table, n)?;
+        }
+        Ok(())
+    }
+}
+fn catalog_key() -> SqliteColOption {
+    SqliteColOption::default()
+        .with_type_sql(SqliteDataType::INTEGER)
+        .with_primary_key(true)
+}
+// Lo que va de los lectores al escritor
+enum FileEvent {
+    // Antes del primer lote, con los registros del archivo
+    Start { file: usize, registros: Option<u64> },
+    Batch(Box<LoadBatch>),
+    // Después del último lote
+    Done { file: usize, batches: u64 },
+}
+struct LoadBatch {
+    file: usize,
+    // Orden del lote en su archivo
+    seq: u64,
+    offset: i64,
+    // Reservados del presupuesto de memoria hasta que se escribe
+    bytes: u64,
+    read: u64,
+    // Los registros aceptados como se leyeron, en el mismo orden que `df`
+    raw: DataFrame,
+    df: DataFrame,
+    rejected: DataFrame,
+    quarantine: Quarantine,
+    // Reporte de calidad de los registros del lote
+    quality: QualityReport,
+    // (FECHA_ACTUALIZACION, ID_REGISTRO) distintos del lote
+    ids: Vec<RecordId>,
+    lectura: Duration,
+    limpieza: Duration,
+    validacion: Duration,
+}
+// Estado de cada archivo en el escritor
+struct FileLoad {
+    entry: LedgerEntry,
+    summary: FileSummary,
+    started: Option<Instant>,
+    next: u64,
+    // Lotes que llegaron antes que los anteriores de su archivo
+    pending: BTreeMap<u64, LoadBatch>,
+    batches: Option<u64>,
+    done: bool,
+    // (FECHA_ACTUALIZACION, ID_REGISTRO) de los lotes ya escritos
+    ids: HashSet<RecordId>,
+}
+impl FileLoad {
+    fn new(entry: LedgerEntry) -> Self {
+        let mut summary = FileSummary::new(entry.archivo.clone());
+        summary.fecha_actualizacion = entry.fecha_actualizacion.clone();
+        Self {
+            entry,
+            summary,
+            started: None,
+            next: 0,
+            pending: BTreeMap::new(),
+            batches: None,
+            done: false,
+            ids: HashSet::new(),
+        }
+    }
+}
+// Nombre del CSV sin extensión, identifica sus particiones Parquet
+fn source_name(file: &str) -> String {
+    Path::new(file)
+        .file_stem()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+fn zip_files(dir: &Path) -> Result<Vec<PathBuf>, color_eyre::eyre::Error> {
+    let mut files = Vec::new();
+    if dir.is_dir() {
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "zip") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
@@ -0,0 +1,362 @@
+use crate::config::{EtlConfig, EtlPaths, SourcesConfig};
+use crate::etl::Etl;
+use crate::pl_sql::{ForeignKeyReport, SqlWriter, SqliteSchema};
+use crate::quarantine::REJECTED_TABLE;
+use crate::summary::RunSummary;
+use crate::synthetic::{SyntheticDataset, CATALOGS_FILE, COVID_COLUMNS, DESCRIPTORS_FILE};
+use crate::utils::get_all_data_from;
+use polars::prelude::DataType;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+// Nombre del zip del diccionario en el portal de datos abiertos
+pub const DICC_ZIP: &str = "diccionario_datos_abiertos.zip";
+// Servidor HTTP local que sirve los archivos de una carpeta con las mismas
+// rutas que el portal de datos abiertos. Solo atiende GET y se detiene al
+// soltarlo
+pub struct MirrorServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    requests: Arc<Mutex<Vec<String>>>,
+    handle: Option<JoinHandle<()>>,
+}
+impl MirrorServer {
+    pub fn start<P: AsRef<Path>>(root: P) -> Result<Self, color_eyre::eyre::Error> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let root = root.as_ref().to_path_buf();
+        let handle = {
+            let stop = Arc::clone(&stop);
+            let requests = Arc::clone(&requests);
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if stop.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let root = root.clone();
+                    let requests = Arc::clone(&requests);
+                    // Un hilo por conexión, las descargas van en paralelo
+                    thread::spawn(move || {
+                        let _ = serve(stream, &root, &requests);
+                    });
+                }
+            })
+        };
+        Ok(Self {
+            addr,
+            stop,
+            requests,
+            handle: Some(handle),
+        })
+    }
+    pub fn base_url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+    // Rutas pedidas hasta ahora, en el orden en que llegaron
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+impl Drop for MirrorServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // `accept` está bloqueado, una conexión más lo despierta para salir
+        let _ = TcpStream::connect(self.addr);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+fn serve(stream: TcpStream, root: &Path, requests: &Mutex<Vec<String>>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or("/"));
+    let route = target.split('?').next().unwrap_or("/").to_string();
+    // Se descartan los encabezados
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header != "\r\n" && header != "\n" {
+        header.clear();
+    }
+    requests.lock().unwrap().push(route.clone());
+    let relative = Path::new(route.trim_start_matches('/'));
+    let path = root.join(relative);
+    let mut stream = stream;
+    // Nada fuera de la carpeta raíz
+    let inside = relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if method != "GET" || !inside || !path.is_file() {
+        return stream.write_all(
+            b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        );
+    }
+    let mut file = File::open(&path)?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        file.metadata()?.len()
+    )?;
+    io::copy(&mut file, &mut stream)?;
+    stream.flush()
+}
+// Prueba de extremo a extremo del ETL: publica un `SyntheticDataset` como
+// zips en un `MirrorServer` y descarga, crea el esquema, carga los catálogos
+// y los registros en una carpeta temporal que se borra al soltarlo
+pub struct EtlHarness {
+    dir: PathBuf,
+    dataset: SyntheticDataset,
+    config: EtlConfig,
+    server: MirrorServer,
+}
+impl EtlHarness {
+    // `name` distingue la carpeta temporal de cada prueba
+    pub fn new(name: &str, dataset: SyntheticDataset) -> Result<Self, color_eyre::eyre::Error> {
+        let dir = std::env::temp_dir().join(format!(
+            "db_cov19mx_harness_{}_{}",
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        let mirror = dir.join("mirror");
+        publish(&dataset, dir.join("generado"), &mirror)?;
+        let server = MirrorServer::start(&mirror)?;
+        let mut config = EtlConfig {
+            sources: SourcesConfig {
+                years: dataset.years().to_vec(),
+                url_covid: format!(
+                    "{}/historicos/{{year}}/COVID19MEXICO{{year}}.zip",
+                    server.base_url()
+                ),
+                url_dicc: format!("{}/{}", server.base_url(), DICC_ZIP),
+            },
+            paths: EtlPaths {
+                zip_dir: dir.join("data_zip"),
+                dicc_zip_dir: dir.join("dicc_zip"),
+                csv_dir: dir.join("data_csv"),
+                dicc_dir: dir.join("data_dicc"),
+                db_dir: dir.join("DB"),
+                parquet_dir: dir.join("data_parquet"),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // Parquet no entra en lo que se revisa y es lo más lento de la carga
+        config.output.parquet = false;
+        Ok(Self {
+            dir,
+            dataset,
+            config,
+            server,
+        })
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    // Carpeta que sirve `server`, con las rutas del portal
+    pub fn mirror_dir(&self) -> PathBuf {
+        self.dir.join("mirror")
+    }
+    pub fn dataset(&self) -> &SyntheticDataset {
+        &self.dataset
+    }
+    pub fn config(&self) -> &EtlConfig {
+        &self.config
+    }
+    pub fn server(&self) -> &MirrorServer {
+        &self.server
+    }
+    // Un `Etl` nuevo con la configuración del arnés, para agregarle
+    // cancelación o progreso
+    pub fn etl(&self) -> Etl {
+        Etl::new(self.config.clone())
+    }
+    pub fn get_all_data(&self) -> Result<(), color_eyre::eyre::Error> {
+        get_all_data_from(&self.config.sources, &self.config.paths)
+    }
+    // get_all_data -> esquema -> catálogos -> carga. Falla antes de cargar si
+    // los descriptores descargados no traen todas las columnas de los CSV
+    pub fn run(&self) -> Result<RunSummary, color_eyre::eyre::Error> {
+        self.get_all_data()?;
+        let etl = self.etl();
+        let schema = etl.schema_sql()?;
+        let missing = COVID_COLUMNS
+            .iter()
+            .filter(|c| schema.get(c).is_none())
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(color_eyre::eyre::eyre!(
+                "Columnas sin descriptor: {}",
+                missing.join(", ")
+            ));
+        }
+        etl.catalogs()?;
+        etl.load(self.dataset.years())
+    }
+    pub fn writer(&self) -> Result<SqlWriter, color_eyre::eyre::Error> {
+        SqlWriter::new(self.config.paths.db_path())
+    }
+    // Primera columna de la primera fila de `query` como entero
+    pub fn query_i64(&self, query: &str) -> Result<i64, color_eyre::eyre::Error> {
+        let df = self
+            .writer()?
+            .reader()
+            .with_table(None::<String>)
+            .with_query(Some(query))
+            .finish()?;
+        let n = df.get_columns()[0].cast(&DataType::Int64)?.i64()?.get(0);
+        Ok(n.unwrap_or_default())
+    }
+    // Registros de `table`, 0 si no existe
+    pub fn count(&self, table: &str) -> Result<i64, color_eyre::eyre::Error> {
+        if !self.writer()?.reader().table_exists(table)? {
+            return Ok(0);
+        }
+        self.query_i64(&format!("SELECT COUNT(*) AS N FROM {}", table))
+    }
+    // Lo que quedó en la base después de `run`
+    pub fn snapshot(&self) -> Result<DbSnapshot, color_eyre::eyre::Error> {
+        let mut snapshot = DbSnapshot::default();
+        for table in ["COVID19MEXICO", REJECTED_TABLE, "PAISES", "PAISES_MAPEO"] {
+            snapshot
+                .registros
+                .insert(table.to_string(), self.count(table)?);
+        }
+        snapshot.llaves_foraneas = self.writer()?.check_foreign_keys()?;
+        // Las llaves foráneas no se revisan al insertar si SQLite las tiene
+        // apagadas, por eso los países se buscan directamente en PAISES
+        for (table, column) in [
+            ("COVID19MEXICO", "PAIS_NACIONALIDAD"),
+            ("COVID19MEXICO", "PAIS_ORIGEN"),
+            ("PAISES_MAPEO", "CLAVE"),
+        ] {
+            if self.count(table)? > 0 {
+                let n = self.query_i64(&format!(
+                    "SELECT COUNT(DISTINCT {column}) AS N FROM {table} \
+                     WHERE {column} IS NOT NULL AND {column} NOT IN (SELECT CLAVE FROM PAISES)"
+                ))?;
+                snapshot
+                    .paises_faltantes
+                    .insert(column_key(table, column), n);
+            }
+        }
+        let schema = self.etl().schema_sql()?;
+        snapshot.columnas_faltantes = self.missing_columns("COVID19MEXICO", &schema)?;
+        Ok(snapshot)
+    }
+    fn missing_columns(
+        &self,
+        table: &str,
+        schema: &SqliteSchema,
+    ) -> Result<Vec<String>, color_eyre::eyre::Error> {
+        let mut missing = Vec::new();
+        for column in schema.iter_columns() {
+            let n = self.query_i64(&format!(
+                "SELECT COUNT(*) AS N FROM pragma_table_info('{}') WHERE name = '{}'",
+                table, column
+            ))?;
+            if n == 0 {
+                missing.push(column.clone());
+            }
+        }
+        Ok(missing)
+    }
+}
+impl Drop for EtlHarness {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+fn column_key(table: &str, column: &str) -> String {
+    format!("{}.{}", table, column)
+}
+// Deja en `mirror` los zips con las rutas del portal:
+// historicos/{year}/COVID19MEXICO{year}.zip y diccionario_datos_abiertos.zip
+pub fn publish<P: AsRef<Path>, Q: AsRef<Path>>(
+    dataset: &SyntheticDataset,
+    work_dir: P,
+    mirror: Q,
+) -> Result<(), color_eyre::eyre::Error> {
+    let (work_dir, mirror) = (work_dir.as_ref(), mirror.as_ref());
+    let (descriptors, catalogs) = dataset.write_dictionary(work_dir)?;
+    fs::create_dir_all(mirror)?;
+    write_zip(
+        mirror.join(DICC_ZIP),
+        &[(DESCRIPTORS_FILE, descriptors), (CATALOGS_FILE, catalogs)],
+    )?;
+    for (year, csv) in dataset.years().iter().zip(dataset.write_csvs(work_dir)?) {
+        let dir = mirror.join("historicos").join(year.to_string());
+        fs::create_dir_all(&dir)?;
+        let name = SyntheticDataset::csv_name(*year);
+        write_zip(
+            dir.join(format!("COVID19MEXICO{}.zip", year)),
+            &[(name.as_str(), csv)],
+        )?;
+    }
+    Ok(())
+}
+fn write_zip<P: AsRef<Path>>(
+    path: P,
+    files: &[(&str, PathBuf)],
+) -> Result<(), color_eyre::eyre::Error> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    for (name, file) in files {
+        zip.start_file(*name, SimpleFileOptions::default())?;
+        io::copy(&mut File::open(file)?, &mut zip)?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+// Conteos y revisiones de la base de un `EtlHarness`
+#[derive(Debug, Clone, Default)]
+pub struct DbSnapshot {
+    // Registros por tabla
+    pub registros: BTreeMap<String, i64>,
+    pub llaves_foraneas: ForeignKeyReport,
+    // Claves distintas sin país en PAISES, por `TABLA.COLUMNA`
+    pub paises_faltantes: BTreeMap<String, i64>,
+    // Columnas del esquema de los descriptores que no tiene COVID19MEXICO
+    pub columnas_faltantes: Vec<String>,
+}
+impl DbSnapshot {
+    pub fn registros(&self, table: &str) -> i64 {
+        self.registros.get(table).copied().unwrap_or_default()
+    }
+    pub fn is_consistent(&self) -> bool {
+        self.llaves_foraneas.is_empty()
+            && self.paises_faltantes.values().all(|n| *n == 0)
+            && self.columnas_faltantes.is_empty()
+    }
+}
+impl std::fmt::Display for DbSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (table, n) in &self.registros {
+            writeln!(f, "{}: {} registros", table, n)?;
+        }
+        for (column, n) in &self.paises_faltantes {
+            writeln!(f, "{}: {} claves sin país", column, n)?;
+        }
+        if !self.llaves_foraneas.is_empty() {
+            write!(f, "{}", self.llaves_foraneas)?;
+        }
+        for column in &self.columnas_faltantes {
+            writeln!(f, "falta la columna {}", column)?;
+        }
+        Ok(())
+    }
+}
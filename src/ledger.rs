@@ -0,0 +1,205 @@
+use crate::pl_sql::{
+    IfExistsOption, MigrateOption, SqlWriter, SqliteBackend, SqliteColOption, SqliteDataType,
+    SqliteSchema,
+};
+use chrono::Utc;
+use polars::prelude::*;
+pub const LEDGER_TABLE: &str = "INGESTA_ARCHIVOS";
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IngestStatus {
+    Started,
+    Loaded,
+    Failed,
+    // Se detuvo con Ctrl-C, se reanuda desde `avance`
+    Cancelled,
+}
+impl IngestStatus {
+    pub fn code(&self) -> &'static str {
+        match self {
+            IngestStatus::Started => "EN_PROCESO",
+            IngestStatus::Loaded => "CARGADO",
+            IngestStatus::Failed => "FALLIDO",
+            IngestStatus::Cancelled => "CANCELADO",
+        }
+    }
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "EN_PROCESO" => Some(IngestStatus::Started),
+            "CARGADO" => Some(IngestStatus::Loaded),
+            "FALLIDO" => Some(IngestStatus::Failed),
+            "CANCELADO" => Some(IngestStatus::Cancelled),
+            _ => None,
+        }
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub archivo: String,
+    pub sha256: String,
+    pub fecha_actualizacion: Option<String>,
+    pub registros: i64,
+    pub estado: IngestStatus,
+    // Filas del CSV ya escritas, el siguiente lote empieza en este offset
+    pub avance: i64,
+    pub actualizado_en: String,
+}
+impl LedgerEntry {
+    pub fn new<T: Into<String>>(
+        archivo: T,
+        sha256: T,
+        fecha_actualizacion: Option<String>,
+    ) -> Self {
+        Self {
+            archivo: archivo.into(),
+            sha256: sha256.into(),
+            fecha_actualizacion,
+            registros: 0,
+            estado: IngestStatus::Started,
+            avance: 0,
+            actualizado_en: Utc::now().to_rfc3339(),
+        }
+    }
+    pub fn with_status(mut self, estado: IngestStatus, registros: i64) -> Self {
+        self.estado = estado;
+        self.registros = registros;
+        self.actualizado_en = Utc::now().to_rfc3339();
+        self
+    }
+    // Avance después de confirmar un lote
+    pub fn with_checkpoint(mut self, avance: i64, registros: i64) -> Self {
+        self.avance = avance;
+        self.registros = registros;
+        self.actualizado_en = Utc::now().to_rfc3339();
+        self
+    }
+}
+// Bitácora de los archivos cargados en `INGESTA_ARCHIVOS`, cada archivo se
+// identifica por su hash para no volver a cargar el mismo contenido
+pub struct IngestLedger {
+    writer: SqlWriter<SqliteBackend>,
+    entries: Vec<LedgerEntry>,
+}
+impl IngestLedger {
+    pub fn open(writer: &SqlWriter<SqliteBackend>) -> Result<Self, color_eyre::eyre::Error> {
+        let writer = writer
+            .clone()
+            .with_table(Some(LEDGER_TABLE))
+            .with_schema(Some(Self::schema()))
+            .with_index(false)
+            .if_exists(IfExistsOption::Append)
+            .with_migrate(MigrateOption::AddColumns)
+            .with_upsert_key(Some("SHA256"));
+        let reader = writer.reader();
+        let mut entries = Vec::new();
+        if reader.table_exists(LEDGER_TABLE)? {
+            let df = reader.finish()?;
+            let text = |c: &str| -> PolarsResult<Vec<Option<String>>> {
+                Ok(df
+                    .column(c)?
+                    .cast(&DataType::String)?
+                    .str()?
+                    .into_iter()
+                    .map(|v| v.map(|v| v.to_string()))
+                    .collect())
+            };
+            let registros = df.column("REGISTROS")?.cast(&DataType::Int64)?;
+            // Las bitácoras anteriores al checkpoint no tienen AVANCE
+            let avance = match df.column("AVANCE") {
+                Ok(avance) => avance.cast(&DataType::Int64)?,
+                Err(_) => Column::full_null("AVANCE".into(), df.height(), &DataType::Int64),
+            };
+            for (((((archivo, sha256), fecha), (estado, actualizado_en)), registros), avance) in
+                text("ARCHIVO")?
+                    .into_iter()
+                    .zip(text("SHA256")?)
+                    .zip(text("FECHA_ACTUALIZACION")?)
+                    .zip(text("ESTADO")?.into_iter().zip(text("ACTUALIZADO_EN")?))
+                    .zip(registros.i64()?)
+                    .zip(avance.i64()?)
+            {
+                entries.push(LedgerEntry {
+                    archivo: archivo.unwrap_or_default(),
+                    sha256: sha256.unwrap_or_default(),
+                    fecha_actualizacion: fecha,
+                    registros: registros.unwrap_or_default(),
+                    estado: estado
+                        .as_deref()
+                        .and_then(IngestStatus::from_code)
+                        .unwrap_or(IngestStatus::Failed),
+                    avance: avance.unwrap_or_default(),
+                    actualizado_en: actualizado_en.unwrap_or_default(),
+                });
+            }
+        }
+        Ok(Self { writer, entries })
+    }
+    fn schema() -> SqliteSchema {
+        let text = || SqliteColOption::default().with_type_sql(SqliteDataType::TEXT);
+        SqliteSchema::new("ARCHIVO", text())
+            .add_schema(&SqliteSchema::new("SHA256", text().with_primary_key(true)))
+            .add_schema(&SqliteSchema::new("FECHA_ACTUALIZACION", text()))
+            .add_schema(&SqliteSchema::new(
+                "REGISTROS",
+                SqliteColOption::default().with_type_sql(SqliteDataType::INTEGER),
+            ))
+            .add_schema(&SqliteSchema::new("ESTADO", text()))
+            .add_schema(&SqliteSchema::new(
+                "AVANCE",
+                SqliteColOption::default().with_type_sql(SqliteDataType::INTEGER),
+            ))
+            .add_schema(&SqliteSchema::new("ACTUALIZADO_EN", text()))
+    }
+    pub fn entries(&self) -> &[LedgerEntry] {
+        &self.entries
+    }
+    // El mismo contenido ya se cargó completo
+    pub fn is_loaded(&self, sha256: &str) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.sha256 == sha256 && e.estado == IngestStatus::Loaded)
+    }
+    pub fn entry(&self, sha256: &str) -> Option<&LedgerEntry> {
+        self.entries.iter().find(|e| e.sha256 == sha256)
+    }
+    // Entrada de un archivo que no terminó de cargarse, se reanuda desde su `avance`
+    pub fn checkpoint(&self, sha256: &str) -> Option<&LedgerEntry> {
+        self.entry(sha256)
+            .filter(|e| e.estado != IngestStatus::Loaded && e.avance > 0)
+    }
+    // Publicación más reciente de `archivo` que se cargó completa
+    pub fn last_release(&self, archivo: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .filter(|e| e.archivo == archivo && e.estado == IngestStatus::Loaded)
+            .filter_map(|e| e.fecha_actualizacion.as_deref())
+            .max()
+    }
+    // Guarda la entrada, si el hash ya estaba se actualiza su estado
+    pub fn record(&mut self, entry: LedgerEntry) -> Result<(), color_eyre::eyre::Error> {
+        let mut df = df!(
+            "ARCHIVO" => [entry.archivo.as_str()],
+            "SHA256" => [entry.sha256.as_str()],
+            "FECHA_ACTUALIZACION" => [entry.fecha_actualizacion.as_deref()],
+            "REGISTROS" => [entry.registros],
+            "ESTADO" => [entry.estado.code()],
+            "AVANCE" => [entry.avance],
+            "ACTUALIZADO_EN" => [entry.actualizado_en.as_str()],
+        )?;
+        self.writer.clone().finish(&mut df)?;
+        self.entries.retain(|e| e.sha256 != entry.sha256);
+        self.entries.push(entry);
+        Ok(())
+    }
+}
+// Fecha de publicación más reciente de los registros de `lf`
+pub fn release_date(lf: &LazyFrame) -> Result<Option<String>, color_eyre::eyre::Error> {
+    let df = lf
+        .clone()
+        .select([col("FECHA_ACTUALIZACION").cast(DataType::String).max()])
+        .collect()?;
+    Ok(df
+        .column("FECHA_ACTUALIZACION")?
+        .str()?
+        .get(0)
+        .map(|d| d.to_string()))
+}
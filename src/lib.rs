@@ -1,5 +1,26 @@
+pub mod cancel;
+pub mod cli;
+pub mod config;
+pub mod countries;
 pub mod download;
+pub mod duck_sql;
+pub mod etl;
+#[cfg(feature = "test-support")]
+pub mod harness;
+pub mod ledger;
+pub mod logging;
+pub mod output;
+pub mod pg_sql;
+pub mod pipeline;
+pub mod pl_csv;
+pub mod pl_parquet;
 pub mod pl_sql;
+pub mod progress;
+pub mod quality;
+pub mod quarantine;
+pub mod sentinel;
+pub mod summary;
+pub mod synthetic;
 pub mod unzip;
 pub mod utils;
 pub mod xlxs_to_pl;
@@ -0,0 +1,75 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    // Una línea legible por evento
+    #[default]
+    Text,
+    // Un objeto JSON por línea, para guardar y consultar las bitácoras
+    Json,
+}
+// Nivel, formato y destino de la bitácora; al cerrar cada span se registra su
+// duración junto con sus campos (archivo, tabla, registros)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    // Directivas como en `RUST_LOG`, p. ej. `info` o `info,db_cov19mx::pl_sql=debug`
+    pub level: String,
+    pub format: LogFormat,
+    // Si no se da se escribe en stderr
+    pub file: Option<PathBuf>,
+}
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            // sqlx registra cada consulta en info
+            level: "info,sqlx=warn".into(),
+            format: LogFormat::Text,
+            file: None,
+        }
+    }
+}
+impl LoggingConfig {
+    pub fn filter(&self) -> Result<EnvFilter, color_eyre::eyre::Error> {
+        EnvFilter::try_new(&self.level)
+            .map_err(|e| eyre!("logging.level: `{}` no es válido: {}", self.level, e))
+    }
+}
+// Instala el suscriptor global, solo se puede llamar una vez por proceso
+pub fn init_logging(config: &LoggingConfig) -> Result<(), color_eyre::eyre::Error> {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(config.filter()?)
+        .with_span_events(FmtSpan::CLOSE);
+    let file = match &config.file {
+        Some(path) => {
+            if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)?;
+            }
+            Some(Mutex::new(
+                File::options().create(true).append(true).open(path)?,
+            ))
+        }
+        None => None,
+    };
+    let result = match (config.format, file) {
+        (LogFormat::Text, Some(file)) => builder.with_ansi(false).with_writer(file).try_init(),
+        (LogFormat::Text, None) => builder.with_writer(std::io::stderr).try_init(),
+        (LogFormat::Json, Some(file)) => builder
+            .json()
+            .with_current_span(true)
+            .with_writer(file)
+            .try_init(),
+        (LogFormat::Json, None) => builder
+            .json()
+            .with_current_span(true)
+            .with_writer(std::io::stderr)
+            .try_init(),
+    };
+    result.map_err(|e| eyre!("No se pudo iniciar la bitácora: {}", e))
+}
@@ -0,0 +1,222 @@
+#[cfg(feature = "duckdb")]
+use crate::duck_sql::DuckDbBackend;
+use crate::pg_sql::PgBackend;
+use crate::pl_sql::*;
+use crate::quarantine::{existing_keys, KEYS_PER_QUERY};
+use crate::utils::block_on;
+use futures::future::BoxFuture;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+// Base de datos a la que van los catálogos y COVID19MEXICO. La bitácora, los
+// rechazados, el reporte de calidad y las corridas se quedan en SQLite
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputBackend {
+    #[default]
+    Sqlite,
+    Postgres,
+    DuckDb,
+}
+impl OutputBackend {
+    pub fn name(&self) -> &'static str {
+        match self {
+            OutputBackend::Sqlite => "sqlite",
+            OutputBackend::Postgres => "postgres",
+            OutputBackend::DuckDb => "duckdb",
+        }
+    }
+}
+// `SqlBackend` de la salida elegida en `output.backend`
+#[derive(Clone)]
+pub enum OutputSql {
+    Sqlite(SqliteBackend),
+    Postgres(PgBackend),
+    #[cfg(feature = "duckdb")]
+    DuckDb(DuckDbBackend),
+}
+impl OutputSql {
+    // Con SQLite la salida es la misma base que `control`, así comparten la
+    // conexión de `SqlWriter::new_bulk`
+    pub fn connect(
+        backend: OutputBackend,
+        url: Option<&str>,
+        control: &SqliteBackend,
+    ) -> Result<Self, color_eyre::eyre::Error> {
+        let url = || {
+            url.ok_or_else(|| {
+                color_eyre::eyre::eyre!("La salida {} necesita `output.url`", backend.name())
+            })
+        };
+        Ok(match backend {
+            OutputBackend::Sqlite => OutputSql::Sqlite(control.clone()),
+            OutputBackend::Postgres => {
+                OutputSql::Postgres(SqlWriter::new_postgres(url()?)?.backend().clone())
+            }
+            #[cfg(feature = "duckdb")]
+            OutputBackend::DuckDb => OutputSql::DuckDb(DuckDbBackend::new(url()?)),
+            #[cfg(not(feature = "duckdb"))]
+            OutputBackend::DuckDb => {
+                return Err(color_eyre::eyre::eyre!(
+                    "La salida duckdb necesita compilar con `--features duckdb`"
+                ))
+            }
+        })
+    }
+    pub fn is_sqlite(&self) -> bool {
+        matches!(self, OutputSql::Sqlite(_))
+    }
+    // SQLite siempre declara las llaves foráneas y se revisan al final con
+    // `foreign_key_check`. DuckDB solo las declara si se exigen al insertar, así
+    // COVID19MEXICO queda relacionada con sus catálogos
+    pub fn foreign_keys(&self) -> bool {
+        match self {
+            #[cfg(feature = "duckdb")]
+            OutputSql::DuckDb(_) => true,
+            _ => false,
+        }
+    }
+    // Postgres y DuckDB no migran tablas, se crean con todas las columnas del
+    // diccionario desde la primera carga
+    pub fn migrate(&self) -> MigrateOption {
+        match self {
+            OutputSql::Sqlite(_) => MigrateOption::AddColumns,
+            _ => MigrateOption::Ignore,
+        }
+    }
+    // Claves de `keys` que ya están en `table` con una `FECHA_ACTUALIZACION`
+    // posterior a `release`
+    pub fn newer_keys(
+        &self,
+        table: &str,
+        key_column: &str,
+        keys: &Column,
+        release: &str,
+    ) -> Result<HashSet<String>, color_eyre::eyre::Error> {
+        let keys_str = keys.cast(&DataType::String)?;
+        let keys_str = keys_str
+            .str()?
+            .into_iter()
+            .flatten()
+            .map(|k| k.to_string())
+            .collect::<Vec<_>>();
+        let mut existing = HashSet::new();
+        match self {
+            OutputSql::Sqlite(backend) => {
+                // `date()` lee igual el texto ISO-8601 y el día juliano de
+                // `SqliteDateFormat`, comparar la columna directo falla con REAL
+                let filter =
+                    SqlPredicate::raw("date(FECHA_ACTUALIZACION) > date(?)", vec![release.into()]);
+                return existing_keys(&backend.reader(), table, key_column, keys, Some(filter));
+            }
+            OutputSql::Postgres(backend) => block_on(async {
+                let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+                    .bind(table)
+                    .fetch_one(backend.pool())
+                    .await?;
+                if !exists {
+                    return Ok(());
+                }
+                let qry = format!(
+                    "SELECT {}::text FROM {} WHERE {}::text = ANY($1) AND FECHA_ACTUALIZACION > $2::date",
+                    key_column, table, key_column
+                );
+                for chunk in keys_str.chunks(KEYS_PER_QUERY) {
+                    let found: Vec<String> = sqlx::query_scalar(&qry)
+                        .bind(chunk)
+                        .bind(release)
+                        .fetch_all(backend.pool())
+                        .await?;
+                    existing.extend(found);
+                }
+                Ok::<_, color_eyre::eyre::Error>(())
+            })??,
+            #[cfg(feature = "duckdb")]
+            OutputSql::DuckDb(backend) => {
+                if !duckdb_table_exists(backend, table)? {
+                    return Ok(existing);
+                }
+                for chunk in keys_str.chunks(KEYS_PER_QUERY) {
+                    let found = backend.query_column(&format!(
+                        "SELECT CAST({} AS VARCHAR) FROM {} WHERE CAST({} AS VARCHAR) IN ({}) \
+                         AND FECHA_ACTUALIZACION > DATE {}",
+                        key_column,
+                        table,
+                        key_column,
+                        chunk.iter().map(|k| quote(k)).collect::<Vec<_>>().join(","),
+                        quote(release)
+                    ))?;
+                    existing.extend(found.into_iter().flatten());
+                }
+            }
+        }
+        Ok(existing)
+    }
+    // Registros de `table`, `None` si no existe
+    pub fn count(&self, table: &str) -> Result<Option<i64>, color_eyre::eyre::Error> {
+        let qry = format!("SELECT COUNT(*) AS N FROM {}", table);
+        match self {
+            OutputSql::Sqlite(backend) => {
+                let reader = backend.reader();
+                if !reader.table_exists(table)? {
+                    return Ok(None);
+                }
+                let df = reader.with_query(Some(qry)).finish()?;
+                Ok(df.column("N")?.cast(&DataType::Int64)?.i64()?.get(0))
+            }
+            OutputSql::Postgres(backend) => block_on(async {
+                let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+                    .bind(table)
+                    .fetch_one(backend.pool())
+                    .await?;
+                if !exists {
+                    return Ok(None);
+                }
+                let n: i64 = sqlx::query_scalar(&qry).fetch_one(backend.pool()).await?;
+                Ok::<_, color_eyre::eyre::Error>(Some(n))
+            })?,
+            #[cfg(feature = "duckdb")]
+            OutputSql::DuckDb(backend) => {
+                if !duckdb_table_exists(backend, table)? {
+                    return Ok(None);
+                }
+                Ok(backend
+                    .query_column(&qry)?
+                    .into_iter()
+                    .flatten()
+                    .next()
+                    .and_then(|n| n.parse().ok()))
+            }
+        }
+    }
+}
+impl SqlBackend for OutputSql {
+    fn write<'a>(
+        &'a self,
+        df: &'a mut DataFrame,
+        options: &'a WriteOptions,
+    ) -> BoxFuture<'a, Result<u64, color_eyre::eyre::Error>> {
+        match self {
+            OutputSql::Sqlite(backend) => backend.write(df, options),
+            OutputSql::Postgres(backend) => backend.write(df, options),
+            #[cfg(feature = "duckdb")]
+            OutputSql::DuckDb(backend) => backend.write(df, options),
+        }
+    }
+}
+#[cfg(feature = "duckdb")]
+fn duckdb_table_exists(
+    backend: &DuckDbBackend,
+    table: &str,
+) -> Result<bool, color_eyre::eyre::Error> {
+    Ok(!backend
+        .query_column(&format!(
+            "SELECT table_name FROM information_schema.tables WHERE lower(table_name) = lower({})",
+            quote(table)
+        ))?
+        .is_empty())
+}
+#[cfg(feature = "duckdb")]
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
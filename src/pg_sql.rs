@@ -0,0 +1,298 @@
+use crate::pl_sql::{
+    IfExistsOption, MigrateOption, SqlBackend, SqlWriter, SqliteDataType, SqliteSchema,
+    WriteOptions,
+};
+use crate::progress::ProgressStage;
+use crate::utils::block_on;
+use futures::future::BoxFuture;
+use polars::prelude::*;
+use sqlx::postgres::{PgConnection, PgPool, PgPoolOptions};
+use sqlx::Postgres;
+use sqlx::{Connection, Pool};
+use std::sync::Arc;
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgDataType {
+    BIGINT,
+    DOUBLE,
+    TEXT,
+    BYTEA,
+    BOOLEAN,
+    DATE,
+    TIMESTAMP,
+    NUMERIC(Option<usize>, Option<usize>),
+}
+impl std::fmt::Display for PgDataType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PgDataType::BIGINT => write!(f, "BIGINT"),
+            PgDataType::DOUBLE => write!(f, "DOUBLE PRECISION"),
+            PgDataType::TEXT => write!(f, "TEXT"),
+            PgDataType::BYTEA => write!(f, "BYTEA"),
+            PgDataType::BOOLEAN => write!(f, "BOOLEAN"),
+            PgDataType::DATE => write!(f, "DATE"),
+            PgDataType::TIMESTAMP => write!(f, "TIMESTAMP"),
+            PgDataType::NUMERIC(p, s) => match (p, s) {
+                (Some(p), Some(s)) => write!(f, "NUMERIC({}, {})", p, s),
+                (Some(p), None) => write!(f, "NUMERIC({})", p),
+                _ => write!(f, "NUMERIC"),
+            },
+        }
+    }
+}
+impl PgDataType {
+    pub fn from_sqlite_type(type_sql: &SqliteDataType) -> Self {
+        match type_sql {
+            SqliteDataType::INTEGER => PgDataType::BIGINT,
+            SqliteDataType::REAL => PgDataType::DOUBLE,
+            SqliteDataType::TEXT => PgDataType::TEXT,
+            SqliteDataType::BLOB => PgDataType::BYTEA,
+            SqliteDataType::NUMERIC(p, s) => PgDataType::NUMERIC(*p, *s),
+        }
+    }
+    // SQLite stores dates and booleans as TEXT/INTEGER, Postgres has native
+    // types for them so the polars type wins over TEXT/INTEGER/REAL
+    pub fn from_column(data_type: Option<&DataType>, type_sql: &SqliteDataType) -> Self {
+        match (data_type, type_sql) {
+            (Some(DataType::Date), SqliteDataType::TEXT | SqliteDataType::REAL) => PgDataType::DATE,
+            (Some(DataType::Datetime(_, _)), SqliteDataType::TEXT | SqliteDataType::REAL) => {
+                PgDataType::TIMESTAMP
+            }
+            (Some(DataType::Boolean), SqliteDataType::INTEGER) => PgDataType::BOOLEAN,
+            _ => PgDataType::from_sqlite_type(type_sql),
+        }
+    }
+}
+// CREATE TABLE for Postgres from the same schema used with SQLite
+pub fn create_table_pg<T: Into<String>>(
+    schema: &SqliteSchema,
+    df_schema: &Schema,
+    table_name: T,
+    foreign_keys: bool,
+) -> String {
+    let mut col_definitions = Vec::new();
+    let mut constraints = Vec::new();
+    for (column, options) in schema.iter_fields() {
+        let type_pg = PgDataType::from_column(df_schema.get(column), options.type_sql());
+        col_definitions.push(options.build_col_def_as(
+            column,
+            &type_pg.to_string(),
+            "GENERATED BY DEFAULT AS IDENTITY",
+        ));
+        if let Some((table, parent)) = options.foreign_key_ref() {
+            if foreign_keys {
+                constraints.push(format!(
+                    "FOREIGN KEY ({}) REFERENCES {}({})",
+                    column, table, parent
+                ));
+            }
+        }
+    }
+    col_definitions.extend(constraints);
+    format!(
+        "CREATE TABLE IF NOT EXISTS {} (\n    {}\n);",
+        table_name.into(),
+        col_definitions.join(",\n    ")
+    )
+}
+// Rows of `df` as CSV for `COPY ... WITH (FORMAT csv)`, every value is quoted
+// so only the unquoted empty field is read as NULL. Binary columns are
+// written in the `\x` hex format of BYTEA
+pub fn df_to_csv(df: &DataFrame) -> Result<Vec<u8>, color_eyre::eyre::Error> {
+    let columns = df
+        .get_columns()
+        .iter()
+        .map(|c| match c.dtype() {
+            DataType::Binary => Ok(c
+                .as_materialized_series()
+                .binary()?
+                .into_iter()
+                .map(|v| v.map(bytea_hex))
+                .collect::<StringChunked>()
+                .into_column()),
+            _ => c.cast(&DataType::String),
+        })
+        .collect::<PolarsResult<Vec<_>>>()?;
+    let columns = columns
+        .iter()
+        .map(|c| c.str().cloned())
+        .collect::<PolarsResult<Vec<_>>>()?;
+    let mut buf = Vec::new();
+    for i in 0..df.height() {
+        for (j, column) in columns.iter().enumerate() {
+            if j > 0 {
+                buf.push(b',');
+            }
+            if let Some(value) = column.get(i) {
+                buf.push(b'"');
+                buf.extend_from_slice(value.replace('"', "\"\"").as_bytes());
+                buf.push(b'"');
+            }
+        }
+        buf.push(b'\n');
+    }
+    Ok(buf)
+}
+fn bytea_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(2 + bytes.len() * 2);
+    hex.push_str("\\x");
+    for b in bytes {
+        hex.push_str(&format!("{:02x}", b));
+    }
+    hex
+}
+#[derive(Clone)]
+pub struct PgBackend {
+    pool: Arc<PgPool>,
+}
+impl PgBackend {
+    pub fn new(pool: Pool<Postgres>) -> Self {
+        Self {
+            pool: Arc::new(pool),
+        }
+    }
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+    async fn copy_batch(
+        conn: &mut PgConnection,
+        table_name: &str,
+        columns: &str,
+        batch: &DataFrame,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        let mut copy = conn
+            .copy_in_raw(&format!(
+                "COPY {} ({}) FROM STDIN WITH (FORMAT csv)",
+                table_name, columns
+            ))
+            .await?;
+        copy.send(df_to_csv(batch)?).await?;
+        Ok(copy.finish().await?)
+    }
+    async fn write_df(
+        &self,
+        df: &mut DataFrame,
+        options: &WriteOptions,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        let table_name = &options.table_name;
+        let mut conn = self.pool.acquire().await?;
+        // DROP, CREATE and COPY are transactional in Postgres, a failure
+        // leaves the table as it was
+        let mut tx = conn.begin().await?;
+        match options.if_exists {
+            IfExistsOption::Append => {
+                if options.migrate != MigrateOption::Ignore {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Schema migration is only supported with SQLite"
+                    ));
+                }
+            }
+            IfExistsOption::Replace => {
+                sqlx::query(&format!("DROP TABLE IF EXISTS {}", table_name))
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            IfExistsOption::Fail => {
+                let exists: bool = sqlx::query_scalar("SELECT to_regclass($1) IS NOT NULL")
+                    .bind(table_name)
+                    .fetch_one(&mut *tx)
+                    .await?;
+                if exists {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Table {} already exists",
+                        table_name
+                    ));
+                }
+            }
+        }
+        let qry = create_table_pg(
+            &options.schema,
+            df.schema(),
+            table_name,
+            options.foreign_keys,
+        );
+        sqlx::query(&qry).execute(&mut *tx).await?;
+
+        let columns = df.get_column_names_str().join(",");
+        // Without strict insert or with upsert the rows go through a staging
+        // table so the duplicated keys are resolved with ON CONFLICT
+        let staged = !options.strict_insert || options.upsert_key.is_some();
+        let target = if !staged {
+            table_name.clone()
+        } else {
+            let stage = format!("{}__stage", table_name);
+            sqlx::query(&format!("DROP TABLE IF EXISTS {}", stage))
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(&format!(
+                "CREATE TEMP TABLE {} AS SELECT {} FROM {} WITH NO DATA",
+                stage, columns, table_name
+            ))
+            .execute(&mut *tx)
+            .await?;
+            stage
+        };
+        let batch_size: usize = options.batch_size.into();
+        let progress = options.progress();
+        progress.start(ProgressStage::Insert, table_name, Some(df.height() as u64));
+        let mut offset = 0;
+        let mut written = 0;
+        while offset < df.height() {
+            let batch = df.slice(offset as i64, batch_size);
+            written += Self::copy_batch(&mut tx, &target, &columns, &batch).await?;
+            progress.advance(ProgressStage::Insert, table_name, batch.height() as u64);
+            offset += batch_size;
+        }
+        if staged {
+            let on_conflict = options
+                .on_conflict(&df.get_column_names_str())
+                .unwrap_or_else(|| "ON CONFLICT DO NOTHING".to_string());
+            // ON CONFLICT DO UPDATE fails when two rows of the statement have
+            // the same key, only the last one copied is kept
+            let select = match &options.upsert_key {
+                Some(key) => format!(
+                    "SELECT DISTINCT ON ({}) {} FROM {} ORDER BY {}, ctid DESC",
+                    key, columns, target, key
+                ),
+                None => format!("SELECT {} FROM {}", columns, target),
+            };
+            written = sqlx::query(&format!(
+                "INSERT INTO {} ({}) {} {}",
+                table_name, columns, select, on_conflict
+            ))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+            sqlx::query(&format!("DROP TABLE {}", target))
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        progress.finish(ProgressStage::Insert, table_name);
+        Ok(written)
+    }
+}
+impl SqlBackend for PgBackend {
+    fn write<'a>(
+        &'a self,
+        df: &'a mut DataFrame,
+        options: &'a WriteOptions,
+    ) -> BoxFuture<'a, Result<u64, color_eyre::eyre::Error>> {
+        Box::pin(self.write_df(df, options))
+    }
+}
+impl SqlWriter<PgBackend> {
+    pub fn new_postgres(db_url: &str) -> Result<Self, color_eyre::eyre::Error> {
+        block_on(Self::connect_postgres(db_url))?
+    }
+    pub async fn connect_postgres(db_url: &str) -> Result<Self, color_eyre::eyre::Error> {
+        let n_threads = num_cpus::get();
+        let pool = PgPoolOptions::new()
+            .max_connections(n_threads as u32)
+            .connect(db_url)
+            .await?;
+        Ok(SqlWriter::new_from_pg_pool(pool).n_threads(n_threads))
+    }
+    pub fn new_from_pg_pool(pool: Pool<Postgres>) -> Self {
+        SqlWriter::with_backend(PgBackend::new(pool))
+    }
+}
@@ -0,0 +1,230 @@
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use tracing::Span;
+// Hilos y memoria de la carga; los lotes se dimensionan con `memory_budget_mb`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    // Archivos que se leen a la vez
+    pub readers: usize,
+    // Hilos que limpian lotes, por omisión uno por CPU
+    pub workers: Option<usize>,
+    // Memoria máxima de los lotes en proceso, entre lectura y escritura
+    pub memory_budget_mb: u64,
+    // Lotes que pueden esperar entre una etapa y la siguiente
+    pub channel_capacity: usize,
+}
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        Self {
+            readers: 2,
+            workers: None,
+            memory_budget_mb: 2048,
+            channel_capacity: 2,
+        }
+    }
+}
+impl PipelineConfig {
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or_else(|| {
+            thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1)
+        })
+    }
+    pub fn memory_budget(&self) -> u64 {
+        self.memory_budget_mb * 1024 * 1024
+    }
+}
+// Bytes reservados por los lotes que van en el pipeline; si no alcanza se
+// espera a que el escritor libere. Un lote solo siempre entra aunque pase el límite
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    limit: u64,
+    state: Mutex<(u64, bool)>,
+    released: Condvar,
+}
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        Self {
+            limit,
+            ..Default::default()
+        }
+    }
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+    pub fn used(&self) -> u64 {
+        self.state.lock().map(|s| s.0).unwrap_or_default()
+    }
+    // Regresa false si el pipeline se detuvo mientras esperaba
+    pub fn acquire(&self, bytes: u64) -> bool {
+        let Ok(mut state) = self.state.lock() else {
+            return false;
+        };
+        while !state.1 && state.0 > 0 && state.0 + bytes > self.limit {
+            state = match self.released.wait(state) {
+                Ok(state) => state,
+                Err(_) => return false,
+            };
+        }
+        if state.1 {
+            return false;
+        }
+        state.0 += bytes;
+        true
+    }
+    pub fn release(&self, bytes: u64) {
+        if let Ok(mut state) = self.state.lock() {
+            state.0 = state.0.saturating_sub(bytes);
+        }
+        self.released.notify_all();
+    }
+    // Despierta a los que esperan, ya no se reserva nada
+    pub fn close(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.1 = true;
+        }
+        self.released.notify_all();
+    }
+}
+// Etapas lectura -> limpieza -> escritura unidas por canales acotados: varios
+// lectores toman fuentes, varios hilos limpian y un solo escritor en el hilo
+// que llama recibe los lotes en el orden en que terminan
+#[derive(Debug)]
+pub struct Pipeline {
+    readers: usize,
+    workers: usize,
+    capacity: usize,
+    budget: MemoryBudget,
+    stopped: AtomicBool,
+}
+impl Pipeline {
+    pub fn new(config: &PipelineConfig) -> Self {
+        Self {
+            readers: config.readers.max(1),
+            workers: config.workers().max(1),
+            capacity: config.channel_capacity.max(1),
+            budget: MemoryBudget::new(config.memory_budget()),
+            stopped: AtomicBool::new(false),
+        }
+    }
+    pub fn budget(&self) -> &MemoryBudget {
+        &self.budget
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        self.budget.close();
+    }
+    // Filas por lote para que cada lector y cada hilo de limpieza tengan uno
+    // en proceso sin pasar el presupuesto
+    pub fn batch_rows(&self, bytes_per_row: u64) -> usize {
+        let slots = (self.readers + self.workers + 1) as u64;
+        (self.budget.limit() / slots / bytes_per_row.max(1)).max(1) as usize
+    }
+    // `read` manda los elementos de la fuente `i` por el canal, si `send` falla el
+    // pipeline ya se detuvo. `write` regresa false para detenerlo después del elemento
+    pub fn run<A, B, R, C, W>(
+        &self,
+        sources: usize,
+        read: R,
+        clean: C,
+        mut write: W,
+    ) -> Result<(), color_eyre::eyre::Error>
+    where
+        A: Send,
+        B: Send,
+        R: Fn(usize, &SyncSender<A>) -> Result<(), color_eyre::eyre::Error> + Sync,
+        C: Fn(A) -> Result<B, color_eyre::eyre::Error> + Sync,
+        W: FnMut(B) -> Result<bool, color_eyre::eyre::Error>,
+    {
+        let next = AtomicUsize::new(0);
+        let (read_tx, read_rx) = sync_channel::<A>(self.capacity);
+        // Se suelta el receptor al detenerse para que los lectores no se queden
+        // esperando con el canal lleno
+        let read_rx: Mutex<Option<Receiver<A>>> = Mutex::new(Some(read_rx));
+        let (clean_tx, clean_rx) =
+            sync_channel::<Result<B, color_eyre::eyre::Error>>(self.capacity);
+        let span = Span::current();
+        thread::scope(|s| {
+            let readers = (0..self.readers)
+                .map(|_| {
+                    let tx = read_tx.clone();
+                    let (span, next, read) = (span.clone(), &next, &read);
+                    s.spawn(move || -> Result<(), color_eyre::eyre::Error> {
+                        let _span = span.enter();
+                        while !self.is_stopped() {
+                            let i = next.fetch_add(1, Ordering::SeqCst);
+                            if i >= sources {
+                                break;
+                            }
+                            if let Err(e) = read(i, &tx) {
+                                self.stop();
+                                return Err(e);
+                            }
+                        }
+                        Ok(())
+                    })
+                })
+                .collect::<Vec<_>>();
+            drop(read_tx);
+            for _ in 0..self.workers {
+                let tx = clean_tx.clone();
+                let (span, read_rx, clean) = (span.clone(), &read_rx, &clean);
+                s.spawn(move || {
+                    let _span = span.enter();
+                    loop {
+                        let item = match read_rx.lock() {
+                            Ok(rx) => rx.as_ref().and_then(|rx| rx.recv().ok()),
+                            Err(_) => None,
+                        };
+                        let Some(item) = item else {
+                            break;
+                        };
+                        if tx.send(clean(item)).is_err() {
+                            if let Ok(mut rx) = read_rx.lock() {
+                                rx.take();
+                            }
+                            break;
+                        }
+                    }
+                });
+            }
+            drop(clean_tx);
+            let mut result = Ok(());
+            for item in clean_rx {
+                match item.and_then(&mut write) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        self.stop();
+                        break;
+                    }
+                    Err(e) => {
+                        self.stop();
+                        result = Err(e);
+                        break;
+                    }
+                }
+            }
+            if let Ok(mut rx) = read_rx.lock() {
+                rx.take();
+            }
+            for reader in readers {
+                let res = reader
+                    .join()
+                    .unwrap_or_else(|_| Err(eyre!("Un lector del pipeline terminó con pánico")));
+                if result.is_ok() {
+                    result = res;
+                }
+            }
+            result
+        })
+    }
+}
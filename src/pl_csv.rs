@@ -0,0 +1,123 @@
+use polars::io::mmap::MmapBytesReader;
+use polars::prelude::*;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+// Rows per chunk that polars parses at a time, the same as its default
+const CHUNK_ROWS: usize = 1 << 18;
+// Reads a CSV once from start to end in batches of `batch_size` rows, unlike
+// `LazyFrame::slice` that parses the file again for every batch
+#[derive(Debug, Clone)]
+pub struct CsvBatchReader {
+    path: PathBuf,
+    schema_overwrite: Option<SchemaRef>,
+    skip_rows: usize,
+    batch_size: usize,
+}
+impl CsvBatchReader {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            schema_overwrite: None,
+            skip_rows: 0,
+            batch_size: CHUNK_ROWS,
+        }
+    }
+    pub fn with_dtype_overwrite(mut self, schema: Option<SchemaRef>) -> Self {
+        self.schema_overwrite = schema;
+        self
+    }
+    // Data rows to skip after the header, used to resume a load
+    pub fn with_skip_rows(mut self, skip_rows: usize) -> Self {
+        self.skip_rows = skip_rows;
+        self
+    }
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+    pub fn finish(self) -> Result<CsvBatches, color_eyre::eyre::Error> {
+        let file = Box::new(File::open(&self.path)?) as Box<dyn MmapBytesReader>;
+        let reader = CsvReadOptions::default()
+            .with_has_header(true)
+            .with_schema_overwrite(self.schema_overwrite)
+            .with_skip_rows_after_header(self.skip_rows)
+            .with_chunk_size(self.batch_size.min(CHUNK_ROWS))
+            .into_reader_with_file_handle(file)
+            .batched(None)?;
+        Ok(CsvBatches {
+            reader,
+            pending: VecDeque::new(),
+            buffered: 0,
+            batch_size: self.batch_size,
+            offset: self.skip_rows as i64,
+            done: false,
+        })
+    }
+}
+// Iterator over the batches; every batch has `batch_size` rows except the last one
+pub struct CsvBatches {
+    reader: OwnedBatchedCsvReader,
+    pending: VecDeque<DataFrame>,
+    buffered: usize,
+    batch_size: usize,
+    offset: i64,
+    done: bool,
+}
+impl CsvBatches {
+    // Data row of the file where the next batch starts
+    pub fn offset(&self) -> i64 {
+        self.offset
+    }
+    fn fill(&mut self) -> PolarsResult<()> {
+        while !self.done && self.buffered < self.batch_size {
+            match self.reader.next_batches(1)? {
+                Some(chunks) => {
+                    for df in chunks.into_iter().filter(|df| df.height() > 0) {
+                        self.buffered += df.height();
+                        self.pending.push_back(df);
+                    }
+                }
+                None => self.done = true,
+            }
+        }
+        Ok(())
+    }
+    fn take(&mut self) -> PolarsResult<Option<DataFrame>> {
+        self.fill()?;
+        let mut out: Option<DataFrame> = None;
+        let mut missing = self.batch_size;
+        while missing > 0 {
+            let Some(df) = self.pending.pop_front() else {
+                break;
+            };
+            let df = if df.height() > missing {
+                self.pending
+                    .push_front(df.slice(missing as i64, usize::MAX));
+                df.slice(0, missing)
+            } else {
+                df
+            };
+            missing -= df.height();
+            self.buffered -= df.height();
+            out = match out {
+                Some(mut acc) => {
+                    acc.vstack_mut(&df)?;
+                    Some(acc)
+                }
+                None => Some(df),
+            };
+        }
+        Ok(out.map(|mut df| {
+            df.as_single_chunk_par();
+            self.offset += df.height() as i64;
+            df
+        }))
+    }
+}
+impl Iterator for CsvBatches {
+    type Item = Result<DataFrame, color_eyre::eyre::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.take().map_err(Into::into).transpose()
+    }
+}
@@ -0,0 +1,247 @@
+use crate::utils::file_sha256;
+use chrono::Utc;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+static PART_ID: AtomicUsize = AtomicUsize::new(0);
+// Name used for the partitions where the key is null, same as Hive
+const NULL_PARTITION: &str = "__HIVE_DEFAULT_PARTITION__";
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SourceArchive {
+    pub file: String,
+    pub sha256: String,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParquetFile {
+    pub path: String,
+    pub n_rows: usize,
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ParquetManifest {
+    pub created_at: String,
+    pub dictionary_version: Option<String>,
+    pub compression: String,
+    pub row_group_size: Option<usize>,
+    pub partition_by: Vec<String>,
+    pub sources: Vec<SourceArchive>,
+    pub files: Vec<ParquetFile>,
+}
+impl ParquetManifest {
+    pub fn read<P: AsRef<Path>>(path: P) -> Result<Self, color_eyre::eyre::Error> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+}
+// Parquet output of the ETL: the COVID19MEXICO rows partitioned Hive style
+// (`ANIO=2021/MES=3/ENTIDAD_RES=9`) and one file per catalog
+#[derive(Debug, Clone)]
+pub struct ParquetExport {
+    dir: PathBuf,
+    table_name: String,
+    date_column: String,
+    partition_by: Vec<String>,
+    compression: ParquetCompression,
+    row_group_size: Option<usize>,
+    dictionary_version: Option<String>,
+    sources: Vec<PathBuf>,
+}
+impl ParquetExport {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            table_name: "COVID19MEXICO".into(),
+            date_column: "FECHA_INGRESO".into(),
+            partition_by: vec!["ENTIDAD_RES".into()],
+            compression: ParquetCompression::Zstd(None),
+            row_group_size: None,
+            dictionary_version: None,
+            sources: Vec::new(),
+        }
+    }
+    pub fn with_table<T: Into<String>>(mut self, table_name: T) -> Self {
+        self.table_name = table_name.into();
+        self
+    }
+    // Column whose year and month are the first levels of the partition
+    pub fn with_date_column<T: Into<String>>(mut self, date_column: T) -> Self {
+        self.date_column = date_column.into();
+        self
+    }
+    pub fn with_partition_by<T: Into<String>>(mut self, columns: Vec<T>) -> Self {
+        self.partition_by = columns.into_iter().map(|c| c.into()).collect();
+        self
+    }
+    pub fn with_compression(mut self, compression: ParquetCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+    pub fn with_row_group_size(mut self, row_group_size: Option<usize>) -> Self {
+        self.row_group_size = row_group_size;
+        self
+    }
+    pub fn with_dictionary_version<T: Into<String>>(mut self, version: Option<T>) -> Self {
+        self.dictionary_version = version.map(|v| v.into());
+        self
+    }
+    // Archives the data comes from, their hashes are written in the manifest
+    pub fn with_sources<P: AsRef<Path>>(mut self, sources: Vec<P>) -> Self {
+        self.sources = sources.iter().map(|s| s.as_ref().to_path_buf()).collect();
+        self
+    }
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+    fn partition_columns(&self) -> Vec<String> {
+        let mut columns = vec!["ANIO".to_string(), "MES".to_string()];
+        columns.extend(self.partition_by.iter().cloned());
+        columns
+    }
+    fn write_file(&self, path: &Path, df: &mut DataFrame) -> Result<(), color_eyre::eyre::Error> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        ParquetWriter::new(File::create(path)?)
+            .with_compression(self.compression)
+            .with_row_group_size(self.row_group_size)
+            .finish(df)?;
+        Ok(())
+    }
+    fn part_prefix(source: &str) -> String {
+        format!("part-{}-", source)
+    }
+    // Every call adds new part files, so the frame can be written in slices.
+    // The file names carry `source` so a reloaded source can replace its parts
+    pub fn write_partitions(
+        &self,
+        df: &DataFrame,
+        source: &str,
+    ) -> Result<usize, color_eyre::eyre::Error> {
+        let date = col(self.date_column.as_str()).cast(DataType::String);
+        let df = df
+            .clone()
+            .lazy()
+            .with_columns([
+                date.clone()
+                    .str()
+                    .slice(lit(0), lit(4))
+                    .cast(DataType::Int32)
+                    .alias("ANIO"),
+                date.str()
+                    .slice(lit(5), lit(2))
+                    .cast(DataType::Int32)
+                    .alias("MES"),
+            ])
+            .collect()?;
+        let keys = self.partition_columns();
+        let partitions = df.partition_by_stable(&keys, true)?;
+        let n_files = partitions.len();
+        for partition in partitions {
+            let mut path = self.dir.join(&self.table_name);
+            for key in &keys {
+                let value = partition.column(key)?.get(0)?;
+                let value = if value.is_null() {
+                    NULL_PARTITION.to_string()
+                } else {
+                    value.str_value().to_string()
+                };
+                path.push(format!("{}={}", key, value));
+            }
+            path.push(format!(
+                "{}{}-{}.parquet",
+                Self::part_prefix(source),
+                std::process::id(),
+                PART_ID.fetch_add(1, Ordering::Relaxed)
+            ));
+            self.write_file(&path, &mut partition.drop_many(&keys))?;
+        }
+        Ok(n_files)
+    }
+    // Deletes the part files written for `source`, returns how many
+    pub fn remove_source(&self, source: &str) -> Result<usize, color_eyre::eyre::Error> {
+        let prefix = Self::part_prefix(source);
+        let mut removed = 0;
+        let mut pending = vec![self.dir.join(&self.table_name)];
+        while let Some(dir) = pending.pop() {
+            if !dir.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if path
+                    .file_name()
+                    .and_then(|f| f.to_str())
+                    .is_some_and(|f| f.starts_with(&prefix) && f.ends_with(".parquet"))
+                {
+                    fs::remove_file(path)?;
+                    removed += 1;
+                }
+            }
+        }
+        Ok(removed)
+    }
+    pub fn write_catalogs(
+        &self,
+        tables: &HashMap<String, DataFrame>,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        for (table_name, df) in tables {
+            let path = self
+                .dir
+                .join("catalogos")
+                .join(format!("{}.parquet", table_name));
+            self.write_file(&path, &mut df.clone())?;
+        }
+        Ok(())
+    }
+    // `_manifest.json` listing every Parquet file of the directory
+    pub fn write_manifest(&self) -> Result<ParquetManifest, color_eyre::eyre::Error> {
+        let mut files = Vec::new();
+        let mut pending = vec![self.dir.clone()];
+        while let Some(dir) = pending.pop() {
+            for entry in fs::read_dir(dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    pending.push(path);
+                } else if path.extension().is_some_and(|e| e == "parquet") {
+                    let n_rows = ParquetReader::new(File::open(&path)?).num_rows()?;
+                    files.push(ParquetFile {
+                        path: path
+                            .strip_prefix(&self.dir)?
+                            .to_string_lossy()
+                            .replace('\\', "/"),
+                        n_rows,
+                    });
+                }
+            }
+        }
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        let sources = self
+            .sources
+            .iter()
+            .map(|s| {
+                Ok(SourceArchive {
+                    file: s
+                        .file_name()
+                        .map(|f| f.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                    sha256: file_sha256(s)?,
+                })
+            })
+            .collect::<Result<Vec<_>, color_eyre::eyre::Error>>()?;
+        let manifest = ParquetManifest {
+            created_at: Utc::now().to_rfc3339(),
+            dictionary_version: self.dictionary_version.clone(),
+            compression: format!("{:?}", self.compression),
+            row_group_size: self.row_group_size,
+            partition_by: self.partition_columns(),
+            sources,
+            files,
+        };
+        fs::create_dir_all(&self.dir)?;
+        serde_json::to_writer_pretty(File::create(self.dir.join("_manifest.json"))?, &manifest)?;
+        Ok(manifest)
+    }
+}
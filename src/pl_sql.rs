@@ -1,14 +1,31 @@
+use crate::progress::{NoProgress, ProgressObserver, ProgressStage, SharedProgress};
+use crate::utils::{block_on, runtime};
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
 use indexmap::IndexMap;
 use num_cpus;
 use polars::prelude::*;
+use sqlx::sqlite::SqliteArguments;
 use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::sqlite::SqliteConnection;
+use sqlx::sqlite::SqliteJournalMode;
 use sqlx::sqlite::SqlitePool;
 use sqlx::sqlite::SqlitePoolOptions;
+use sqlx::sqlite::SqliteRow;
+use sqlx::sqlite::SqliteSynchronous;
+use sqlx::Column as SqlColumnInfo;
+use sqlx::Connection;
+use sqlx::Executor;
 use sqlx::Pool;
+use sqlx::Row;
 use sqlx::Sqlite;
+use sqlx::TypeInfo;
+use sqlx::ValueRef;
 use std::num::NonZeroUsize;
 use std::path::Path;
-use tokio::runtime::Runtime;
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread::JoinHandle;
+use tracing::{info_span, Instrument};
 #[derive(Debug, Clone, PartialEq, Default)]
 pub enum SqliteDataType {
     INTEGER,
@@ -51,9 +68,61 @@ impl SqliteDataType {
             DataType::Decimal(p, s) => SqliteDataType::NUMERIC(*p, *s),
             DataType::Binary => SqliteDataType::BLOB,
             DataType::BinaryOffset => SqliteDataType::BLOB,
+            DataType::Date | DataType::Datetime(_, _) => SqliteDateFormat::default().type_sql(),
             _ => SqliteDataType::TEXT,
         }
     }
+    pub fn to_polars_type(&self) -> DataType {
+        match self {
+            SqliteDataType::INTEGER => DataType::Int64,
+            SqliteDataType::REAL => DataType::Float64,
+            SqliteDataType::TEXT => DataType::String,
+            SqliteDataType::BLOB => DataType::Binary,
+            SqliteDataType::NUMERIC(None, None) => DataType::Float64,
+            SqliteDataType::NUMERIC(p, s) => DataType::Decimal(*p, Some(s.unwrap_or(0))),
+        }
+    }
+    // Parse the type declared in `PRAGMA table_info`, e.g. `NUMERIC(10, 2)`
+    pub fn from_declared_type(declared: &str) -> Self {
+        let declared = declared.trim().to_uppercase();
+        let (name, args) = match declared.split_once('(') {
+            Some((name, args)) => (name.trim(), Some(args.trim_end_matches(')'))),
+            None => (declared.as_str(), None),
+        };
+        match name {
+            "INTEGER" | "INT" | "BIGINT" => SqliteDataType::INTEGER,
+            "REAL" | "DOUBLE" | "FLOAT" => SqliteDataType::REAL,
+            "BLOB" => SqliteDataType::BLOB,
+            "NUMERIC" | "DECIMAL" => {
+                let mut args = args
+                    .unwrap_or_default()
+                    .split(',')
+                    .map(|a| a.trim().parse::<usize>().ok());
+                SqliteDataType::NUMERIC(args.next().flatten(), args.next().flatten())
+            }
+            _ => SqliteDataType::TEXT,
+        }
+    }
+}
+// How `Date`/`Datetime` columns are stored, SQLite has no date type but its
+// date functions accept both ISO-8601 text and julian day numbers
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SqliteDateFormat {
+    #[default]
+    Iso8601,
+    JulianDay,
+}
+impl SqliteDateFormat {
+    pub fn type_sql(&self) -> SqliteDataType {
+        match self {
+            SqliteDateFormat::Iso8601 => SqliteDataType::TEXT,
+            SqliteDateFormat::JulianDay => SqliteDataType::REAL,
+        }
+    }
+    // Days since the unix epoch to julian day
+    fn julian_day(days: f64) -> f64 {
+        days + 2_440_587.5
+    }
 }
 #[derive(Debug, Default, Clone, PartialEq)]
 pub struct ForeinKey {
@@ -68,7 +137,7 @@ impl ForeinKey {
         }
     }
 }
-#[derive(Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct SqliteColOption {
     type_sql: SqliteDataType,
     nullable: bool,
@@ -126,7 +195,16 @@ impl SqliteColOption {
         self
     }
     pub fn build_col_def<T: Into<String>>(&self, column_name: T) -> String {
-        let mut col_def = format!("{} {}", column_name.into(), self.type_sql);
+        self.build_col_def_as(column_name, &self.type_sql.to_string(), "AUTOINCREMENT")
+    }
+    // Column definition with the type and auto increment keyword of another backend
+    pub(crate) fn build_col_def_as<T: Into<String>>(
+        &self,
+        column_name: T,
+        type_sql: &str,
+        auto_increment: &str,
+    ) -> String {
+        let mut col_def = format!("{} {}", column_name.into(), type_sql);
         if self.primary_key {
             col_def.push_str(" PRIMARY KEY");
         }
@@ -134,7 +212,7 @@ impl SqliteColOption {
             col_def.push_str(" UNIQUE");
         }
         if self.auto_increment {
-            col_def.push_str(" AUTOINCREMENT");
+            col_def.push_str(&format!(" {}", auto_increment));
         }
         if let Some(ref default) = self.default {
             col_def.push_str(&format!(" DEFAULT {}", default));
@@ -144,8 +222,24 @@ impl SqliteColOption {
         }
         col_def
     }
+    pub fn type_sql(&self) -> &SqliteDataType {
+        &self.type_sql
+    }
+    pub(crate) fn auto_increment(&self) -> bool {
+        self.auto_increment
+    }
+    pub fn foreign_key_ref(&self) -> Option<(&str, &str)> {
+        self.foreing_key
+            .as_ref()
+            .map(|fk| (fk.table.as_str(), fk.column.as_str()))
+    }
+    // SQLite only accepts ADD COLUMN for columns without PRIMARY KEY/UNIQUE
+    // and, if they are NOT NULL, with a default value
+    fn can_add_column(&self) -> bool {
+        !self.primary_key && !self.unique && (self.nullable || self.default.is_some())
+    }
 }
-#[derive(Clone, PartialEq, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
 pub struct SqliteSchema {
     columns: IndexMap<String, SqliteColOption>,
 }
@@ -185,6 +279,79 @@ impl SqliteSchema {
         self.columns.insert(column.into(), type_of);
         self
     }
+    pub fn get(&self, column: &str) -> Option<&SqliteColOption> {
+        self.columns.get(column)
+    }
+    // Compare this schema (incoming) against the one stored in the database
+    pub fn diff(&self, stored: &SqliteSchema) -> SchemaDiff {
+        let mut diff = SchemaDiff::default();
+        for (column, options) in self.iter_fields() {
+            match stored.get(column) {
+                None => diff.added.push(column.clone()),
+                Some(old) if old.type_sql != options.type_sql => diff.changed.push((
+                    column.clone(),
+                    old.type_sql.clone(),
+                    options.type_sql.clone(),
+                )),
+                _ => {}
+            }
+        }
+        diff.removed = stored
+            .iter_columns()
+            .filter(|c| self.get(c).is_none())
+            .cloned()
+            .collect();
+        diff
+    }
+    pub fn migration_plan<T: Into<String>>(
+        &self,
+        stored: &SqliteSchema,
+        table_name: T,
+    ) -> MigrationPlan {
+        let table_name = table_name.into();
+        let diff = self.diff(stored);
+        let requires_rebuild = !diff.changed.is_empty()
+            || diff
+                .added
+                .iter()
+                .any(|c| !self.columns[c.as_str()].can_add_column());
+        let mut statements = Vec::new();
+        if requires_rebuild {
+            // Columns only present in the stored table are kept so no data is lost
+            let mut merged = self.clone();
+            for column in &diff.removed {
+                merged.with_column(column, stored.columns[column.as_str()].clone());
+            }
+            let tmp_table = format!("{}__migracion", table_name);
+            let copied = stored.iter_columns().cloned().collect::<Vec<_>>().join(",");
+            statements.push(format!("DROP TABLE IF EXISTS {}", tmp_table));
+            statements.push(merged.finish(&tmp_table));
+            statements.push(format!(
+                "INSERT INTO {} ({}) SELECT {} FROM {}",
+                tmp_table, copied, copied, table_name
+            ));
+            statements.push(format!("DROP TABLE {}", table_name));
+            statements.push(format!(
+                "ALTER TABLE {} RENAME TO {}",
+                tmp_table, table_name
+            ));
+        } else {
+            for column in &diff.added {
+                let options = &self.columns[column.as_str()];
+                let mut col_def = options.build_col_def(column);
+                if let Some(fk) = &options.foreing_key {
+                    col_def.push_str(&format!(" REFERENCES {}({})", fk.table, fk.column));
+                }
+                statements.push(format!("ALTER TABLE {} ADD COLUMN {}", table_name, col_def));
+            }
+        }
+        MigrationPlan {
+            table_name,
+            diff,
+            requires_rebuild,
+            statements,
+        }
+    }
     pub fn finish<T: Into<String>>(&mut self, table_name: T) -> String {
         let mut col_definitions = Vec::new();
         let mut foreign_keys = Vec::new();
@@ -210,68 +377,602 @@ impl SqliteSchema {
         )
     }
 }
-#[derive(Clone, Default)]
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SchemaDiff {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<(String, SqliteDataType, SqliteDataType)>,
+}
+impl SchemaDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+impl std::fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for column in &self.added {
+            writeln!(f, "+ {}", column)?;
+        }
+        for column in &self.removed {
+            writeln!(f, "- {}", column)?;
+        }
+        for (column, old, new) in &self.changed {
+            writeln!(f, "~ {}: {} -> {}", column, old, new)?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MigrationPlan {
+    pub table_name: String,
+    pub diff: SchemaDiff,
+    pub requires_rebuild: bool,
+    pub statements: Vec<String>,
+}
+impl MigrationPlan {
+    pub fn is_empty(&self) -> bool {
+        self.statements.is_empty()
+    }
+}
+impl std::fmt::Display for MigrationPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for statement in &self.statements {
+            writeln!(f, "{};", statement)?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+// Read the columns of an existing table through `PRAGMA table_info`,
+// returns None when the table does not exist
+pub async fn read_table_schema(
+    conn: &mut SqliteConnection,
+    table_name: &str,
+) -> Result<Option<SqliteSchema>, color_eyre::eyre::Error> {
+    let rows = sqlx::query(&format!("PRAGMA table_info({})", table_name))
+        .fetch_all(&mut *conn)
+        .await?;
+    if rows.is_empty() {
+        return Ok(None);
+    }
+    let mut fks: IndexMap<String, ForeinKey> = IndexMap::new();
+    for row in sqlx::query(&format!("PRAGMA foreign_key_list({})", table_name))
+        .fetch_all(&mut *conn)
+        .await?
+    {
+        fks.insert(
+            row.try_get("from")?,
+            ForeinKey::new(row.try_get::<String, _>("table")?, row.try_get("to")?),
+        );
+    }
+    let mut schema = SqliteSchema::default();
+    for row in rows {
+        let name: String = row.try_get("name")?;
+        let declared: String = row.try_get("type")?;
+        let notnull: i64 = row.try_get("notnull")?;
+        let default: Option<String> = row.try_get("dflt_value")?;
+        let pk: i64 = row.try_get("pk")?;
+        let mut options = SqliteColOption::default()
+            .with_type_sql(SqliteDataType::from_declared_type(&declared))
+            .with_default(default)
+            .with_nullable(notnull == 0)
+            .with_primary_key(pk > 0);
+        options.foreing_key = fks.shift_remove(&name);
+        schema.with_column(name, options);
+    }
+    Ok(Some(schema))
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForeignKeyViolation {
+    pub table_name: String,
+    pub column: String,
+    pub parent_table: String,
+    pub parent_column: String,
+    pub n_rows: i64,
+    // Orphan value and number of rows that use it, most frequent first
+    pub orphan_values: Vec<(String, i64)>,
+}
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ForeignKeyReport {
+    pub violations: Vec<ForeignKeyViolation>,
+}
+impl ForeignKeyReport {
+    pub fn is_empty(&self) -> bool {
+        self.violations.is_empty()
+    }
+    pub fn n_rows(&self) -> i64 {
+        self.violations.iter().map(|v| v.n_rows).sum()
+    }
+}
+impl std::fmt::Display for ForeignKeyReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for v in &self.violations {
+            let values = v
+                .orphan_values
+                .iter()
+                .take(10)
+                .map(|(value, n)| format!("{} ({})", value, n))
+                .collect::<Vec<_>>();
+            writeln!(
+                f,
+                "{}.{} -> {}({}): {} rows, {} orphan values: {}{}",
+                v.table_name,
+                v.column,
+                v.parent_table,
+                v.parent_column,
+                v.n_rows,
+                v.orphan_values.len(),
+                values.join(", "),
+                if v.orphan_values.len() > 10 {
+                    ", ..."
+                } else {
+                    ""
+                }
+            )?;
+        }
+        std::fmt::Result::Ok(())
+    }
+}
+// Run `PRAGMA foreign_key_check` over one table (or the whole database) and
+// summarize the orphan values of every violated foreign key
+pub async fn foreign_key_check(
+    conn: &mut SqliteConnection,
+    table_name: Option<&str>,
+) -> Result<ForeignKeyReport, color_eyre::eyre::Error> {
+    let groups: Vec<(String, i64, i64)> =
+        match table_name {
+            Some(table_name) => sqlx::query_as(
+                "SELECT \"table\", fkid, COUNT(*) FROM pragma_foreign_key_check(?) GROUP BY 1, 2",
+            )
+            .bind(table_name)
+            .fetch_all(&mut *conn)
+            .await?,
+            None => sqlx::query_as(
+                "SELECT \"table\", fkid, COUNT(*) FROM pragma_foreign_key_check() GROUP BY 1, 2",
+            )
+            .fetch_all(&mut *conn)
+            .await?,
+        };
+    let mut report = ForeignKeyReport::default();
+    for (table, fkid, n_rows) in groups {
+        let (column, parent_table, parent_column): (String, String, Option<String>) =
+            sqlx::query_as(
+                "SELECT \"from\", \"table\", \"to\" FROM pragma_foreign_key_list(?) WHERE id = ?",
+            )
+            .bind(&table)
+            .bind(fkid)
+            .fetch_one(&mut *conn)
+            .await?;
+        let parent_column = parent_column.unwrap_or_else(|| "rowid".to_string());
+        let parent_exists: Option<String> =
+            sqlx::query_scalar("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+                .bind(&parent_table)
+                .fetch_optional(&mut *conn)
+                .await?;
+        let orphans = match parent_exists {
+            Some(_) => format!(
+                "SELECT CAST(c.{col} AS TEXT), COUNT(*) FROM {table} c \
+                 WHERE c.{col} IS NOT NULL AND NOT EXISTS \
+                 (SELECT 1 FROM {parent} p WHERE p.{pcol} = c.{col}) \
+                 GROUP BY 1 ORDER BY 2 DESC",
+                col = column,
+                table = table,
+                parent = parent_table,
+                pcol = parent_column
+            ),
+            None => format!(
+                "SELECT CAST({col} AS TEXT), COUNT(*) FROM {table} \
+                 WHERE {col} IS NOT NULL GROUP BY 1 ORDER BY 2 DESC",
+                col = column,
+                table = table
+            ),
+        };
+        let orphan_values: Vec<(String, i64)> =
+            sqlx::query_as(&orphans).fetch_all(&mut *conn).await?;
+        report.violations.push(ForeignKeyViolation {
+            table_name: table,
+            column,
+            parent_table,
+            parent_column,
+            n_rows,
+            orphan_values,
+        });
+    }
+    Ok(report)
+}
+#[derive(Debug, Clone, Default)]
 pub enum IfExistsOption {
     #[default]
     Fail,
     Replace,
     Append,
 }
+// What to do with an existing table whose columns differ from the incoming
+// schema when appending
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum MigrateOption {
+    #[default]
+    Ignore,
+    AddColumns,
+    Rebuild,
+}
 
+// Connection settings used while loading large tables, they trade durability
+// for speed so `SqlWriter::end_bulk_load` must be called once the load ends
+#[derive(Debug, Clone)]
+pub struct BulkLoadProfile {
+    journal_mode: SqliteJournalMode,
+    synchronous: SqliteSynchronous,
+    cache_size: i64,
+    temp_store_memory: bool,
+    mmap_size: u64,
+    page_size: Option<u32>,
+    vacuum: bool,
+}
+impl Default for BulkLoadProfile {
+    fn default() -> Self {
+        Self {
+            journal_mode: SqliteJournalMode::Wal,
+            synchronous: SqliteSynchronous::Off,
+            // Negative values are KiB, ~1 GiB of page cache
+            cache_size: -1_000_000,
+            temp_store_memory: true,
+            mmap_size: 1 << 30,
+            page_size: None,
+            vacuum: true,
+        }
+    }
+}
+impl BulkLoadProfile {
+    pub fn with_journal_mode(mut self, journal_mode: SqliteJournalMode) -> Self {
+        self.journal_mode = journal_mode;
+        self
+    }
+    pub fn with_synchronous(mut self, synchronous: SqliteSynchronous) -> Self {
+        self.synchronous = synchronous;
+        self
+    }
+    pub fn with_cache_size(mut self, cache_size: i64) -> Self {
+        self.cache_size = cache_size;
+        self
+    }
+    pub fn with_temp_store_memory(mut self, temp_store_memory: bool) -> Self {
+        self.temp_store_memory = temp_store_memory;
+        self
+    }
+    pub fn with_mmap_size(mut self, mmap_size: u64) -> Self {
+        self.mmap_size = mmap_size;
+        self
+    }
+    // Only applies to new databases or after the final VACUUM
+    pub fn with_page_size(mut self, page_size: Option<u32>) -> Self {
+        self.page_size = page_size;
+        self
+    }
+    pub fn with_vacuum(mut self, vacuum: bool) -> Self {
+        self.vacuum = vacuum;
+        self
+    }
+    fn connect_options(&self, options: SqliteConnectOptions) -> SqliteConnectOptions {
+        let mut options = options
+            .journal_mode(self.journal_mode)
+            .synchronous(self.synchronous)
+            .pragma("cache_size", self.cache_size.to_string())
+            .pragma(
+                "temp_store",
+                if self.temp_store_memory {
+                    "MEMORY"
+                } else {
+                    "DEFAULT"
+                },
+            )
+            .pragma("mmap_size", self.mmap_size.to_string());
+        if let Some(page_size) = self.page_size {
+            options = options.page_size(page_size);
+        }
+        options
+    }
+}
+// Settings of the database that outlive the bulk load. The other pragmas of
+// `BulkLoadProfile` are per connection and end with the pool, only the
+// journal mode is stored in the file and has to be restored
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlitePragmas {
+    pub journal_mode: String,
+}
+impl SqlitePragmas {
+    pub async fn read(conn: &mut SqliteConnection) -> Result<Self, color_eyre::eyre::Error> {
+        let journal_mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+            .fetch_one(&mut *conn)
+            .await?;
+        Ok(Self { journal_mode })
+    }
+    pub async fn apply(&self, conn: &mut SqliteConnection) -> Result<(), color_eyre::eyre::Error> {
+        sqlx::query(&format!("PRAGMA journal_mode = {}", self.journal_mode))
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+}
+// Options of `SqlWriter` shared by every backend, resolved for one `finish`
 #[derive(Clone)]
-pub struct SqlWriter {
+pub struct WriteOptions {
+    pub table_name: String,
+    pub schema: SqliteSchema,
+    pub if_exists: IfExistsOption,
+    pub migrate: MigrateOption,
+    pub foreign_keys: bool,
+    pub date_format: SqliteDateFormat,
+    pub strict_insert: bool,
+    pub upsert_key: Option<String>,
+    pub batch_size: NonZeroUsize,
+    pub n_threads: usize,
+    pub parallel: bool,
+    pub progress: Option<SharedProgress>,
+}
+impl WriteOptions {
+    // Observer of the inserted rows, a no-op one when not set
+    pub fn progress(&self) -> &dyn ProgressObserver {
+        self.progress.as_deref().unwrap_or(&NoProgress)
+    }
+    // Upsert clause shared by SQLite, Postgres and DuckDB, every column but
+    // the key takes the incoming value
+    pub fn on_conflict(&self, columns: &[&str]) -> Option<String> {
+        let key = self.upsert_key.as_ref()?;
+        let updates = columns
+            .iter()
+            .filter(|c| *c != key)
+            .map(|c| format!("{} = excluded.{}", c, c))
+            .collect::<Vec<_>>();
+        Some(if updates.is_empty() {
+            format!("ON CONFLICT ({}) DO NOTHING", key)
+        } else {
+            format!("ON CONFLICT ({}) DO UPDATE SET {}", key, updates.join(", "))
+        })
+    }
+}
+// Database where `SqlWriter` creates the tables and inserts the rows
+pub trait SqlBackend: Clone + Send + Sync {
+    // Returns the rows written
+    fn write<'a>(
+        &'a self,
+        df: &'a mut DataFrame,
+        options: &'a WriteOptions,
+    ) -> BoxFuture<'a, Result<u64, color_eyre::eyre::Error>>;
+}
+#[derive(Clone)]
+pub struct SqliteBackend {
     pool: Arc<SqlitePool>,
+    bulk_load: Option<(BulkLoadProfile, SqlitePragmas)>,
+}
+impl SqliteBackend {
+    pub fn new(pool: Pool<Sqlite>) -> Self {
+        Self {
+            pool: Arc::new(pool),
+            bulk_load: None,
+        }
+    }
+    async fn migrate_table(
+        &self,
+        conn: &mut SqliteConnection,
+        options: &WriteOptions,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        let stored = match read_table_schema(conn, &options.table_name).await? {
+            Some(stored) => stored,
+            None => return Ok(()),
+        };
+        let plan = options
+            .schema
+            .migration_plan(&stored, options.table_name.clone());
+        if plan.is_empty() {
+            return Ok(());
+        }
+        if plan.requires_rebuild && options.migrate != MigrateOption::Rebuild {
+            return Err(color_eyre::eyre::eyre!(
+                "Table {} needs to be rebuilt to match the schema:\n{}",
+                options.table_name,
+                plan.diff
+            ));
+        }
+        let mut tx = Connection::begin(conn).await?;
+        for statement in &plan.statements {
+            sqlx::query(statement).execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+    async fn write_df(
+        &self,
+        df: &mut DataFrame,
+        options: &WriteOptions,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        let table_name = &options.table_name;
+        let qry = options.schema.clone().finish(table_name);
+        // The pragma only applies to the connection, so every statement
+        // of the load goes through the same one
+        let mut conn = self.pool.acquire().await?;
+        sqlx::query(if options.foreign_keys {
+            "PRAGMA foreign_keys = ON"
+        } else {
+            "PRAGMA foreign_keys = OFF"
+        })
+        .execute(&mut *conn)
+        .await?;
+        match options.if_exists {
+            IfExistsOption::Append => {
+                if options.migrate != MigrateOption::Ignore {
+                    self.migrate_table(&mut conn, options).await?;
+                }
+            }
+            IfExistsOption::Replace => {
+                sqlx::query(&format!("DROP TABLE IF EXISTS {}", table_name))
+                    .execute(&mut *conn)
+                    .await?;
+            }
+            IfExistsOption::Fail => {
+                let rows = sqlx::query("SELECT name FROM sqlite_master WHERE name = ?")
+                    .bind(table_name)
+                    .fetch_all(&mut *conn)
+                    .await?;
+                if !rows.is_empty() {
+                    return Err(color_eyre::eyre::eyre!(
+                        "Table {} already exists",
+                        table_name
+                    ));
+                }
+            }
+        }
+        sqlx::query(&qry).execute(&mut *conn).await?;
+        insert_df(df, options, &mut conn).await
+    }
+}
+impl SqlBackend for SqliteBackend {
+    fn write<'a>(
+        &'a self,
+        df: &'a mut DataFrame,
+        options: &'a WriteOptions,
+    ) -> BoxFuture<'a, Result<u64, color_eyre::eyre::Error>> {
+        Box::pin(self.write_df(df, options))
+    }
+}
+
+#[derive(Clone)]
+pub struct SqlWriter<B: SqlBackend = SqliteBackend> {
+    backend: B,
     table_name: Option<String>,
     if_exists: IfExistsOption,
     index: bool,
     parallel: bool,
     n_threads: usize,
     strict_insert: bool,
+    upsert_key: Option<String>,
     batch_size: NonZeroUsize,
     index_label: Option<String>,
     schema: Option<SqliteSchema>,
+    migrate: MigrateOption,
+    foreign_keys: bool,
+    date_format: SqliteDateFormat,
+    progress: Option<SharedProgress>,
 }
-impl SqlWriter {
+impl SqlWriter<SqliteBackend> {
     pub fn new<P: AsRef<Path>>(db_url: P) -> Result<Self, color_eyre::eyre::Error> {
-        let rt = Runtime::new()?;
+        block_on(Self::connect(db_url))?
+    }
+    pub async fn connect<P: AsRef<Path>>(db_url: P) -> Result<Self, color_eyre::eyre::Error> {
         let n_threads = num_cpus::get();
         let options = SqliteConnectOptions::new()
             .filename(db_url)
             .busy_timeout(std::time::Duration::from_secs(10))
             .create_if_missing(true);
-        let pool = Arc::new(
-            rt.block_on(
-                SqlitePoolOptions::new()
-                    .max_connections(n_threads as u32)
-                    .connect_with(options),
-            )?,
-        );
-        Ok(SqlWriter {
-            pool,
-            if_exists: IfExistsOption::default(),
-            index: true,
-            parallel: true,
-            batch_size: NonZeroUsize::new(1024).unwrap(),
-            strict_insert: true,
-            n_threads,
-            index_label: None,
-            table_name: None,
-            schema: None,
-        })
+        let pool = SqlitePoolOptions::new()
+            .max_connections(n_threads as u32)
+            .connect_with(options)
+            .await?;
+        Ok(SqlWriter::with_backend(SqliteBackend::new(pool)).n_threads(n_threads))
+    }
+    // Writer with a single connection tuned with `profile`, SQLite only
+    // allows one writer at a time so more connections would only wait
+    pub fn new_bulk<P: AsRef<Path>>(
+        db_url: P,
+        profile: BulkLoadProfile,
+    ) -> Result<Self, color_eyre::eyre::Error> {
+        block_on(Self::connect_bulk(db_url, profile))?
+    }
+    pub async fn connect_bulk<P: AsRef<Path>>(
+        db_url: P,
+        profile: BulkLoadProfile,
+    ) -> Result<Self, color_eyre::eyre::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(db_url)
+            .busy_timeout(std::time::Duration::from_secs(10))
+            .create_if_missing(true);
+        let mut conn = SqliteConnection::connect_with(&options).await?;
+        let saved = SqlitePragmas::read(&mut conn).await?;
+        conn.close().await?;
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(profile.connect_options(options))
+            .await?;
+        let mut writer = SqlWriter::new_from_pool(pool)?.n_threads(1);
+        writer.backend.bulk_load = Some((profile, saved));
+        Ok(writer)
+    }
+    // Restore the journal mode the database had before `new_bulk` and
+    // refresh the statistics of the query planner
+    pub fn end_bulk_load(&self) -> Result<(), color_eyre::eyre::Error> {
+        block_on(self.end_bulk_load_async())?
+    }
+    pub async fn end_bulk_load_async(&self) -> Result<(), color_eyre::eyre::Error> {
+        let (profile, saved) = match &self.backend.bulk_load {
+            Some(bulk_load) => bulk_load,
+            None => return Ok(()),
+        };
+        let mut conn = self.backend.pool.acquire().await?;
+        saved.apply(&mut conn).await?;
+        sqlx::query("ANALYZE").execute(&mut *conn).await?;
+        if profile.vacuum {
+            sqlx::query("VACUUM").execute(&mut *conn).await?;
+        }
+        Ok(())
     }
     pub fn new_from_pool(pool: Pool<Sqlite>) -> Result<Self, color_eyre::eyre::Error> {
-        Ok(SqlWriter {
-            pool: Arc::new(pool),
+        Ok(SqlWriter::with_backend(SqliteBackend::new(pool)))
+    }
+    pub fn check_foreign_keys(&self) -> Result<ForeignKeyReport, color_eyre::eyre::Error> {
+        block_on(self.check_foreign_keys_async())?
+    }
+    pub async fn check_foreign_keys_async(
+        &self,
+    ) -> Result<ForeignKeyReport, color_eyre::eyre::Error> {
+        foreign_key_check(
+            &mut *self.backend.pool.acquire().await?,
+            self.table_name.as_deref(),
+        )
+        .await
+    }
+    // Dry run of the migration `finish` would apply to the stored table,
+    // the plan is empty when the table does not exist or has no drift
+    pub fn plan_migration(&self, df: &DataFrame) -> Result<MigrationPlan, color_eyre::eyre::Error> {
+        block_on(self.plan_migration_async(df))?
+    }
+    pub async fn plan_migration_async(
+        &self,
+        df: &DataFrame,
+    ) -> Result<MigrationPlan, color_eyre::eyre::Error> {
+        let table_name = self.get_table_name();
+        let schema = self.clone().build_schema(df)?;
+        let stored =
+            read_table_schema(&mut *self.backend.pool.acquire().await?, &table_name).await?;
+        Ok(match stored {
+            Some(stored) => schema.migration_plan(&stored, table_name),
+            None => MigrationPlan {
+                table_name,
+                ..Default::default()
+            },
+        })
+    }
+}
+impl<B: SqlBackend> SqlWriter<B> {
+    pub fn with_backend(backend: B) -> Self {
+        SqlWriter {
+            backend,
             if_exists: IfExistsOption::default(),
             index: true,
             parallel: true,
             batch_size: NonZeroUsize::new(1024).unwrap(),
             strict_insert: true,
+            upsert_key: None,
             n_threads: num_cpus::get(),
             index_label: None,
             table_name: None,
             schema: None,
-        })
+            migrate: MigrateOption::default(),
+            foreign_keys: false,
+            date_format: SqliteDateFormat::default(),
+            progress: None,
+        }
+    }
+    pub fn backend(&self) -> &B {
+        &self.backend
     }
     pub fn with_table<T: Into<String>>(mut self, table_name: Option<T>) -> Self {
         self.table_name = table_name.map(|t| t.into());
@@ -309,18 +1010,42 @@ impl SqlWriter {
         self.strict_insert = strict;
         self
     }
+    // Rows whose key already exists replace the stored values
+    pub fn with_upsert_key<T: Into<String>>(mut self, key: Option<T>) -> Self {
+        self.upsert_key = key.map(|k| k.into());
+        self
+    }
     pub fn n_threads(mut self, n_threads: usize) -> Self {
         self.n_threads = n_threads;
         self
     }
-
-    pub fn finish(&mut self, df: &mut DataFrame) -> Result<(), color_eyre::eyre::Error> {
-        // Delete table and if create the schema
-        let table_name = match self.table_name.as_ref() {
+    pub fn with_migrate(mut self, migrate: MigrateOption) -> Self {
+        self.migrate = migrate;
+        self
+    }
+    // `PRAGMA foreign_keys` used while writing, off by default like SQLite.
+    // When disabled the orphan values can be reviewed afterwards with
+    // `check_foreign_keys`
+    pub fn with_foreign_keys(mut self, foreign_keys: bool) -> Self {
+        self.foreign_keys = foreign_keys;
+        self
+    }
+    // Reports the rows inserted by every `finish` under the table name
+    pub fn with_progress(mut self, progress: Option<SharedProgress>) -> Self {
+        self.progress = progress;
+        self
+    }
+    pub fn with_date_format(mut self, date_format: SqliteDateFormat) -> Self {
+        self.date_format = date_format;
+        self
+    }
+    fn get_table_name(&self) -> String {
+        match self.table_name.as_ref() {
             Some(t) => t.clone(),
             None => "test".to_string(),
-        };
-        let rt = Runtime::new()?;
+        }
+    }
+    fn build_schema(&mut self, df: &DataFrame) -> Result<SqliteSchema, color_eyre::eyre::Error> {
         let mut schema = SqliteSchema::from_polars_schema(df.schema());
         self.index_label = if self.index && self.index_label.is_none() {
             Some("Id".into())
@@ -349,59 +1074,67 @@ impl SqlWriter {
                 }
             }
         }
-        self.schema = Some(schema.clone());
-        let qry = schema.finish(&table_name);
-        match self.if_exists {
-            IfExistsOption::Append => {}
-            IfExistsOption::Replace => {
-                rt.block_on(
-                    sqlx::query(&format!("DROP TABLE IF EXISTS {}", table_name))
-                        .execute(&*self.pool),
-                )?;
-            }
-            IfExistsOption::Fail => {
-                let table = self
-                    .table_name
-                    .as_ref()
-                    .unwrap_or(&"test".to_string())
-                    .clone();
-                let rows = rt.block_on(
-                    sqlx::query("SELECT name FROM sqlite_master WHERE name = ?")
-                        .bind(&table)
-                        .fetch_all(&*self.pool),
-                )?;
-                if !rows.is_empty() {
-                    return Err(color_eyre::eyre::eyre!("Table {} already exists", table));
+        // The date columns always follow `date_format`, whatever the given schema says
+        for field in df.schema().iter_fields() {
+            if matches!(field.dtype(), DataType::Date | DataType::Datetime(_, _)) {
+                if let Some(options) = schema.get(field.name()) {
+                    let options = options.clone().with_type_sql(self.date_format.type_sql());
+                    schema.with_column(field.name().as_str(), options);
                 }
             }
         }
-        rt.block_on(sqlx::query(&qry).execute(&*self.pool))?;
-        if df.height() < self.batch_size.into() {
-            self.batch_size = NonZeroUsize::new(1).unwrap();
-        }
-        rt.block_on(insert_df(
-            df,
-            table_name,
-            self.batch_size.into(),
-            self.strict_insert,
-            self.n_threads,
-            self.parallel,
-            Arc::clone(&self.pool),
-        ))?;
+        self.schema = Some(schema.clone());
+        Ok(schema)
+    }
 
-        Ok(())
+    // Returns the rows written, with upsert or INSERT OR IGNORE they can be
+    // fewer than the rows of `df`
+    pub fn finish(&mut self, df: &mut DataFrame) -> Result<u64, color_eyre::eyre::Error> {
+        block_on(self.finish_async(df))?
+    }
+    pub async fn finish_async(
+        &mut self,
+        df: &mut DataFrame,
+    ) -> Result<u64, color_eyre::eyre::Error> {
+        // Delete table and if create the schema
+        let table_name = self.get_table_name();
+        let schema = self.build_schema(df)?;
+        // A frame smaller than a batch goes in a single one
+        let batch_size = NonZeroUsize::new(df.height())
+            .map_or(self.batch_size, |height| self.batch_size.min(height));
+        let options = WriteOptions {
+            table_name,
+            schema,
+            if_exists: self.if_exists.clone(),
+            migrate: self.migrate.clone(),
+            foreign_keys: self.foreign_keys,
+            date_format: self.date_format,
+            strict_insert: self.strict_insert,
+            upsert_key: self.upsert_key.clone(),
+            batch_size,
+            n_threads: self.n_threads,
+            parallel: self.parallel,
+            progress: self.progress.clone(),
+        };
+        // One span per write with the table and rows, its close event carries the duration
+        let span = info_span!(
+            "sql_write",
+            table = %options.table_name,
+            rows = df.height(),
+            if_exists = ?options.if_exists
+        );
+        self.backend.write(df, &options).instrument(span).await
     }
 }
 
 async fn insert_df(
     df: &mut DataFrame,
-    table_name: String,
-    batch_size: usize,
-    strict_insert: bool,
-    n_threads: usize,
-    parallel: bool,
-    pool: Arc<SqlitePool>,
-) -> Result<(), color_eyre::eyre::Error> {
+    options: &WriteOptions,
+    conn: &mut SqliteConnection,
+) -> Result<u64, color_eyre::eyre::Error> {
+    let table_name = &options.table_name;
+    let batch_size: usize = options.batch_size.into();
+    let date_format = options.date_format;
     let generate_insert_qry = |row: Option<Vec<AnyValue>>| -> Option<String> {
         match row {
             Some(r) => Some(
@@ -430,6 +1163,19 @@ async fn insert_df(
                         AnyValue::Float32(v) => v.to_string(),
                         AnyValue::Float64(v) => v.to_string(),
                         AnyValue::Decimal(i, d) => format!("{}.{}", i, d),
+                        AnyValue::Date(days) if date_format == SqliteDateFormat::JulianDay => {
+                            SqliteDateFormat::julian_day(*days as f64).to_string()
+                        }
+                        AnyValue::Datetime(v, unit, _)
+                            if date_format == SqliteDateFormat::JulianDay =>
+                        {
+                            let per_day = match unit {
+                                TimeUnit::Nanoseconds => 86_400_000_000_000.0,
+                                TimeUnit::Microseconds => 86_400_000_000.0,
+                                TimeUnit::Milliseconds => 86_400_000.0,
+                            };
+                            SqliteDateFormat::julian_day(*v as f64 / per_day).to_string()
+                        }
                         _ => format!(
                             "'{}'",
                             value.to_string().trim_matches('\"').replace("'", "''")
@@ -443,7 +1189,13 @@ async fn insert_df(
     };
     let len = df.height();
     let mut n_rows_finished: usize = 0;
-    let insert = if strict_insert {
+    let mut n_rows_written: u64 = 0;
+    let progress = options.progress();
+    progress.start(ProgressStage::Insert, table_name, Some(len as u64));
+    let on_conflict = options
+        .on_conflict(&df.get_column_names_str())
+        .unwrap_or_default();
+    let insert = if options.strict_insert || options.upsert_key.is_some() {
         format!(
             "INSERT INTO {} ({}) ",
             table_name,
@@ -468,7 +1220,7 @@ async fn insert_df(
             break;
         }
 
-        for dfc in df.split_chunks_by_n(n_threads, parallel) {
+        for dfc in df.split_chunks_by_n(options.n_threads, options.parallel) {
             let mut row_sql = Vec::new();
             for i in 0..dfc.height() {
                 let row = dfc.get(i);
@@ -480,10 +1232,436 @@ async fn insert_df(
             if dfc.is_empty() {
                 continue;
             }
-            let full_insert = format!("{} VALUES {}", insert, row_sql.join(","));
-            sqlx::query(&full_insert).execute(&*pool).await?;
+            let full_insert = format!("{} VALUES {} {}", insert, row_sql.join(","), on_conflict);
+            n_rows_written += sqlx::query(&full_insert)
+                .execute(&mut *conn)
+                .await?
+                .rows_affected();
+            progress.advance(ProgressStage::Insert, table_name, dfc.height() as u64);
         }
         n_rows_finished += batch_size;
     }
-    Ok(())
+    progress.finish(ProgressStage::Insert, table_name);
+    Ok(n_rows_written)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+impl From<i64> for SqlValue {
+    fn from(v: i64) -> Self {
+        SqlValue::Integer(v)
+    }
+}
+impl From<i32> for SqlValue {
+    fn from(v: i32) -> Self {
+        SqlValue::Integer(v as i64)
+    }
+}
+impl From<u32> for SqlValue {
+    fn from(v: u32) -> Self {
+        SqlValue::Integer(v as i64)
+    }
+}
+impl From<f64> for SqlValue {
+    fn from(v: f64) -> Self {
+        SqlValue::Real(v)
+    }
+}
+impl From<&str> for SqlValue {
+    fn from(v: &str) -> Self {
+        SqlValue::Text(v.to_string())
+    }
+}
+impl From<String> for SqlValue {
+    fn from(v: String) -> Self {
+        SqlValue::Text(v)
+    }
+}
+// Condition pushed down into the WHERE clause of the reader, the values are
+// always sent as bound parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlPredicate {
+    sql: String,
+    params: Vec<SqlValue>,
+}
+impl SqlPredicate {
+    pub fn raw<T: Into<String>>(sql: T, params: Vec<SqlValue>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+        }
+    }
+    fn combine(mut self, op: &str, other: SqlPredicate) -> Self {
+        self.sql = format!("({}) {} ({})", self.sql, op, other.sql);
+        self.params.extend(other.params);
+        self
+    }
+    pub fn and(self, other: SqlPredicate) -> Self {
+        self.combine("AND", other)
+    }
+    pub fn or(self, other: SqlPredicate) -> Self {
+        self.combine("OR", other)
+    }
+}
+impl std::ops::Not for SqlPredicate {
+    type Output = SqlPredicate;
+    fn not(mut self) -> Self::Output {
+        self.sql = format!("NOT ({})", self.sql);
+        self
+    }
+}
+impl std::fmt::Display for SqlPredicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.sql)
+    }
+}
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlCol(String);
+pub fn sql_col<T: Into<String>>(name: T) -> SqlCol {
+    SqlCol(name.into())
+}
+impl SqlCol {
+    fn compare<V: Into<SqlValue>>(&self, op: &str, value: V) -> SqlPredicate {
+        SqlPredicate::raw(format!("{} {} ?", self.0, op), vec![value.into()])
+    }
+    pub fn eq<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare("=", value)
+    }
+    pub fn neq<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare("<>", value)
+    }
+    pub fn gt<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare(">", value)
+    }
+    pub fn gt_eq<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare(">=", value)
+    }
+    pub fn lt<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare("<", value)
+    }
+    pub fn lt_eq<V: Into<SqlValue>>(&self, value: V) -> SqlPredicate {
+        self.compare("<=", value)
+    }
+    pub fn between<V: Into<SqlValue>>(&self, low: V, high: V) -> SqlPredicate {
+        SqlPredicate::raw(
+            format!("{} BETWEEN ? AND ?", self.0),
+            vec![low.into(), high.into()],
+        )
+    }
+    pub fn is_in<V: Into<SqlValue>>(&self, values: Vec<V>) -> SqlPredicate {
+        let params: Vec<SqlValue> = values.into_iter().map(|v| v.into()).collect();
+        if params.is_empty() {
+            return SqlPredicate::raw("0", vec![]);
+        }
+        SqlPredicate::raw(
+            format!("{} IN ({})", self.0, vec!["?"; params.len()].join(",")),
+            params,
+        )
+    }
+    pub fn is_null(&self) -> SqlPredicate {
+        SqlPredicate::raw(format!("{} IS NULL", self.0), vec![])
+    }
+    pub fn is_not_null(&self) -> SqlPredicate {
+        SqlPredicate::raw(format!("{} IS NOT NULL", self.0), vec![])
+    }
+}
+
+#[derive(Clone)]
+pub struct SqlReader {
+    pool: Arc<SqlitePool>,
+    table_name: Option<String>,
+    query: Option<String>,
+    columns: Option<Vec<String>>,
+    predicates: Vec<SqlPredicate>,
+    limit: Option<usize>,
+    schema: Option<SqliteSchema>,
+    chunk_size: NonZeroUsize,
+}
+impl SqliteBackend {
+    pub fn reader(&self) -> SqlReader {
+        SqlReader::from_shared_pool(Arc::clone(&self.pool))
+    }
+}
+impl SqlWriter<SqliteBackend> {
+    pub fn reader(&self) -> SqlReader {
+        self.backend.reader().with_table(self.table_name.clone())
+    }
+}
+impl SqlReader {
+    pub fn new<P: AsRef<Path>>(db_url: P) -> Result<Self, color_eyre::eyre::Error> {
+        block_on(Self::connect(db_url))?
+    }
+    pub async fn connect<P: AsRef<Path>>(db_url: P) -> Result<Self, color_eyre::eyre::Error> {
+        let options = SqliteConnectOptions::new()
+            .filename(db_url)
+            .busy_timeout(std::time::Duration::from_secs(10))
+            .read_only(true);
+        let pool = SqlitePoolOptions::new()
+            .max_connections(num_cpus::get() as u32)
+            .connect_with(options)
+            .await?;
+        Ok(Self::from_shared_pool(Arc::new(pool)))
+    }
+    pub fn new_from_pool(pool: Pool<Sqlite>) -> Result<Self, color_eyre::eyre::Error> {
+        Ok(Self::from_shared_pool(Arc::new(pool)))
+    }
+    pub fn table_exists(&self, table_name: &str) -> Result<bool, color_eyre::eyre::Error> {
+        block_on(self.table_exists_async(table_name))?
+    }
+    pub async fn table_exists_async(
+        &self,
+        table_name: &str,
+    ) -> Result<bool, color_eyre::eyre::Error> {
+        let row = sqlx::query("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?")
+            .bind(table_name)
+            .fetch_optional(&*self.pool)
+            .await?;
+        Ok(row.is_some())
+    }
+    fn from_shared_pool(pool: Arc<SqlitePool>) -> Self {
+        SqlReader {
+            pool,
+            table_name: None,
+            query: None,
+            columns: None,
+            predicates: Vec::new(),
+            limit: None,
+            schema: None,
+            chunk_size: NonZeroUsize::new(100_000).unwrap(),
+        }
+    }
+    pub fn with_table<T: Into<String>>(mut self, table_name: Option<T>) -> Self {
+        self.table_name = table_name.map(|t| t.into());
+        self
+    }
+    // Arbitrary SELECT, used as a subquery when there are predicates or columns
+    pub fn with_query<T: Into<String>>(mut self, query: Option<T>) -> Self {
+        self.query = query.map(|q| q.into());
+        self
+    }
+    pub fn with_schema(mut self, schema: Option<SqliteSchema>) -> Self {
+        self.schema = schema;
+        self
+    }
+    pub fn with_chunk_size(mut self, chunk_size: NonZeroUsize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+    pub fn select<T: Into<String>>(mut self, columns: Vec<T>) -> Self {
+        self.columns = Some(columns.into_iter().map(|c| c.into()).collect());
+        self
+    }
+    pub fn filter(mut self, predicate: SqlPredicate) -> Self {
+        self.predicates.push(predicate);
+        self
+    }
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+    // SQL that will be executed, with `?` placeholders for the predicates
+    pub fn build_query(&self) -> Result<String, color_eyre::eyre::Error> {
+        let source = match (&self.query, &self.table_name) {
+            (Some(q), _) => format!("({})", q.trim().trim_end_matches(';')),
+            (None, Some(t)) => t.clone(),
+            (None, None) => {
+                return Err(color_eyre::eyre::eyre!(
+                    "SqlReader needs a table or a query to read"
+                ))
+            }
+        };
+        let columns = match &self.columns {
+            Some(c) => c.join(","),
+            None => "*".to_string(),
+        };
+        let mut qry = format!("SELECT {} FROM {}", columns, source);
+        if !self.predicates.is_empty() {
+            let conditions = self
+                .predicates
+                .iter()
+                .map(|p| format!("({})", p.sql))
+                .collect::<Vec<_>>();
+            qry.push_str(&format!(" WHERE {}", conditions.join(" AND ")));
+        }
+        if let Some(limit) = self.limit {
+            qry.push_str(&format!(" LIMIT {}", limit));
+        }
+        Ok(qry)
+    }
+    fn bind_params<'q>(
+        &self,
+        mut qry: sqlx::query::Query<'q, Sqlite, SqliteArguments<'q>>,
+    ) -> sqlx::query::Query<'q, Sqlite, SqliteArguments<'q>> {
+        for value in self.predicates.iter().flat_map(|p| p.params.iter()) {
+            qry = match value.clone() {
+                SqlValue::Null => qry.bind(None::<i64>),
+                SqlValue::Integer(v) => qry.bind(v),
+                SqlValue::Real(v) => qry.bind(v),
+                SqlValue::Text(v) => qry.bind(v),
+            };
+        }
+        qry
+    }
+    // Declared type of every output column, the schema given by the user
+    // has priority over the types of the table
+    async fn resolve_columns(
+        &self,
+        qry: &str,
+    ) -> Result<Vec<(String, Option<SqliteDataType>)>, color_eyre::eyre::Error> {
+        let stored = match (&self.schema, &self.table_name, &self.query) {
+            (None, Some(table), None) => {
+                read_table_schema(&mut *self.pool.acquire().await?, table).await?
+            }
+            _ => None,
+        };
+        let describe = (&*self.pool).describe(qry).await?;
+        Ok(describe
+            .columns()
+            .iter()
+            .map(|c| {
+                let name = c.name().to_string();
+                let type_sql = self
+                    .schema
+                    .as_ref()
+                    .or(stored.as_ref())
+                    .and_then(|s| s.get(&name))
+                    .map(|o| o.type_sql.clone())
+                    .or_else(|| match c.type_info().name() {
+                        "NULL" => None,
+                        declared => Some(SqliteDataType::from_declared_type(declared)),
+                    });
+                (name, type_sql)
+            })
+            .collect())
+    }
+    pub fn finish(&self) -> Result<DataFrame, color_eyre::eyre::Error> {
+        block_on(self.finish_async())?
+    }
+    pub async fn finish_async(&self) -> Result<DataFrame, color_eyre::eyre::Error> {
+        let qry = self.build_query()?;
+        let columns = self.resolve_columns(&qry).await?;
+        // Not cached: a statement prepared before an ALTER TABLE keeps the old
+        // columns while `resolve_columns` already sees the new ones
+        let rows = self
+            .bind_params(sqlx::query(&qry).persistent(false))
+            .fetch_all(&*self.pool)
+            .await?;
+        rows_to_df(&columns, &rows)
+    }
+    // Read the result in DataFrames of at most `chunk_size` rows, the rows are
+    // streamed from SQLite in a background thread so memory stays bounded
+    pub fn iter_chunks(&self) -> Result<SqlChunks, color_eyre::eyre::Error> {
+        let reader = self.clone();
+        let (tx, rx) = sync_channel::<Result<DataFrame, color_eyre::eyre::Error>>(1);
+        let handle = std::thread::spawn(move || {
+            let res = runtime().block_on(async {
+                let qry = reader.build_query()?;
+                let columns = reader.resolve_columns(&qry).await?;
+                let mut stream = reader
+                    .bind_params(sqlx::query(&qry).persistent(false))
+                    .fetch(&*reader.pool);
+                let mut buffer = Vec::with_capacity(reader.chunk_size.into());
+                while let Some(row) = stream.try_next().await? {
+                    buffer.push(row);
+                    if buffer.len() == usize::from(reader.chunk_size) {
+                        if tx.send(rows_to_df(&columns, &buffer)).is_err() {
+                            return Ok(());
+                        }
+                        buffer.clear();
+                    }
+                }
+                if !buffer.is_empty() {
+                    let _ = tx.send(rows_to_df(&columns, &buffer));
+                }
+                Ok::<(), color_eyre::eyre::Error>(())
+            });
+            if let Err(e) = res {
+                let _ = tx.send(Err(e));
+            }
+        });
+        Ok(SqlChunks {
+            rx,
+            handle: Some(handle),
+        })
+    }
+}
+pub struct SqlChunks {
+    rx: Receiver<Result<DataFrame, color_eyre::eyre::Error>>,
+    handle: Option<JoinHandle<()>>,
+}
+impl Iterator for SqlChunks {
+    type Item = Result<DataFrame, color_eyre::eyre::Error>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.recv() {
+            std::result::Result::Ok(df) => Some(df),
+            Err(_) => {
+                if let Some(handle) = self.handle.take() {
+                    let _ = handle.join();
+                }
+                None
+            }
+        }
+    }
+}
+
+fn rows_to_df(
+    columns: &[(String, Option<SqliteDataType>)],
+    rows: &[SqliteRow],
+) -> Result<DataFrame, color_eyre::eyre::Error> {
+    let mut result = Vec::with_capacity(columns.len());
+    for (i, (name, type_sql)) in columns.iter().enumerate() {
+        // Columns without declared type (expressions) take the type of the
+        // first value that is not NULL
+        let type_sql = match type_sql {
+            Some(t) => t.clone(),
+            None => rows
+                .iter()
+                .map(|r| r.try_get_raw(i))
+                .find_map(|v| match v {
+                    std::result::Result::Ok(v) if !v.is_null() => {
+                        Some(SqliteDataType::from_declared_type(v.type_info().name()))
+                    }
+                    _ => None,
+                })
+                .unwrap_or_default(),
+        };
+        let name = PlSmallStr::from(name.as_str());
+        let series = match type_sql.to_polars_type() {
+            DataType::Int64 => {
+                let values = rows
+                    .iter()
+                    .map(|r| r.try_get_unchecked::<Option<i64>, _>(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Series::new(name, values)
+            }
+            DataType::String => {
+                let values = rows
+                    .iter()
+                    .map(|r| r.try_get_unchecked::<Option<String>, _>(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Series::new(name, values)
+            }
+            DataType::Binary => {
+                let values = rows
+                    .iter()
+                    .map(|r| r.try_get_unchecked::<Option<Vec<u8>>, _>(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Series::new(name, values)
+            }
+            dtype => {
+                let values = rows
+                    .iter()
+                    .map(|r| r.try_get_unchecked::<Option<f64>, _>(i))
+                    .collect::<Result<Vec<_>, _>>()?;
+                Series::new(name, values).cast(&dtype)?
+            }
+        };
+        result.push(series.into_column());
+    }
+    Ok(DataFrame::new(result)?)
 }
@@ -0,0 +1,201 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressStage {
+    // Bytes descargados de un archivo
+    Download,
+    // Entradas descomprimidas de un ZIP
+    Extract,
+    // Registros de un CSV procesados por lotes
+    Load,
+    // Registros insertados en una tabla
+    Insert,
+}
+// Recibe el avance de cada tarea, identificada por su etapa y su nombre
+// (URL, ZIP, CSV o tabla). Se comparte entre hilos
+pub trait ProgressObserver: Debug + Send + Sync {
+    // `total` en las unidades de la etapa si se conoce
+    fn start(&self, stage: ProgressStage, name: &str, total: Option<u64>);
+    fn advance(&self, stage: ProgressStage, name: &str, n: u64);
+    fn finish(&self, stage: ProgressStage, name: &str);
+}
+pub type SharedProgress = Arc<dyn ProgressObserver>;
+// Tarea de una etapa por su nombre
+type TaskKey = (ProgressStage, String);
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+impl ProgressObserver for NoProgress {
+    fn start(&self, _stage: ProgressStage, _name: &str, _total: Option<u64>) {}
+    fn advance(&self, _stage: ProgressStage, _name: &str, _n: u64) {}
+    fn finish(&self, _stage: ProgressStage, _name: &str) {}
+}
+// Cómo se muestra el avance
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressMode {
+    #[default]
+    None,
+    // Barras en la terminal (stderr)
+    Bar,
+    // Un objeto JSON por línea en stdout
+    Json,
+}
+impl ProgressMode {
+    pub fn observer(&self) -> Option<SharedProgress> {
+        match self {
+            ProgressMode::None => None,
+            ProgressMode::Bar => Some(Arc::new(TerminalProgress::new())),
+            ProgressMode::Json => Some(Arc::new(JsonProgress::stdout())),
+        }
+    }
+}
+// Una barra por tarea, las que no tienen total se muestran como contador
+#[derive(Debug, Default)]
+pub struct TerminalProgress {
+    bars: MultiProgress,
+    tasks: Mutex<HashMap<TaskKey, ProgressBar>>,
+}
+impl TerminalProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    fn style(stage: ProgressStage, total: Option<u64>) -> ProgressStyle {
+        let template = match (stage, total) {
+            (ProgressStage::Download, Some(_)) => {
+                "{prefix:>8} {msg} [{bar:30}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})"
+            }
+            (ProgressStage::Download, None) => "{prefix:>8} {msg} {spinner} {bytes}",
+            (_, Some(_)) => {
+                "{prefix:>8} {msg} [{bar:30}] {human_pos}/{human_len} ({per_sec}, {eta})"
+            }
+            (_, None) => "{prefix:>8} {msg} {spinner} {human_pos}",
+        };
+        ProgressStyle::with_template(template)
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("=> ")
+    }
+}
+impl ProgressObserver for TerminalProgress {
+    fn start(&self, stage: ProgressStage, name: &str, total: Option<u64>) {
+        let bar = match total {
+            Some(total) => ProgressBar::new(total),
+            None => ProgressBar::new_spinner(),
+        };
+        let bar = self.bars.add(bar.with_style(Self::style(stage, total)));
+        bar.set_prefix(format!("{:?}", stage).to_lowercase());
+        bar.set_message(name.to_string());
+        if let Ok(mut tasks) = self.tasks.lock() {
+            if let Some(old) = tasks.insert((stage, name.to_string()), bar) {
+                old.finish_and_clear();
+            }
+        }
+    }
+    fn advance(&self, stage: ProgressStage, name: &str, n: u64) {
+        if let Ok(tasks) = self.tasks.lock() {
+            if let Some(bar) = tasks.get(&(stage, name.to_string())) {
+                bar.inc(n);
+            }
+        }
+    }
+    fn finish(&self, stage: ProgressStage, name: &str) {
+        if let Ok(mut tasks) = self.tasks.lock() {
+            if let Some(bar) = tasks.remove(&(stage, name.to_string())) {
+                // Las inserciones son muchas y cortas, solo se deja la barra de lo demás
+                match stage {
+                    ProgressStage::Insert => bar.finish_and_clear(),
+                    _ => bar.finish(),
+                }
+            }
+        }
+    }
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressEventKind {
+    Start,
+    Advance,
+    Finish,
+}
+// Línea que escribe `JsonProgress`, `done` es el acumulado de la tarea
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProgressEvent {
+    pub event: ProgressEventKind,
+    pub stage: ProgressStage,
+    pub name: String,
+    pub done: u64,
+    pub total: Option<u64>,
+}
+// JSON delimitado por saltos de línea para quien orquesta las corridas
+#[derive(Debug)]
+pub struct JsonProgress<W: Write + Debug + Send> {
+    out: Mutex<W>,
+    tasks: Mutex<HashMap<TaskKey, (u64, Option<u64>)>>,
+}
+impl JsonProgress<io::Stdout> {
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+}
+impl<W: Write + Debug + Send> JsonProgress<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            tasks: Mutex::new(HashMap::new()),
+        }
+    }
+    pub fn into_inner(self) -> W {
+        self.out.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+    fn emit(
+        &self,
+        event: ProgressEventKind,
+        stage: ProgressStage,
+        name: &str,
+        n: u64,
+        total: Option<u64>,
+    ) {
+        let Ok(mut tasks) = self.tasks.lock() else {
+            return;
+        };
+        let key = (stage, name.to_string());
+        let (done, total) = match event {
+            ProgressEventKind::Start => {
+                tasks.insert(key, (0, total));
+                (0, total)
+            }
+            ProgressEventKind::Advance => {
+                let task = tasks.entry(key).or_insert((0, None));
+                task.0 += n;
+                *task
+            }
+            ProgressEventKind::Finish => tasks.remove(&key).unwrap_or((0, None)),
+        };
+        let line = ProgressEvent {
+            event,
+            stage,
+            name: name.to_string(),
+            done,
+            total,
+        };
+        // El avance nunca detiene la carga, si no se puede escribir se pierde
+        if let (Ok(mut out), Ok(line)) = (self.out.lock(), serde_json::to_string(&line)) {
+            let _ = writeln!(out, "{}", line).and_then(|_| out.flush());
+        }
+    }
+}
+impl<W: Write + Debug + Send> ProgressObserver for JsonProgress<W> {
+    fn start(&self, stage: ProgressStage, name: &str, total: Option<u64>) {
+        self.emit(ProgressEventKind::Start, stage, name, 0, total);
+    }
+    fn advance(&self, stage: ProgressStage, name: &str, n: u64) {
+        self.emit(ProgressEventKind::Advance, stage, name, n, None);
+    }
+    fn finish(&self, stage: ProgressStage, name: &str) {
+        self.emit(ProgressEventKind::Finish, stage, name, 0, None);
+    }
+}
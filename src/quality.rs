@@ -0,0 +1,313 @@
+use crate::pl_sql::SqliteSchema;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::path::Path;
+pub const METRIC_ROWS: &str = "REGISTROS";
+pub const METRIC_NULLS: &str = "NULOS";
+pub const METRIC_OUT_OF_CATALOG: &str = "FUERA_DE_CATALOGO";
+pub const METRIC_IMPOSSIBLE_AGE: &str = "EDAD_IMPOSIBLE";
+pub const METRIC_DEATH_BEFORE_ADMISSION: &str = "DEFUNCION_ANTES_DE_INGRESO";
+pub const METRIC_DUPLICATED_ID: &str = "ID_REGISTRO_DUPLICADO";
+// (fecha de publicación, ID_REGISTRO) de un registro
+pub type RecordId = (Option<String>, Option<String>);
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QualityMetric {
+    pub archivo: String,
+    pub fecha_actualizacion: Option<String>,
+    pub metrica: String,
+    pub columna: Option<String>,
+    pub valor: u64,
+    pub registros: u64,
+    pub tasa: f64,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub metrics: Vec<QualityMetric>,
+}
+impl QualityReport {
+    pub fn extend(&mut self, other: QualityReport) {
+        self.metrics.extend(other.metrics);
+    }
+    // Suma los conteos de otro reporte, p. ej. el de otro lote del mismo
+    // archivo, y vuelve a calcular las tasas
+    pub fn merge(&mut self, other: QualityReport) {
+        for metric in other.metrics {
+            let same = self.metrics.iter_mut().find(|m| {
+                m.archivo == metric.archivo
+                    && m.fecha_actualizacion == metric.fecha_actualizacion
+                    && m.metrica == metric.metrica
+                    && m.columna == metric.columna
+            });
+            let Some(m) = same else {
+                self.metrics.push(metric);
+                continue;
+            };
+            m.valor += metric.valor;
+            m.registros += metric.registros;
+            m.tasa = if m.registros == 0 {
+                0.0
+            } else {
+                m.valor as f64 / m.registros as f64
+            };
+        }
+    }
+    // Total de una métrica en todos los archivos y fechas
+    pub fn total(&self, metrica: &str, columna: Option<&str>) -> u64 {
+        self.metrics
+            .iter()
+            .filter(|m| m.metrica == metrica && m.columna.as_deref() == columna)
+            .map(|m| m.valor)
+            .sum()
+    }
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), color_eyre::eyre::Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+    // Formato largo para la tabla QUALITY_REPORT
+    pub fn to_frame(&self) -> PolarsResult<DataFrame> {
+        df!(
+            "ARCHIVO" => self.metrics.iter().map(|m| m.archivo.clone()).collect::<Vec<_>>(),
+            "FECHA_ACTUALIZACION" => self.metrics.iter().map(|m| m.fecha_actualizacion.clone()).collect::<Vec<_>>(),
+            "METRICA" => self.metrics.iter().map(|m| m.metrica.clone()).collect::<Vec<_>>(),
+            "COLUMNA" => self.metrics.iter().map(|m| m.columna.clone()).collect::<Vec<_>>(),
+            "VALOR" => self.metrics.iter().map(|m| m.valor).collect::<Vec<_>>(),
+            "REGISTROS" => self.metrics.iter().map(|m| m.registros).collect::<Vec<_>>(),
+            "TASA" => self.metrics.iter().map(|m| m.tasa).collect::<Vec<_>>(),
+        )
+    }
+}
+impl std::fmt::Display for QualityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for m in self.metrics.iter().filter(|m| m.valor > 0) {
+            if m.metrica == METRIC_ROWS {
+                continue;
+            }
+            writeln!(
+                f,
+                "{} {} {}{}: {} ({:.2}%)",
+                m.archivo,
+                m.fecha_actualizacion.as_deref().unwrap_or("-"),
+                m.metrica,
+                m.columna
+                    .as_ref()
+                    .map(|c| format!(" {}", c))
+                    .unwrap_or_default(),
+                m.valor,
+                m.tasa * 100.0
+            )?;
+        }
+        Ok(())
+    }
+}
+// Validaciones que se corren sobre cada archivo ya limpio, agrupadas por la
+// fecha de publicación (`FECHA_ACTUALIZACION`)
+#[derive(Debug, Clone)]
+pub struct QualityCheck {
+    catalogs: HashMap<String, Vec<u64>>,
+    id_column: String,
+    release_column: String,
+    max_age: i64,
+}
+impl Default for QualityCheck {
+    fn default() -> Self {
+        Self {
+            catalogs: HashMap::new(),
+            id_column: "ID_REGISTRO".into(),
+            release_column: "FECHA_ACTUALIZACION".into(),
+            max_age: 120,
+        }
+    }
+}
+impl QualityCheck {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Las claves válidas de cada columna salen del catálogo al que apunta en `schema`
+    pub fn with_catalogs(
+        mut self,
+        tables: &HashMap<String, DataFrame>,
+        schema: &SqliteSchema,
+    ) -> Result<Self, color_eyre::eyre::Error> {
+        for (column, options) in schema.iter_fields() {
+            let Some((table, parent)) = options.foreign_key_ref() else {
+                continue;
+            };
+            let Some(catalog) = tables.get(table) else {
+                continue;
+            };
+            let codes = catalog.column(parent)?.cast(&DataType::UInt64)?;
+            self.catalogs
+                .insert(column.clone(), codes.u64()?.into_iter().flatten().collect());
+        }
+        Ok(self)
+    }
+    pub fn with_max_age(mut self, max_age: i64) -> Self {
+        self.max_age = max_age;
+        self
+    }
+    pub fn check(
+        &self,
+        lf: LazyFrame,
+        archivo: &str,
+    ) -> Result<QualityReport, color_eyre::eyre::Error> {
+        let mut lf = lf;
+        let schema = lf.collect_schema()?;
+        let release = col(self.release_column.as_str())
+            .cast(DataType::String)
+            .alias("__release");
+        let mut aggs = vec![len().alias(METRIC_ROWS)];
+        for column in schema.iter_names() {
+            aggs.push(
+                col(column.as_str())
+                    .null_count()
+                    .alias(format!("{}|{}", METRIC_NULLS, column)),
+            );
+        }
+        for (column, codes) in &self.catalogs {
+            let Some(dtype) = schema.get(column) else {
+                continue;
+            };
+            let codes = Series::new("codes".into(), codes).cast(dtype)?;
+            aggs.push(
+                col(column.as_str())
+                    .is_not_null()
+                    .and(col(column.as_str()).is_in(lit(codes)).not())
+                    .sum()
+                    .alias(format!("{}|{}", METRIC_OUT_OF_CATALOG, column)),
+            );
+        }
+        if schema.contains("EDAD") {
+            // Con un diccionario que no la marca como numérica la edad llega
+            // como texto, lo que no es un número queda fuera de la cuenta
+            let edad = col("EDAD").cast(DataType::Int64);
+            aggs.push(
+                edad.clone()
+                    .lt(lit(0))
+                    .or(edad.gt(lit(self.max_age)))
+                    .sum()
+                    .alias(format!("{}|EDAD", METRIC_IMPOSSIBLE_AGE)),
+            );
+        }
+        if schema.contains("FECHA_DEF") && schema.contains("FECHA_INGRESO") {
+            // Las fechas ISO se pueden comparar como texto, el centinela 9999-99-99 nunca es menor
+            aggs.push(
+                col("FECHA_DEF")
+                    .cast(DataType::String)
+                    .lt(col("FECHA_INGRESO").cast(DataType::String))
+                    .sum()
+                    .alias(format!("{}|FECHA_DEF", METRIC_DEATH_BEFORE_ADMISSION)),
+            );
+        }
+        let counts = lf
+            .clone()
+            .with_column(release.clone())
+            .group_by([col("__release")])
+            .agg(aggs)
+            .sort(["__release"], Default::default())
+            .collect()?;
+        let mut duplicated: HashMap<Option<String>, u64> = HashMap::new();
+        if schema.contains(&self.id_column) {
+            let dup = lf
+                .with_column(release)
+                .group_by([col("__release"), col(self.id_column.as_str())])
+                .agg([len().alias("n")])
+                .filter(col("n").gt(lit(1)))
+                .group_by([col("__release")])
+                .agg([(col("n") - lit(1)).sum().cast(DataType::UInt64).alias("n")])
+                .collect()?;
+            for (release, n) in dup
+                .column("__release")?
+                .str()?
+                .into_iter()
+                .zip(dup.column("n")?.u64()?)
+            {
+                duplicated.insert(release.map(|r| r.to_string()), n.unwrap_or_default());
+            }
+        }
+        let mut report = QualityReport::default();
+        let releases = counts.column("__release")?.str()?.clone();
+        let rows = counts.column(METRIC_ROWS)?.cast(&DataType::UInt64)?;
+        let rows = rows.u64()?;
+        for i in 0..counts.height() {
+            let fecha_actualizacion = releases.get(i).map(|r| r.to_string());
+            let registros = rows.get(i).unwrap_or_default();
+            let mut push = |metrica: &str, columna: Option<&str>, valor: u64| {
+                report.metrics.push(QualityMetric {
+                    archivo: archivo.to_string(),
+                    fecha_actualizacion: fecha_actualizacion.clone(),
+                    metrica: metrica.to_string(),
+                    columna: columna.map(|c| c.to_string()),
+                    valor,
+                    registros,
+                    tasa: if registros == 0 {
+                        0.0
+                    } else {
+                        valor as f64 / registros as f64
+                    },
+                });
+            };
+            push(METRIC_ROWS, None, registros);
+            for column in counts.get_columns() {
+                let Some((metrica, columna)) = column.name().split_once('|') else {
+                    continue;
+                };
+                let valor = column.get(i)?.extract::<u64>().unwrap_or_default();
+                push(metrica, Some(columna), valor);
+            }
+            push(
+                METRIC_DUPLICATED_ID,
+                Some(self.id_column.as_str()),
+                duplicated
+                    .get(&fecha_actualizacion)
+                    .copied()
+                    .unwrap_or_default(),
+            );
+        }
+        Ok(report)
+    }
+    // Pares (fecha de publicación, ID_REGISTRO) distintos de `lf`. `check` solo
+    // ve los repetidos de un lote, con estos se cuentan los de lotes anteriores
+    pub fn ids(&self, lf: LazyFrame) -> Result<Vec<RecordId>, color_eyre::eyre::Error> {
+        let mut lf = lf;
+        if !lf.collect_schema()?.contains(&self.id_column) {
+            return Ok(Vec::new());
+        }
+        let ids = lf
+            .select([
+                col(self.release_column.as_str()).cast(DataType::String),
+                col(self.id_column.as_str()).cast(DataType::String),
+            ])
+            .unique(None, UniqueKeepStrategy::Any)
+            .collect()?;
+        let releases = ids.column(&self.release_column)?.str()?;
+        let keys = ids.column(&self.id_column)?.str()?;
+        Ok(releases
+            .into_iter()
+            .zip(keys)
+            .map(|(release, key)| (release.map(|r| r.to_string()), key.map(|k| k.to_string())))
+            .collect())
+    }
+    // ID_REGISTRO repetidos de más por fecha de publicación, sin registros para
+    // que al juntarlo con `merge` no cambie el total del archivo
+    pub fn duplicated(
+        &self,
+        archivo: &str,
+        repetidos: &BTreeMap<Option<String>, u64>,
+    ) -> QualityReport {
+        let metrics = repetidos
+            .iter()
+            .map(|(release, valor)| QualityMetric {
+                archivo: archivo.to_string(),
+                fecha_actualizacion: release.clone(),
+                metrica: METRIC_DUPLICATED_ID.to_string(),
+                columna: Some(self.id_column.clone()),
+                valor: *valor,
+                registros: 0,
+                tasa: 0.0,
+            })
+            .collect();
+        QualityReport { metrics }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::pl_sql::{sql_col, SqlPredicate, SqlReader};
+use polars::prelude::*;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
+pub const REJECTED_TABLE: &str = "COVID19MEXICO_RECHAZADOS";
+// Máximo de parámetros por consulta al buscar claves que ya existen
+pub const KEYS_PER_QUERY: usize = 10_000;
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RejectReason {
+    // El registro no trae clave
+    NullKey,
+    // La clave se repite dentro del mismo lote, se conserva la primera
+    DuplicateInBatch,
+    // La clave ya está en la tabla con una publicación más reciente
+    DuplicateInTable,
+    // Un valor no está en su catálogo, p. ej. un MUNICIPIO_RES sin municipio
+    OutOfCatalog,
+}
+impl RejectReason {
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::NullKey => "CLAVE_NULA",
+            RejectReason::DuplicateInBatch => "DUPLICADO_LOTE",
+            RejectReason::DuplicateInTable => "DUPLICADO_TABLA",
+            RejectReason::OutOfCatalog => "FUERA_DE_CATALOGO",
+        }
+    }
+}
+impl std::fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+// Separa los registros que no se pueden insertar para guardarlos en
+// `COVID19MEXICO_RECHAZADOS` en lugar de perderlos con `INSERT OR IGNORE`
+#[derive(Debug, Clone)]
+pub struct Quarantine {
+    key_column: String,
+    // Valor calculado de cada fila y las claves válidas de su catálogo
+    catalogs: Vec<(Expr, Arc<HashSet<String>>)>,
+    counts: BTreeMap<RejectReason, u64>,
+}
+impl Default for Quarantine {
+    fn default() -> Self {
+        Self::new("ID_REGISTRO")
+    }
+}
+impl Quarantine {
+    pub fn new<T: Into<String>>(key_column: T) -> Self {
+        Self {
+            key_column: key_column.into(),
+            catalogs: Vec::new(),
+            counts: BTreeMap::new(),
+        }
+    }
+    // Rechaza las filas en las que `expr` no es nulo y no está en `keys`. Se
+    // evalúa sobre las filas que llegan a `split`, así una columna que se arma
+    // al limpiar se revisa sin limpiar los rechazados
+    pub fn with_catalog(mut self, expr: Expr, keys: Arc<HashSet<String>>) -> Self {
+        self.catalogs.push((expr, keys));
+        self
+    }
+    pub fn key_column(&self) -> &str {
+        &self.key_column
+    }
+    // Regresa (aceptados, rechazados), los rechazados llevan los valores
+    // originales como texto más `ARCHIVO`, `OFFSET_LOTE` y `MOTIVO`
+    pub fn split(
+        &mut self,
+        df: DataFrame,
+        existing: &HashSet<String>,
+        archivo: &str,
+        offset: i64,
+    ) -> Result<(DataFrame, DataFrame), color_eyre::eyre::Error> {
+        let (mask, rejected) = self.reject(&df, existing, archivo, offset)?;
+        Ok((df.filter(&mask)?, rejected))
+    }
+    // Como `split` pero con las mismas filas ya limpias en `clean`: se decide y
+    // se guarda con `raw` y se regresan los aceptados de `clean`
+    pub fn split_clean(
+        &mut self,
+        raw: DataFrame,
+        clean: DataFrame,
+        existing: &HashSet<String>,
+        archivo: &str,
+        offset: i64,
+    ) -> Result<(DataFrame, DataFrame), color_eyre::eyre::Error> {
+        let (mask, rejected) = self.reject(&raw, existing, archivo, offset)?;
+        Ok((clean.filter(&mask)?, rejected))
+    }
+    fn reject(
+        &mut self,
+        df: &DataFrame,
+        existing: &HashSet<String>,
+        archivo: &str,
+        offset: i64,
+    ) -> Result<(BooleanChunked, DataFrame), color_eyre::eyre::Error> {
+        let keys = df.column(&self.key_column)?.cast(&DataType::String)?;
+        let mut outside = vec![false; df.height()];
+        for (expr, codes) in &self.catalogs {
+            let values = df
+                .clone()
+                .lazy()
+                .select([expr.clone().cast(DataType::String).alias("__valor")])
+                .collect()?;
+            for (i, value) in values.column("__valor")?.str()?.into_iter().enumerate() {
+                if value.is_some_and(|v| !codes.contains(v)) {
+                    outside[i] = true;
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        let reasons = keys
+            .str()?
+            .into_iter()
+            .zip(outside)
+            .map(|(key, outside)| match key {
+                None => Some(RejectReason::NullKey),
+                Some(key) if existing.contains(key) => Some(RejectReason::DuplicateInTable),
+                Some(_) if outside => Some(RejectReason::OutOfCatalog),
+                Some(key) if !seen.insert(key) => Some(RejectReason::DuplicateInBatch),
+                Some(_) => None,
+            })
+            .collect::<Vec<_>>();
+        let mask = reasons
+            .iter()
+            .map(|r| r.is_none())
+            .collect::<BooleanChunked>();
+        let rejected = df.filter(&!&mask)?;
+        let codes = reasons
+            .iter()
+            .flatten()
+            .map(|r| {
+                *self.counts.entry(*r).or_default() += 1;
+                r.code()
+            })
+            .collect::<Vec<_>>();
+        let n = codes.len();
+        let mut columns = rejected
+            .get_columns()
+            .iter()
+            .map(|c| c.cast(&DataType::String))
+            .collect::<PolarsResult<Vec<_>>>()?;
+        columns.push(Column::new("ARCHIVO".into(), vec![archivo; n]));
+        columns.push(Column::new("OFFSET_LOTE".into(), vec![offset; n]));
+        columns.push(Column::new("MOTIVO".into(), codes));
+        Ok((mask, DataFrame::new(columns)?))
+    }
+    // Suma los conteos de otra cuarentena, p. ej. la de otro hilo
+    pub fn merge(&mut self, other: &Quarantine) {
+        for (reason, n) in &other.counts {
+            *self.counts.entry(*reason).or_default() += n;
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+    pub fn n_rows(&self) -> u64 {
+        self.counts.values().sum()
+    }
+    pub fn count(&self, reason: RejectReason) -> u64 {
+        self.counts.get(&reason).copied().unwrap_or_default()
+    }
+}
+impl std::fmt::Display for Quarantine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (reason, n) in &self.counts {
+            writeln!(f, "{}: {}", reason, n)?;
+        }
+        Ok(())
+    }
+}
+// Claves de `keys` que ya están en `table` y cumplen `filter`, si la tabla no
+// existe no hay ninguna
+pub fn existing_keys(
+    reader: &SqlReader,
+    table: &str,
+    key_column: &str,
+    keys: &Column,
+    filter: Option<SqlPredicate>,
+) -> Result<HashSet<String>, color_eyre::eyre::Error> {
+    let mut existing = HashSet::new();
+    if !reader.table_exists(table)? {
+        return Ok(existing);
+    }
+    let keys = keys.cast(&DataType::String)?;
+    let keys = keys.str()?.into_iter().flatten().collect::<Vec<_>>();
+    for chunk in keys.chunks(KEYS_PER_QUERY) {
+        let mut query = reader
+            .clone()
+            .with_query(None::<String>)
+            .with_table(Some(table))
+            .select(vec![key_column])
+            .filter(sql_col(key_column).is_in(chunk.to_vec()));
+        if let Some(filter) = filter.clone() {
+            query = query.filter(filter);
+        }
+        let df = query.finish()?;
+        let found = df.column(key_column)?.cast(&DataType::String)?;
+        existing.extend(found.str()?.into_iter().flatten().map(|k| k.to_string()));
+    }
+    Ok(existing)
+}
@@ -0,0 +1,137 @@
+use crate::pl_sql::SqliteSchema;
+use indexmap::IndexMap;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+// Descripciones con las que los catálogos marcan los valores que no son un dato
+pub const SENTINEL_LABELS: [&str; 3] = ["NO APLICA", "SE IGNORA", "NO ESPECIFICADO"];
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SentinelAction {
+    // Se dejan los códigos tal cual vienen
+    #[default]
+    Keep,
+    // Los códigos se cambian por NULL
+    Null,
+    // Los códigos se mueven a la columna `{columna}_CENTINELA` y la columna queda en NULL
+    Flag,
+}
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SentinelRules {
+    default_action: SentinelAction,
+    codes: IndexMap<String, Vec<u64>>,
+    actions: HashMap<String, SentinelAction>,
+}
+impl SentinelRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // Toma los códigos centinela del catálogo al que apunta cada columna en `schema`
+    pub fn from_catalogs(
+        tables: &HashMap<String, DataFrame>,
+        schema: &SqliteSchema,
+    ) -> Result<Self, color_eyre::eyre::Error> {
+        let mut rules = Self::new();
+        for (column, options) in schema.iter_fields() {
+            let Some((table, _)) = options.foreign_key_ref() else {
+                continue;
+            };
+            let Some(catalog) = tables.get(table) else {
+                continue;
+            };
+            let codes = sentinel_codes(catalog)?;
+            if !codes.is_empty() {
+                rules = rules.with_codes(column, codes);
+            }
+        }
+        Ok(rules)
+    }
+    // Acción de las columnas que no tienen una propia
+    pub fn with_default_action(mut self, action: SentinelAction) -> Self {
+        self.default_action = action;
+        self
+    }
+    pub fn with_codes<T: Into<String>>(mut self, column: T, codes: Vec<u64>) -> Self {
+        self.codes.insert(column.into(), codes);
+        self
+    }
+    pub fn with_action<T: Into<String>>(mut self, column: T, action: SentinelAction) -> Self {
+        self.actions.insert(column.into(), action);
+        self
+    }
+    pub fn codes(&self, column: &str) -> Option<&Vec<u64>> {
+        self.codes.get(column)
+    }
+    pub fn action(&self, column: &str) -> SentinelAction {
+        self.actions
+            .get(column)
+            .copied()
+            .unwrap_or(self.default_action)
+    }
+    pub fn iter_columns(&self) -> impl Iterator<Item = &String> {
+        self.codes.keys()
+    }
+    // Aplica las reglas a las columnas de `lf` que existan en el esquema
+    pub fn apply(&self, lf: LazyFrame) -> PolarsResult<LazyFrame> {
+        let mut lf = lf;
+        let schema = lf.collect_schema()?;
+        let mut flags = Vec::new();
+        let mut values = Vec::new();
+        for (column, codes) in &self.codes {
+            let Some(dtype) = schema.get(column) else {
+                continue;
+            };
+            let action = self.action(column);
+            if action == SentinelAction::Keep || codes.is_empty() {
+                continue;
+            }
+            let codes = Series::new("codes".into(), codes).cast(dtype)?;
+            let is_sentinel = col(column.as_str()).is_in(lit(codes));
+            if action == SentinelAction::Flag {
+                flags.push(
+                    when(is_sentinel.clone())
+                        .then(col(column.as_str()))
+                        .otherwise(lit(NULL))
+                        .alias(format!("{}_CENTINELA", column)),
+                );
+            }
+            values.push(
+                when(is_sentinel)
+                    .then(lit(NULL))
+                    .otherwise(col(column.as_str()))
+                    .alias(column.as_str()),
+            );
+        }
+        if !flags.is_empty() {
+            lf = lf.with_columns(flags);
+        }
+        Ok(lf.with_columns(values))
+    }
+}
+// Claves del catálogo cuya descripción es una de `SENTINEL_LABELS`, la primera
+// columna es la clave y la descripción es `DESCRIPCIÓN` o la segunda columna
+pub fn sentinel_codes(catalog: &DataFrame) -> Result<Vec<u64>, color_eyre::eyre::Error> {
+    if catalog.width() < 2 {
+        return Ok(Vec::new());
+    }
+    let keys = catalog.get_columns()[0].cast(&DataType::UInt64)?;
+    let labels = match catalog.column("DESCRIPCIÓN") {
+        Ok(labels) => labels,
+        Err(_) => &catalog.get_columns()[1],
+    }
+    .cast(&DataType::String)?;
+    let codes = keys
+        .u64()?
+        .into_iter()
+        .zip(labels.str()?)
+        .filter_map(|(key, label)| match (key, label) {
+            (Some(key), Some(label))
+                if SENTINEL_LABELS.contains(&label.trim().to_uppercase().as_str()) =>
+            {
+                Some(key)
+            }
+            _ => None,
+        })
+        .collect();
+    Ok(codes)
+}
@@ -0,0 +1,268 @@
+use chrono::Utc;
+use polars::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::path::Path;
+use std::time::{Duration, Instant};
+pub const RUNS_TABLE: &str = "ETL_RUNS";
+pub const RUN_FILES_TABLE: &str = "ETL_RUNS_ARCHIVOS";
+// Registros de un archivo en cada paso de la carga
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct FileSummary {
+    pub archivo: String,
+    pub fecha_actualizacion: Option<String>,
+    // Filas del CSV
+    pub leidos: u64,
+    // Filas que quedan después de la cuarentena y la limpieza
+    pub limpios: u64,
+    // Filas que van a COVID19MEXICO_RECHAZADOS
+    pub rechazados: u64,
+    // Filas que la base reporta como escritas, con upsert incluye las actualizadas
+    pub insertados: u64,
+    pub segundos: f64,
+}
+impl FileSummary {
+    pub fn new<T: Into<String>>(archivo: T) -> Self {
+        Self {
+            archivo: archivo.into(),
+            ..Default::default()
+        }
+    }
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(self.leidos, self.segundos)
+    }
+}
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StageTiming {
+    pub etapa: String,
+    pub segundos: f64,
+}
+// Resumen de una corrida de `load`; se imprime como tabla, se guarda en JSON y
+// se agrega a ETL_RUNS para seguir la tendencia entre corridas
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RunSummary {
+    // Fecha de inicio en RFC 3339, identifica la corrida en ETL_RUNS_ARCHIVOS
+    pub id_corrida: String,
+    pub fin: String,
+    pub segundos: f64,
+    pub archivos: Vec<FileSummary>,
+    // Archivos que ya estaban cargados
+    pub omitidos: Vec<String>,
+    pub etapas: Vec<StageTiming>,
+    // Máximo de memoria residente del proceso (VmHWM), solo en Linux
+    pub memoria_pico_bytes: Option<u64>,
+    // Tamaño estimado del lote más grande en memoria
+    pub lote_max_bytes: u64,
+    pub db_bytes_antes: u64,
+    pub db_bytes_despues: u64,
+    // Se detuvo con Ctrl-C, la siguiente corrida reanuda desde la bitácora
+    pub cancelada: bool,
+}
+impl RunSummary {
+    pub fn leidos(&self) -> u64 {
+        self.archivos.iter().map(|f| f.leidos).sum()
+    }
+    pub fn limpios(&self) -> u64 {
+        self.archivos.iter().map(|f| f.limpios).sum()
+    }
+    pub fn rechazados(&self) -> u64 {
+        self.archivos.iter().map(|f| f.rechazados).sum()
+    }
+    pub fn insertados(&self) -> u64 {
+        self.archivos.iter().map(|f| f.insertados).sum()
+    }
+    // Registros leídos por segundo de toda la corrida
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(self.leidos(), self.segundos)
+    }
+    pub fn stage(&self, etapa: &str) -> Option<f64> {
+        self.etapas
+            .iter()
+            .find(|s| s.etapa == etapa)
+            .map(|s| s.segundos)
+    }
+    pub fn write_json<P: AsRef<Path>>(&self, path: P) -> Result<(), color_eyre::eyre::Error> {
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+    // Una fila por corrida para ETL_RUNS, las etapas van como JSON
+    pub fn to_frame(&self) -> Result<DataFrame, color_eyre::eyre::Error> {
+        Ok(df!(
+            "ID_CORRIDA" => [self.id_corrida.clone()],
+            "FIN" => [self.fin.clone()],
+            "SEGUNDOS" => [self.segundos],
+            "ARCHIVOS" => [self.archivos.len() as u64],
+            "OMITIDOS" => [self.omitidos.len() as u64],
+            "LEIDOS" => [self.leidos()],
+            "LIMPIOS" => [self.limpios()],
+            "RECHAZADOS" => [self.rechazados()],
+            "INSERTADOS" => [self.insertados()],
+            "REGISTROS_POR_SEGUNDO" => [self.rows_per_second()],
+            "MEMORIA_PICO_BYTES" => [self.memoria_pico_bytes],
+            "LOTE_MAX_BYTES" => [self.lote_max_bytes],
+            "DB_BYTES_ANTES" => [self.db_bytes_antes],
+            "DB_BYTES_DESPUES" => [self.db_bytes_despues],
+            "ETAPAS" => [serde_json::to_string(&self.etapas)?],
+            "CANCELADA" => [self.cancelada],
+        )?)
+    }
+    // Una fila por archivo cargado para ETL_RUNS_ARCHIVOS
+    pub fn files_frame(&self) -> PolarsResult<DataFrame> {
+        let files = &self.archivos;
+        df!(
+            "ID_CORRIDA" => files.iter().map(|_| self.id_corrida.clone()).collect::<Vec<_>>(),
+            "ARCHIVO" => files.iter().map(|f| f.archivo.clone()).collect::<Vec<_>>(),
+            "FECHA_ACTUALIZACION" => files.iter().map(|f| f.fecha_actualizacion.clone()).collect::<Vec<_>>(),
+            "LEIDOS" => files.iter().map(|f| f.leidos).collect::<Vec<_>>(),
+            "LIMPIOS" => files.iter().map(|f| f.limpios).collect::<Vec<_>>(),
+            "RECHAZADOS" => files.iter().map(|f| f.rechazados).collect::<Vec<_>>(),
+            "INSERTADOS" => files.iter().map(|f| f.insertados).collect::<Vec<_>>(),
+            "SEGUNDOS" => files.iter().map(|f| f.segundos).collect::<Vec<_>>(),
+        )
+    }
+}
+impl std::fmt::Display for RunSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<32} {:>12} {:>12} {:>12} {:>12} {:>10} {:>12}",
+            "archivo", "leídos", "limpios", "rechazados", "insertados", "segundos", "registros/s"
+        )?;
+        let total = FileSummary {
+            archivo: "TOTAL".into(),
+            leidos: self.leidos(),
+            limpios: self.limpios(),
+            rechazados: self.rechazados(),
+            insertados: self.insertados(),
+            segundos: self.segundos,
+            ..Default::default()
+        };
+        for file in self.archivos.iter().chain([&total]) {
+            writeln!(
+                f,
+                "{:<32} {:>12} {:>12} {:>12} {:>12} {:>10.1} {:>12.0}",
+                file.archivo,
+                file.leidos,
+                file.limpios,
+                file.rechazados,
+                file.insertados,
+                file.segundos,
+                file.rows_per_second()
+            )?;
+        }
+        for archivo in &self.omitidos {
+            writeln!(f, "{:<32} omitido", archivo)?;
+        }
+        writeln!(f)?;
+        for stage in &self.etapas {
+            writeln!(f, "{:<32} {:>10.1} s", stage.etapa, stage.segundos)?;
+        }
+        writeln!(f)?;
+        if let Some(peak) = self.memoria_pico_bytes {
+            writeln!(f, "memoria pico: {}", mib(peak))?;
+        }
+        writeln!(f, "lote más grande: {}", mib(self.lote_max_bytes))?;
+        writeln!(
+            f,
+            "base de datos: {} -> {}",
+            mib(self.db_bytes_antes),
+            mib(self.db_bytes_despues)
+        )?;
+        if self.cancelada {
+            writeln!(
+                f,
+                "corrida cancelada, la siguiente carga continúa donde se quedó"
+            )?;
+        }
+        Ok(())
+    }
+}
+// Lleva el tiempo de la corrida y de cada etapa mientras se carga
+#[derive(Debug)]
+pub struct RunRecorder {
+    started: Instant,
+    summary: RunSummary,
+}
+impl RunRecorder {
+    pub fn start<P: AsRef<Path>>(db_path: P) -> Self {
+        Self {
+            started: Instant::now(),
+            summary: RunSummary {
+                id_corrida: Utc::now().to_rfc3339(),
+                db_bytes_antes: db_size(db_path),
+                ..Default::default()
+            },
+        }
+    }
+    // Suma el tiempo a la etapa, se puede llamar varias veces por etapa
+    pub fn add_stage(&mut self, etapa: &str, elapsed: Duration) {
+        let etapas = &mut self.summary.etapas;
+        match etapas.iter_mut().find(|s| s.etapa == etapa) {
+            Some(stage) => stage.segundos += elapsed.as_secs_f64(),
+            None => etapas.push(StageTiming {
+                etapa: etapa.to_string(),
+                segundos: elapsed.as_secs_f64(),
+            }),
+        }
+    }
+    // Corre `f` y suma su duración a la etapa
+    pub fn time<T>(&mut self, etapa: &str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let out = f();
+        self.add_stage(etapa, start.elapsed());
+        out
+    }
+    pub fn observe_batch(&mut self, df: &DataFrame) {
+        self.summary.lote_max_bytes = self.summary.lote_max_bytes.max(df.estimated_size() as u64);
+    }
+    pub fn add_file(&mut self, file: FileSummary) {
+        self.summary.archivos.push(file);
+    }
+    pub fn cancel(&mut self) {
+        self.summary.cancelada = true;
+    }
+    pub fn skip_file<T: Into<String>>(&mut self, archivo: T) {
+        self.summary.omitidos.push(archivo.into());
+    }
+    pub fn finish<P: AsRef<Path>>(mut self, db_path: P) -> RunSummary {
+        self.summary.fin = Utc::now().to_rfc3339();
+        self.summary.segundos = self.started.elapsed().as_secs_f64();
+        self.summary.memoria_pico_bytes = peak_memory();
+        self.summary.db_bytes_despues = db_size(db_path);
+        self.summary
+    }
+}
+// Tamaño del archivo de SQLite junto con su WAL, 0 si no existe
+pub fn db_size<P: AsRef<Path>>(db_path: P) -> u64 {
+    let db_path = db_path.as_ref();
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    [db_path.as_os_str().to_owned(), wal]
+        .iter()
+        .filter_map(|p| fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+// VmHWM de /proc/self/status en bytes
+pub fn peak_memory() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    let kb: u64 = line
+        .trim_start_matches("VmHWM:")
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
+fn per_second(rows: u64, segundos: f64) -> f64 {
+    if segundos > 0.0 {
+        rows as f64 / segundos
+    } else {
+        0.0
+    }
+}
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0))
+}
@@ -0,0 +1,702 @@
+use chrono::{Datelike, Duration, NaiveDate};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+// Nombres con los que viene el diccionario de datos publicado el 2024-07-08
+pub const DESCRIPTORS_FILE: &str = "240708 Descriptores_.xlsx";
+pub const CATALOGS_FILE: &str = "240708 Catalogos.xlsx";
+// Columnas de los históricos COVID19MEXICO en el orden del CSV
+pub const COVID_COLUMNS: [&str; 40] = [
+    "FECHA_ACTUALIZACION",
+    "ID_REGISTRO",
+    "ORIGEN",
+    "SECTOR",
+    "ENTIDAD_UM",
+    "SEXO",
+    "ENTIDAD_NAC",
+    "ENTIDAD_RES",
+    "MUNICIPIO_RES",
+    "TIPO_PACIENTE",
+    "FECHA_INGRESO",
+    "FECHA_SINTOMAS",
+    "FECHA_DEF",
+    "INTUBADO",
+    "NEUMONIA",
+    "EDAD",
+    "NACIONALIDAD",
+    "EMBARAZO",
+    "HABLA_LENGUA_INDIG",
+    "INDIGENA",
+    "DIABETES",
+    "EPOC",
+    "ASMA",
+    "INMUSUPR",
+    "HIPERTENSION",
+    "OTRA_COM",
+    "CARDIOVASCULAR",
+    "OBESIDAD",
+    "RENAL_CRONICA",
+    "TABAQUISMO",
+    "OTRO_CASO",
+    "TOMA_MUESTRA_LAB",
+    "RESULTADO_LAB",
+    "TOMA_MUESTRA_ANTIGENO",
+    "RESULTADO_ANTIGENO",
+    "CLASIFICACION_FINAL",
+    "MIGRANTE",
+    "PAIS_NACIONALIDAD",
+    "PAIS_ORIGEN",
+    "UCI",
+];
+const ENTIDADES: [(&str, &str); 32] = [
+    ("AGUASCALIENTES", "AS"),
+    ("BAJA CALIFORNIA", "BC"),
+    ("BAJA CALIFORNIA SUR", "BS"),
+    ("CAMPECHE", "CC"),
+    ("COAHUILA DE ZARAGOZA", "CL"),
+    ("COLIMA", "CM"),
+    ("CHIAPAS", "CS"),
+    ("CHIHUAHUA", "CH"),
+    ("CIUDAD DE MÉXICO", "DF"),
+    ("DURANGO", "DG"),
+    ("GUANAJUATO", "GT"),
+    ("GUERRERO", "GR"),
+    ("HIDALGO", "HG"),
+    ("JALISCO", "JC"),
+    ("MÉXICO", "MC"),
+    ("MICHOACÁN DE OCAMPO", "MN"),
+    ("MORELOS", "MS"),
+    ("NAYARIT", "NT"),
+    ("NUEVO LEÓN", "NL"),
+    ("OAXACA", "OC"),
+    ("PUEBLA", "PL"),
+    ("QUERÉTARO", "QO"),
+    ("QUINTANA ROO", "QR"),
+    ("SAN LUIS POTOSÍ", "SP"),
+    ("SINALOA", "SL"),
+    ("SONORA", "SR"),
+    ("TABASCO", "TC"),
+    ("TAMAULIPAS", "TS"),
+    ("TLAXCALA", "TL"),
+    ("VERACRUZ DE IGNACIO DE LA LLAVE", "VZ"),
+    ("YUCATÁN", "YN"),
+    ("ZACATECAS", "ZS"),
+];
+// Países como vienen escritos en PAIS_NACIONALIDAD y PAIS_ORIGEN
+const PAISES: [&str; 7] = [
+    "Estados Unidos de América",
+    "Guatemala",
+    "Honduras",
+    "Venezuela",
+    "Colombia",
+    "Cuba",
+    "España",
+];
+const SECTORES: [&str; 13] = [
+    "CRUZ ROJA",
+    "DIF",
+    "ESTATAL",
+    "IMSS",
+    "IMSS-BIENESTAR",
+    "ISSSTE",
+    "MUNICIPAL",
+    "PEMEX",
+    "PRIVADA",
+    "SEDENA",
+    "SEMAR",
+    "SSA",
+    "UNIVERSITARIO",
+];
+const SI_NO: [&str; 9] = [
+    "INTUBADO",
+    "NEUMONIA",
+    "EMBARAZO",
+    "HABLA_LENGUA_INDIG",
+    "INDIGENA",
+    "OTRO_CASO",
+    "TOMA_MUESTRA_LAB",
+    "TOMA_MUESTRA_ANTIGENO",
+    "MIGRANTE",
+];
+const COMORBILIDADES: [&str; 10] = [
+    "DIABETES",
+    "EPOC",
+    "ASMA",
+    "INMUSUPR",
+    "HIPERTENSION",
+    "OTRA_COM",
+    "CARDIOVASCULAR",
+    "OBESIDAD",
+    "RENAL_CRONICA",
+    "TABAQUISMO",
+];
+// Municipios que tiene cada entidad en el catálogo sintético, más el 999
+pub fn municipios(entidad: u64) -> u64 {
+    5 + entidad % 7
+}
+// Genera los CSV COVID19MEXICO y el diccionario de datos (descriptores y
+// catálogos) con la misma forma que los publicados, para probar el ETL sin
+// descargar nada. Con la misma semilla los archivos salen iguales byte a byte
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticDataset {
+    seed: u64,
+    // Registros de cada año
+    rows: usize,
+    years: Vec<u16>,
+    // FECHA_ACTUALIZACION de todos los registros
+    release: NaiveDate,
+    // Proporción de registros con algún error: ID_REGISTRO repetido, edad
+    // imposible, fecha inválida o defunción antes del ingreso
+    anomaly_rate: f64,
+}
+impl Default for SyntheticDataset {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            rows: 1_000,
+            years: vec![2020],
+            release: NaiveDate::from_ymd_opt(2023, 6, 5).unwrap(),
+            anomaly_rate: 0.0,
+        }
+    }
+}
+impl SyntheticDataset {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            ..Default::default()
+        }
+    }
+    pub fn with_rows(mut self, rows: usize) -> Self {
+        self.rows = rows;
+        self
+    }
+    pub fn with_years(mut self, years: &[u16]) -> Self {
+        self.years = years.to_vec();
+        self
+    }
+    pub fn with_release(mut self, release: NaiveDate) -> Self {
+        self.release = release;
+        self
+    }
+    pub fn with_anomaly_rate(mut self, anomaly_rate: f64) -> Self {
+        self.anomaly_rate = anomaly_rate.clamp(0.0, 1.0);
+        self
+    }
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+    pub fn years(&self) -> &[u16] {
+        &self.years
+    }
+    pub fn release(&self) -> NaiveDate {
+        self.release
+    }
+    pub fn csv_name(year: u16) -> String {
+        format!("COVID19MEXICO{}.csv", year)
+    }
+    // Un CSV por año en `csv_dir` y el diccionario en `dicc_dir`
+    pub fn write<P: AsRef<Path>, Q: AsRef<Path>>(
+        &self,
+        csv_dir: P,
+        dicc_dir: Q,
+    ) -> Result<Vec<PathBuf>, color_eyre::eyre::Error> {
+        self.write_dictionary(dicc_dir)?;
+        self.write_csvs(csv_dir)
+    }
+    pub fn write_csvs<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<Vec<PathBuf>, color_eyre::eyre::Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        let mut files = Vec::new();
+        for year in &self.years {
+            let path = dir.as_ref().join(Self::csv_name(*year));
+            self.write_csv(*year, &path)?;
+            files.push(path);
+        }
+        Ok(files)
+    }
+    // Los registros se escriben conforme se generan, sirve para archivos de
+    // varios millones de filas sin tenerlos en memoria
+    pub fn write_csv<P: AsRef<Path>>(
+        &self,
+        year: u16,
+        path: P,
+    ) -> Result<(), color_eyre::eyre::Error> {
+        let mut out = BufWriter::new(File::create(path)?);
+        writeln!(out, "{}", COVID_COLUMNS.join(","))?;
+        let mut rng = SplitMix64::new(self.seed ^ ((year as u64) << 32));
+        let mut last_id = None;
+        for i in 0..self.rows {
+            let mut record = Record::generate(&mut rng, year, i, self.release);
+            if rng.chance(self.anomaly_rate) {
+                record.add_anomaly(&mut rng, last_id.as_deref());
+            }
+            writeln!(out, "{}", record.to_csv())?;
+            last_id = Some(record.id);
+        }
+        out.flush()?;
+        Ok(())
+    }
+    // Descriptores y catálogos con los nombres del diccionario publicado
+    pub fn write_dictionary<P: AsRef<Path>>(
+        &self,
+        dir: P,
+    ) -> Result<(PathBuf, PathBuf), color_eyre::eyre::Error> {
+        fs::create_dir_all(dir.as_ref())?;
+        let descriptors = dir.as_ref().join(DESCRIPTORS_FILE);
+        let catalogs = dir.as_ref().join(CATALOGS_FILE);
+        write_descriptors(&descriptors)?;
+        write_catalogs(&catalogs)?;
+        Ok((descriptors, catalogs))
+    }
+}
+// Hoja de descriptores: una fila por columna y su formato, las columnas de
+// catálogo llevan `CATÁLOGO: <TABLA>`
+pub fn write_descriptors<P: AsRef<Path>>(path: P) -> Result<(), color_eyre::eyre::Error> {
+    let mut rows = vec![text_row(&[
+        "Nº",
+        "NOMBRE DE VARIABLE",
+        "DESCRIPCIÓN DE VARIABLE",
+        "FORMATO O FUENTE",
+    ])];
+    for (i, column) in COVID_COLUMNS.iter().enumerate() {
+        let format = match *column {
+            "ID_REGISTRO" => "TEXTO, 99 CARACTERES ALFANUMÉRICOS".to_string(),
+            "EDAD" => "NÚMERICA EN AÑOS".to_string(),
+            "PAIS_NACIONALIDAD" | "PAIS_ORIGEN" => "TEXTO, 99 CARACTERES ALFABÉTICOS".to_string(),
+            c if c.starts_with("FECHA_") => "AAAA-MM-DD".to_string(),
+            c => format!("CATÁLOGO: {}", catalog_of(c)),
+        };
+        rows.push(vec![
+            Cell::Number((i + 1) as f64),
+            Cell::Text(column.to_string()),
+            Cell::Text(format!("Variable {} del registro", column)),
+            Cell::Text(format),
+        ]);
+    }
+    write_xlsx(path, &[("Hoja1".to_string(), rows)])
+}
+// Libro de catálogos, una hoja `Catálogo <TABLA>` por tabla
+pub fn write_catalogs<P: AsRef<Path>>(path: P) -> Result<(), color_eyre::eyre::Error> {
+    let simple = |name: &str, values: &[(u64, &str)]| {
+        let mut rows = vec![text_row(&["CLAVE", "DESCRIPCIÓN"])];
+        rows.extend(
+            values
+                .iter()
+                .map(|(k, d)| vec![Cell::Number(*k as f64), Cell::Text(d.to_string())]),
+        );
+        (format!("Catálogo {}", name), rows)
+    };
+    let classification = |name: &str, values: &[(u64, &str, &str)]| {
+        let mut rows = vec![text_row(&["CLAVE", "CLASIFICACIÓN", "DESCRIPCIÓN"])];
+        rows.extend(values.iter().map(|(k, c, d)| {
+            vec![
+                Cell::Number(*k as f64),
+                Cell::Text(c.to_string()),
+                Cell::Text(d.to_string()),
+            ]
+        }));
+        (format!("Catálogo {}", name), rows)
+    };
+    let sectores = SECTORES
+        .iter()
+        .enumerate()
+        .map(|(i, s)| ((i + 1) as u64, *s))
+        .chain([(99, "NO ESPECIFICADO")])
+        .collect::<Vec<_>>();
+    let mut entidades = vec![text_row(&[
+        "CLAVE_ENTIDAD",
+        "ENTIDAD_FEDERATIVA",
+        "ABREVIATURA",
+    ])];
+    for (k, (name, abbr)) in ENTIDADES
+        .iter()
+        .enumerate()
+        .map(|(i, e)| ((i + 1) as u64, *e))
+        .chain([
+            (36, ("ESTADOS UNIDOS MEXICANOS", "EUM")),
+            (97, ("NO APLICA", "NA")),
+            (98, ("SE IGNORA", "SI")),
+            (99, ("NO ESPECIFICADO", "NE")),
+        ])
+    {
+        entidades.push(vec![
+            Cell::Number(k as f64),
+            Cell::Text(name.to_string()),
+            Cell::Text(abbr.to_string()),
+        ]);
+    }
+    // La clave del municipio va como texto de tres dígitos, igual que en el
+    // catálogo publicado, para que `get_df_cat` la junte con la de la entidad
+    let mut municipios_rows = vec![text_row(&["CLAVE_ENTIDAD", "CLAVE_MUNICIPIO", "MUNICIPIO"])];
+    for entidad in 1..=ENTIDADES.len() as u64 {
+        let nombre = ENTIDADES[entidad as usize - 1].0;
+        for municipio in (1..=municipios(entidad)).chain([999]) {
+            let descripcion = if municipio == 999 {
+                "NO ESPECIFICADO".to_string()
+            } else {
+                format!("MUNICIPIO {} DE {}", municipio, nombre)
+            };
+            municipios_rows.push(vec![
+                Cell::Number(entidad as f64),
+                Cell::Text(format!("{:03}", municipio)),
+                Cell::Text(descripcion),
+            ]);
+        }
+    }
+    let sheets = vec![
+        simple(
+            "ORIGEN",
+            &[(1, "USMER"), (2, "FUERA DE USMER"), (3, "NO ESPECIFICADO")],
+        ),
+        simple("SECTOR", &sectores),
+        simple(
+            "SEXO",
+            &[(1, "MUJER"), (2, "HOMBRE"), (99, "NO ESPECIFICADO")],
+        ),
+        simple(
+            "TIPO_PACIENTE",
+            &[
+                (1, "AMBULATORIO"),
+                (2, "HOSPITALIZADO"),
+                (99, "NO ESPECIFICADO"),
+            ],
+        ),
+        simple(
+            "SI_NO",
+            &[
+                (1, "SI"),
+                (2, "NO"),
+                (97, "NO APLICA"),
+                (98, "SE IGNORA"),
+                (99, "NO ESPECIFICADO"),
+            ],
+        ),
+        simple(
+            "NACIONALIDAD",
+            &[(1, "MEXICANA"), (2, "EXTRANJERA"), (99, "NO ESPECIFICADO")],
+        ),
+        simple(
+            "RESULTADO_LAB",
+            &[
+                (1, "POSITIVO A SARS-COV-2"),
+                (2, "NO POSITIVO A SARS-COV-2"),
+                (3, "RESULTADO PENDIENTE"),
+                (4, "RESULTADO NO ADECUADO"),
+                (97, "NO APLICA"),
+            ],
+        ),
+        simple(
+            "RESULTADO_ANTIGENO",
+            &[
+                (1, "POSITIVO A SARS-COV-2"),
+                (2, "NEGATIVO A SARS-COV-2"),
+                (97, "NO APLICA"),
+            ],
+        ),
+        classification(
+            "CLASIFICACION_FINAL_COVID",
+            &[
+                (
+                    1,
+                    "CASO DE COVID-19 CONFIRMADO POR ASOCIACIÓN CLÍNICA EPIDEMIOLÓGICA",
+                    "CONFIRMADO",
+                ),
+                (
+                    2,
+                    "CASO DE COVID-19 CONFIRMADO POR COMITÉ DE  DICTAMINACIÓN",
+                    "CONFIRMADO",
+                ),
+                (3, "CASO DE SARS-COV-2  CONFIRMADO", "CONFIRMADO"),
+                (4, "INVÁLIDO POR LABORATORIO", "INVÁLIDO"),
+                (5, "NO REALIZADO POR LABORATORIO", "NO REALIZADO"),
+                (6, "CASO SOSPECHOSO", "SOSPECHOSO"),
+                (7, "NEGATIVO A SARS-COV-2", "NEGATIVO"),
+            ],
+        ),
+        ("Catálogo de ENTIDADES".to_string(), entidades),
+        ("Catálogo MUNICIPIOS".to_string(), municipios_rows),
+    ];
+    write_xlsx(path, &sheets)
+}
+// Tabla del catálogo al que apunta una columna, como la escribe el descriptor
+fn catalog_of(column: &str) -> &'static str {
+    match column {
+        "ORIGEN" => "ORIGEN",
+        "SECTOR" => "SECTOR",
+        "SEXO" => "SEXO",
+        "ENTIDAD_UM" | "ENTIDAD_NAC" | "ENTIDAD_RES" => "ENTIDADES",
+        "MUNICIPIO_RES" => "MUNICIPIOS",
+        "TIPO_PACIENTE" => "TIPO_PACIENTE",
+        "NACIONALIDAD" => "NACIONALIDAD",
+        "RESULTADO_LAB" => "RESULTADO_LAB",
+        "RESULTADO_ANTIGENO" => "RESULTADO_ANTIGENO",
+        "CLASIFICACION_FINAL" => "CLASIFICACION_FINAL_COVID",
+        // El descriptor publicado trae el espacio, `get_schema_sql` lo quita
+        _ => "SI_ NO",
+    }
+}
+// Un registro del CSV, los catálogos van como vienen en los históricos
+// (entidades con dos dígitos, municipio con tres) y las fechas `AAAA-MM-DD`
+#[derive(Debug, Clone)]
+struct Record {
+    id: String,
+    values: Vec<String>,
+}
+impl Record {
+    fn generate(rng: &mut SplitMix64, year: u16, i: usize, release: NaiveDate) -> Self {
+        let start = NaiveDate::from_ymd_opt(year as i32, 1, 1).unwrap();
+        let ingreso = start + Duration::days(rng.below(365) as i64);
+        let sintomas = ingreso - Duration::days(rng.below(15) as i64);
+        let hospitalizado = rng.chance(0.2);
+        let defuncion = rng.chance(if hospitalizado { 0.3 } else { 0.01 });
+        let sexo = 1 + rng.below(2);
+        let entidad_res = 1 + rng.below(32);
+        let extranjero = rng.chance(0.02);
+        let muestra_lab = rng.chance(0.6);
+        let muestra_antigeno = !muestra_lab && rng.chance(0.5);
+        // Las claves no se repiten dentro del año y cada año tiene su prefijo
+        let id = format!(
+            "{}{:07x}",
+            (b'a' + (year % 26) as u8) as char,
+            (i as u64).wrapping_mul(0x9e37_79b1) & 0xfff_ffff
+        );
+        let mut values = Vec::with_capacity(COVID_COLUMNS.len());
+        for column in COVID_COLUMNS {
+            let value = match column {
+                "FECHA_ACTUALIZACION" => release.to_string(),
+                "ID_REGISTRO" => id.clone(),
+                "ORIGEN" => (1 + rng.below(2)).to_string(),
+                "SECTOR" if rng.chance(0.01) => "99".into(),
+                "SECTOR" => (1 + rng.below(SECTORES.len() as u64)).to_string(),
+                "ENTIDAD_UM" => format!("{:02}", 1 + rng.below(32)),
+                "SEXO" => sexo.to_string(),
+                "ENTIDAD_NAC" if extranjero => "99".into(),
+                "ENTIDAD_NAC" => format!("{:02}", 1 + rng.below(32)),
+                "ENTIDAD_RES" => format!("{:02}", entidad_res),
+                "MUNICIPIO_RES" => {
+                    let municipio = if rng.chance(0.02) {
+                        999
+                    } else {
+                        1 + rng.below(municipios(entidad_res))
+                    };
+                    format!("{:03}", municipio)
+                }
+                "TIPO_PACIENTE" => if hospitalizado { "2" } else { "1" }.into(),
+                "FECHA_INGRESO" => ingreso.to_string(),
+                "FECHA_SINTOMAS" => sintomas.to_string(),
+                "FECHA_DEF" if defuncion => {
+                    (ingreso + Duration::days(rng.below(30) as i64)).to_string()
+                }
+                "FECHA_DEF" => "9999-99-99".into(),
+                "INTUBADO" | "UCI" if !hospitalizado => "97".into(),
+                "EMBARAZO" if sexo == 2 => "97".into(),
+                "EDAD" => rng.below(100).to_string(),
+                "NACIONALIDAD" => if extranjero { "2" } else { "1" }.into(),
+                "PAIS_NACIONALIDAD" if extranjero => rng.pick(&PAISES).to_string(),
+                "PAIS_NACIONALIDAD" => rng.pick_or(&["México"], "99", 0.01),
+                "PAIS_ORIGEN" if extranjero => rng.pick(&PAISES).to_string(),
+                "PAIS_ORIGEN" => "97".into(),
+                "RESULTADO_LAB" if !muestra_lab => "97".into(),
+                "RESULTADO_LAB" => (1 + rng.below(4)).to_string(),
+                "TOMA_MUESTRA_LAB" => if muestra_lab { "1" } else { "2" }.into(),
+                "RESULTADO_ANTIGENO" if !muestra_antigeno => "97".into(),
+                "RESULTADO_ANTIGENO" => (1 + rng.below(2)).to_string(),
+                "TOMA_MUESTRA_ANTIGENO" => if muestra_antigeno { "1" } else { "2" }.into(),
+                "CLASIFICACION_FINAL" => (1 + rng.below(7)).to_string(),
+                c if COMORBILIDADES.contains(&c) => rng.pick_or(&[1, 2, 2, 2], 98, 0.02),
+                c if SI_NO.contains(&c) || c == "INTUBADO" || c == "UCI" => {
+                    rng.pick_or(&[1, 2, 2], 99, 0.02)
+                }
+                c => unreachable!("columna sin generador: {}", c),
+            };
+            values.push(value);
+        }
+        Self { id, values }
+    }
+    fn set(&mut self, column: &str, value: String) {
+        if let Some(i) = COVID_COLUMNS.iter().position(|c| *c == column) {
+            if column == "ID_REGISTRO" {
+                self.id = value.clone();
+            }
+            self.values[i] = value;
+        }
+    }
+    fn get(&self, column: &str) -> &str {
+        COVID_COLUMNS
+            .iter()
+            .position(|c| *c == column)
+            .map(|i| self.values[i].as_str())
+            .unwrap_or_default()
+    }
+    fn add_anomaly(&mut self, rng: &mut SplitMix64, last_id: Option<&str>) {
+        match (rng.below(4), last_id) {
+            (0, Some(id)) => self.set("ID_REGISTRO", id.to_string()),
+            (1, _) => self.set("EDAD", (130 + rng.below(20)).to_string()),
+            (2, _) => {
+                let ingreso = self.get("FECHA_INGRESO").to_string();
+                self.set("FECHA_SINTOMAS", format!("{}-02-30", &ingreso[..4]));
+            }
+            _ => {
+                let ingreso = NaiveDate::parse_from_str(self.get("FECHA_INGRESO"), "%Y-%m-%d")
+                    .unwrap_or_default();
+                let def = ingreso - Duration::days(1 + rng.below(10) as i64);
+                self.set(
+                    "FECHA_DEF",
+                    format!("{}-{:02}-{:02}", def.year(), def.month(), def.day()),
+                );
+            }
+        }
+    }
+    fn to_csv(&self) -> String {
+        self.values.join(",")
+    }
+}
+// Generador pseudoaleatorio pequeño y estable entre versiones, para que la
+// misma semilla dé siempre los mismos archivos
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        Self(seed)
+    }
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+    fn chance(&mut self, p: f64) -> bool {
+        p > 0.0 && ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+    fn pick<'a, T>(&mut self, values: &'a [T]) -> &'a T {
+        &values[self.below(values.len() as u64) as usize]
+    }
+    // Con probabilidad `p` regresa `other`, si no uno de `values`
+    fn pick_or<T: ToString>(&mut self, values: &[T], other: T, p: f64) -> String {
+        if self.chance(p) {
+            other.to_string()
+        } else {
+            self.pick(values).to_string()
+        }
+    }
+}
+#[derive(Debug, Clone)]
+enum Cell {
+    Number(f64),
+    Text(String),
+}
+fn text_row(values: &[&str]) -> Vec<Cell> {
+    values.iter().map(|v| Cell::Text(v.to_string())).collect()
+}
+// Libro xlsx mínimo (texto en línea, sin estilos) que calamine puede leer
+fn write_xlsx<P: AsRef<Path>>(
+    path: P,
+    sheets: &[(String, Vec<Vec<Cell>>)],
+) -> Result<(), color_eyre::eyre::Error> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = SimpleFileOptions::default();
+    let xml = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#;
+    let overrides = (1..=sheets.len())
+        .map(|i| {
+            format!(
+                r#"<Override PartName="/xl/worksheets/sheet{}.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/>"#,
+                i
+            )
+        })
+        .collect::<String>();
+    zip.start_file("[Content_Types].xml", options)?;
+    write!(
+        zip,
+        r#"{}<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/>{}</Types>"#,
+        xml, overrides
+    )?;
+    zip.start_file("_rels/.rels", options)?;
+    write!(
+        zip,
+        r#"{}<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#,
+        xml
+    )?;
+    zip.start_file("xl/workbook.xml", options)?;
+    write!(
+        zip,
+        r#"{}<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets>{}</sheets></workbook>"#,
+        xml,
+        sheets
+            .iter()
+            .enumerate()
+            .map(|(i, (name, _))| format!(
+                r#"<sheet name="{}" sheetId="{}" r:id="rId{}"/>"#,
+                escape(name),
+                i + 1,
+                i + 1
+            ))
+            .collect::<String>()
+    )?;
+    zip.start_file("xl/_rels/workbook.xml.rels", options)?;
+    write!(
+        zip,
+        r#"{}<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships">{}</Relationships>"#,
+        xml,
+        (1..=sheets.len())
+            .map(|i| format!(
+                r#"<Relationship Id="rId{}" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet{}.xml"/>"#,
+                i, i
+            ))
+            .collect::<String>()
+    )?;
+    for (i, (_, rows)) in sheets.iter().enumerate() {
+        zip.start_file(format!("xl/worksheets/sheet{}.xml", i + 1), options)?;
+        write!(
+            zip,
+            r#"{}<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#,
+            xml
+        )?;
+        for (r, row) in rows.iter().enumerate() {
+            write!(zip, r#"<row r="{}">"#, r + 1)?;
+            for (c, cell) in row.iter().enumerate() {
+                let reference = format!("{}{}", column_name(c), r + 1);
+                match cell {
+                    Cell::Number(n) => write!(zip, r#"<c r="{}"><v>{}</v></c>"#, reference, n)?,
+                    Cell::Text(t) => write!(
+                        zip,
+                        r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#,
+                        reference,
+                        escape(t)
+                    )?,
+                }
+            }
+            write!(zip, "</row>")?;
+        }
+        write!(zip, "</sheetData></worksheet>")?;
+    }
+    zip.finish()?;
+    Ok(())
+}
+// A, B, ..., Z, AA, AB, ...
+fn column_name(mut c: usize) -> String {
+    let mut name = Vec::new();
+    loop {
+        name.push(b'A' + (c % 26) as u8);
+        if c < 26 {
+            break;
+        }
+        c = c / 26 - 1;
+    }
+    name.reverse();
+    String::from_utf8(name).unwrap_or_default()
+}
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,10 +1,22 @@
+use crate::progress::{NoProgress, ProgressObserver, ProgressStage};
 use color_eyre::eyre::Ok;
 use std::fs::{self, File};
 use std::io::{self};
 use std::path::Path;
+use tracing::{debug, info_span};
 use zip::ZipArchive;
 
 pub fn extract_zip(zip_path: &str, output_dir: &Path) -> Result<(), color_eyre::eyre::Error> {
+    extract_zip_with_progress(zip_path, output_dir, &NoProgress)
+}
+
+// Reports every extracted entry to `progress`
+pub fn extract_zip_with_progress(
+    zip_path: &str,
+    output_dir: &Path,
+    progress: &dyn ProgressObserver,
+) -> Result<(), color_eyre::eyre::Error> {
+    let _span = info_span!("extract_zip", zip = zip_path).entered();
     // Create the output directory if it doesn't exist
     fs::create_dir_all(output_dir)?;
 
@@ -13,6 +25,7 @@ pub fn extract_zip(zip_path: &str, output_dir: &Path) -> Result<(), color_eyre::
 
     // Create a ZipArchive from the file
     let mut archive = ZipArchive::new(zip_file)?;
+    progress.start(ProgressStage::Extract, zip_path, Some(archive.len() as u64));
 
     // Iterate through all files in the archive
     for i in 0..archive.len() {
@@ -44,8 +57,10 @@ pub fn extract_zip(zip_path: &str, output_dir: &Path) -> Result<(), color_eyre::
             }
         }
 
-        println!("Extracted: {}", file.name());
+        debug!(file = file.name(), bytes = file.size(), "extracted");
+        progress.advance(ProgressStage::Extract, zip_path, 1);
     }
+    progress.finish(ProgressStage::Extract, zip_path);
 
     Ok(())
 }
@@ -1,79 +1,118 @@
-use crate::download::download_file;
+use crate::config::{EtlPaths, SourcesConfig};
+use crate::countries::CountryDictionary;
+use crate::download::{download_file, download_file_with_progress};
 use crate::pl_sql::{SqliteColOption, SqliteDataType, SqliteSchema};
-use crate::unzip::extract_zip;
+use crate::progress::{NoProgress, ProgressObserver};
+use crate::unzip::{extract_zip, extract_zip_with_progress};
 use crate::xlxs_to_pl::ExcelReader;
 use calamine::{open_workbook, Reader, Xlsx};
 use futures::future::join;
 use polars::prelude::*;
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::fs::create_dir_all;
 use std::fs::File;
+use std::future::Future;
 use std::io::BufReader;
 use std::path::Path;
-use std::thread::{self, JoinHandle};
-use tokio::runtime::Runtime;
+use std::sync::OnceLock;
+use std::thread::{self, ScopedJoinHandle};
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
+use tracing::{warn, Span};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+// Runtime compartido por todas las funciones bloqueantes del crate
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| Runtime::new().expect("No se pudo crear el runtime de tokio"))
+}
+// Ejecuta un future desde código síncrono, si ya hay un runtime de tokio
+// multi hilo lo reutiliza en lugar de crear otro (lo que causaría un panic)
+pub fn block_on<F: Future>(fut: F) -> Result<F::Output, color_eyre::eyre::Error> {
+    match Handle::try_current() {
+        std::result::Result::Ok(handle) => match handle.runtime_flavor() {
+            RuntimeFlavor::MultiThread => Ok(tokio::task::block_in_place(|| handle.block_on(fut))),
+            _ => Err(color_eyre::eyre::eyre!(
+                "Blocking call inside a current_thread Tokio runtime, use the async API"
+            )),
+        },
+        Err(_) => Ok(runtime().block_on(fut)),
+    }
+}
 pub async fn download_urls(urls: Vec<&str>, dir: &Path) -> Result<(), color_eyre::eyre::Error> {
+    download_urls_with_progress(urls, dir, &NoProgress).await
+}
+pub async fn download_urls_with_progress(
+    urls: Vec<&str>,
+    dir: &Path,
+    progress: &dyn ProgressObserver,
+) -> Result<(), color_eyre::eyre::Error> {
     create_dir_all(dir).expect("No se pudo crear la carpeta");
     for url in urls {
         let name_file = Path::new(&url).file_name().unwrap();
         let path = env::current_dir().unwrap().join(dir).join(name_file);
-        download_file(url, &path).await?;
+        download_file_with_progress(url, &path, progress).await?;
     }
     Ok(())
 }
 
-pub fn unzip_data(
-    files: Vec<&'static str>,
-    dir: &'static Path,
+pub fn unzip_data(files: Vec<&str>, dir: &Path) -> Result<(), color_eyre::eyre::Error> {
+    unzip_data_with_progress(files, dir, &NoProgress)
+}
+pub fn unzip_data_with_progress(
+    files: Vec<&str>,
+    dir: &Path,
+    progress: &dyn ProgressObserver,
 ) -> Result<(), color_eyre::eyre::Error> {
     create_dir_all(dir).expect("No se pudo crear la carpeta");
-    let mut handles: Vec<JoinHandle<()>> = Vec::new();
-    for file in files {
-        let handle = thread::spawn(move || {
-            let _ = extract_zip(file, dir);
-        });
-        handles.push(handle);
-    }
-    for handle in handles {
-        handle.join().unwrap();
-    }
+    // Cada hilo continúa el span de quien llama para que la bitácora quede junta
+    let span = Span::current();
+    thread::scope(|s| {
+        let mut handles: Vec<ScopedJoinHandle<()>> = Vec::new();
+        for file in files {
+            let span = span.clone();
+            let handle = s.spawn(move || {
+                let _span = span.enter();
+                if let Err(e) = extract_zip_with_progress(file, dir, progress) {
+                    warn!(zip = file, error = %e, "no se pudo descomprimir");
+                }
+            });
+            handles.push(handle);
+        }
+        for handle in handles {
+            handle.join().unwrap();
+        }
+    });
 
     Ok(())
 }
+// Años de los históricos que se descargan por omisión
+pub const DEFAULT_YEARS: [u16; 4] = [2020, 2021, 2022, 2023];
+pub const URL_DICC: &str =
+    "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/diccionario_datos_abiertos.zip";
+pub fn url_covid(year: u16) -> String {
+    format!(
+        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/{}/COVID19MEXICO{}.zip",
+        year, year
+    )
+}
 fn string_to_static_str(s: String) -> &'static str {
     Box::leak(s.into_boxed_str())
 }
 pub fn to_str(s: String) -> &'static str {
     string_to_static_str(s)
 }
-fn generic_hash(s: &str) -> u64 {
+// Hash SHA-256 de un archivo en hexadecimal, para saber de qué archivo viene la data
+pub fn file_sha256<P: AsRef<Path>>(path: P) -> Result<String, color_eyre::eyre::Error> {
     let mut hasher = Sha256::new();
-    hasher.update(s.as_bytes());
-    // Get the result and convert to a byte array
-    let result = hasher.finalize();
-
-    // Parse enough bytes from the hash to create a u64
-    // We'll use the first 8 bytes
-    let mut bytes = [0u8; 8];
-    bytes.copy_from_slice(&result[0..8]);
-
-    // Convert the bytes to u64 and apply the modulo
-    let hash_int = u64::from_be_bytes(bytes);
-    hash_int % 1_000_000_000
-}
-fn hash_column(exp: Expr) -> Expr {
-    exp.map(
-        |c: Column| -> PolarsResult<Option<Column>> {
-            let out: UInt64Chunked = c
-                .str()?
-                .apply_nonnull_values_generic(DataType::UInt64, generic_hash);
-            Ok(Some(out.into_column()))
-        },
-        GetOutput::from_type(DataType::UInt64),
-    )
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
 }
 pub fn get_df_cat<P: AsRef<Path>>(
     path: P,
@@ -133,6 +172,9 @@ pub fn get_schema_pl<P: AsRef<Path>>(path: P) -> Result<SchemaRef, color_eyre::e
         let type_col = typ.str_value().to_string();
         if type_col.contains("CATÁLOGO") || type_col.contains("CATALÓGO") {
             schema.with_column(column.into(), DataType::UInt64);
+        } else if type_col.contains("NÚMERICA") || type_col.contains("NUMÉRICA") {
+            // EDAD, las validaciones la comparan como número
+            schema.with_column(column.into(), DataType::Int64);
         } else {
             schema.with_column(column.into(), DataType::String);
         }
@@ -185,106 +227,234 @@ pub fn get_schema_sql<P: AsRef<Path>>(path: P) -> Result<SqliteSchema, color_eyr
     );
     Ok(schema)
 }
-pub fn get_unique_contry(df: &LazyFrame, col_name: &str, id_name: &str) -> PolarsResult<LazyFrame> {
-    let pais_nacionalidad = df
-        .clone()
-        .select([when(col("PAIS_NACIONALIDAD").eq(lit("99")))
-            .then(lit("SE INGONARA"))
-            .otherwise(col("PAIS_NACIONALIDAD"))
-            .unique_stable()
-            .alias(col_name)]);
-    let pais_origen = df.clone().select([when(col("PAIS_ORIGEN").eq(lit("97")))
+// La clave de MUNICIPIOS es la entidad seguida del municipio a tres dígitos
+pub fn municipio_res() -> Expr {
+    concat_str(
+        [
+            col("ENTIDAD_RES"),
+            col("MUNICIPIO_RES")
+                .cast(DataType::String)
+                .str()
+                .zfill(lit(3)),
+        ],
+        "",
+        false,
+    )
+    .cast(DataType::UInt64)
+}
+// Los códigos 99 y 97 de las columnas de países se cambian por su descripción
+fn pais_nacionalidad() -> Expr {
+    when(col("PAIS_NACIONALIDAD").eq(lit("99")))
+        .then(lit("SE IGNORA"))
+        .otherwise(col("PAIS_NACIONALIDAD"))
+}
+fn pais_origen() -> Expr {
+    when(col("PAIS_ORIGEN").eq(lit("97")))
         .then(lit("NO APLICA"))
         .otherwise(col("PAIS_ORIGEN"))
-        .unique_stable()
-        .alias(col_name)]);
-    let pais = concat([pais_nacionalidad, pais_origen], UnionArgs::default())?;
-    Ok(pais
-        .select([hash_column(col(col_name)).alias(id_name), col(col_name)])
-        .unique_stable(None, UniqueKeepStrategy::First))
+}
+// Agrega al diccionario los países nuevos de `df` y regresa el catálogo PAISES
+pub fn get_unique_contry(
+    df: &LazyFrame,
+    countries: &mut CountryDictionary,
+) -> Result<DataFrame, color_eyre::eyre::Error> {
+    let pais = concat(
+        [
+            df.clone().select([pais_nacionalidad().alias("PAIS")]),
+            df.clone().select([pais_origen().alias("PAIS")]),
+        ],
+        UnionArgs::default(),
+    )?
+    .select([col("PAIS").unique_stable()])
+    .collect()?;
+    for name in pais.column("PAIS")?.str()?.into_iter().flatten() {
+        countries.get_or_insert(name)?;
+    }
+    Ok(countries.frame()?)
 }
 pub fn trim_cols(column: &Column) -> Expr {
     col(column.name().as_str()).str().strip_chars(lit(" "))
 }
-pub fn clean_data_covid(df: LazyFrame) -> LazyFrame {
+pub fn clean_data_covid(df: LazyFrame, countries: &CountryDictionary) -> LazyFrame {
     df.with_columns(vec![
-        concat_str(
-            [
-                col("ENTIDAD_RES"),
-                col("MUNICIPIO_RES")
-                    .cast(DataType::String)
-                    .str()
-                    .zfill(lit(3)),
-            ],
-            "",
-            false,
-        )
-        .cast(DataType::UInt64)
-        .alias("MUNICIPIO_RES"),
-        hash_column(
-            when(col("PAIS_NACIONALIDAD").eq(lit("99")))
-                .then(lit("SE INGONARA"))
-                .otherwise(col("PAIS_NACIONALIDAD")),
-        )
-        .alias("PAIS_NACIONALIDAD"),
-        hash_column(
-            when(col("PAIS_ORIGEN").eq(lit("97")))
-                .then(lit("NO APLICA"))
-                .otherwise(col("PAIS_ORIGEN")),
-        )
-        .alias("PAIS_ORIGEN"),
+        municipio_res().alias("MUNICIPIO_RES"),
+        countries
+            .key_expr(pais_nacionalidad())
+            .alias("PAIS_NACIONALIDAD"),
+        countries.key_expr(pais_origen()).alias("PAIS_ORIGEN"),
         when(col("FECHA_DEF").eq(lit("9999-99-99")))
             .then(lit(NULL))
             .otherwise(col("FECHA_DEF"))
             .alias("FECHA_DEF"),
     ])
 }
+// Columnas de fecha del conjunto de datos, vienen como texto `AAAA-MM-DD`
+pub const FECHA_COLUMNS: [&str; 4] = [
+    "FECHA_ACTUALIZACION",
+    "FECHA_INGRESO",
+    "FECHA_SINTOMAS",
+    "FECHA_DEF",
+];
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DateParseMode {
+    // Cualquier fecha inválida detiene la carga
+    #[default]
+    Strict,
+    // Las fechas inválidas se guardan como NULL y solo se cuentan
+    Lenient,
+}
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct DateParseReport {
+    // (columna, número de valores inválidos, primer valor inválido)
+    pub errors: Vec<(String, usize, String)>,
+}
+impl DateParseReport {
+    pub fn is_empty(&self) -> bool {
+        self.errors.is_empty()
+    }
+    pub fn n_errors(&self) -> usize {
+        self.errors.iter().map(|(_, n, _)| n).sum()
+    }
+}
+impl std::fmt::Display for DateParseReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (column, n, example) in &self.errors {
+            writeln!(f, "{}: {} fechas inválidas (ej. {:?})", column, n, example)?;
+        }
+        Ok(())
+    }
+}
+// Convierte las columnas de texto a `Date`, el centinela `9999-99-99` pasa a NULL
+// y no se cuenta como error
+pub fn parse_dates(
+    df: DataFrame,
+    columns: &[&str],
+    mode: DateParseMode,
+) -> Result<(DataFrame, DateParseReport), color_eyre::eyre::Error> {
+    let columns = columns
+        .iter()
+        .filter(|c| df.column(c).is_ok_and(|c| c.dtype() == &DataType::String))
+        .collect::<Vec<_>>();
+    let raw = |c: &str| {
+        when(col(c).eq(lit("9999-99-99")))
+            .then(lit(NULL).cast(DataType::String))
+            .otherwise(col(c))
+    };
+    let parsed = |c: &str| {
+        raw(c).str().to_date(StrptimeOptions {
+            format: Some("%Y-%m-%d".into()),
+            strict: false,
+            exact: true,
+            cache: true,
+        })
+    };
+    let mut report = DateParseReport::default();
+    if !columns.is_empty() {
+        let invalid = |c: &str| raw(c).is_not_null().and(parsed(c).is_null());
+        let counts = df
+            .clone()
+            .lazy()
+            .select(
+                columns
+                    .iter()
+                    .flat_map(|c| {
+                        [
+                            invalid(c).sum().alias(format!("{}_n", c)),
+                            raw(c).filter(invalid(c)).first().alias(format!("{}_ej", c)),
+                        ]
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .collect()?;
+        for c in &columns {
+            let n = counts
+                .column(&format!("{}_n", c))?
+                .get(0)?
+                .extract::<usize>()
+                .unwrap_or_default();
+            if n > 0 {
+                let example = counts.column(&format!("{}_ej", c))?.get(0)?;
+                report
+                    .errors
+                    .push((c.to_string(), n, example.str_value().to_string()));
+            }
+        }
+    }
+    if mode == DateParseMode::Strict && !report.is_empty() {
+        return Err(color_eyre::eyre::eyre!(
+            "Hay fechas que no se pudieron convertir:\n{}",
+            report
+        ));
+    }
+    let df = df
+        .lazy()
+        .with_columns(
+            columns
+                .iter()
+                .map(|c| parsed(c).alias(**c))
+                .collect::<Vec<_>>(),
+        )
+        .collect()?;
+    Ok((df, report))
+}
 pub fn is_dir_empty<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
     let mut entries = fs::read_dir(path)?;
     Ok(entries.next().is_none())
 }
-pub fn get_all_data(
-    dir_csv: &'static Path,
-    dir_dicc: &Path,
+pub fn get_all_data(dir_csv: &Path, dir_dicc: &Path) -> Result<(), color_eyre::eyre::Error> {
+    // Se descargan los años de `DEFAULT_YEARS` a `data_zip` y `dicc_zip`
+    let paths = EtlPaths {
+        csv_dir: dir_csv.to_path_buf(),
+        dicc_dir: dir_dicc.to_path_buf(),
+        ..Default::default()
+    };
+    get_all_data_from(&SourcesConfig::default(), &paths)
+}
+// Igual que `get_all_data` pero con las URL y carpetas de la configuración
+pub fn get_all_data_from(
+    sources: &SourcesConfig,
+    paths: &EtlPaths,
 ) -> Result<(), color_eyre::eyre::Error> {
-    // Declaramos los url con que se van a descargar
-    let urls = vec![
-        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/2020/COVID19MEXICO2020.zip",
-        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/2021/COVID19MEXICO2021.zip",
-        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/2022/COVID19MEXICO2022.zip",
-        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/2023/COVID19MEXICO2023.zip",
-     ]; // add more urls as you need
-        // Declaramos la carpeta donde se van a descargar
-    let dir_zip_files = Path::new("data_zip");
-    let rt = Runtime::new().unwrap();
-    let url_dicc = "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/diccionario_datos_abiertos.zip";
-    let dir_dicc_zip = Path::new("dicc_zip");
-    create_dir_all(dir_dicc_zip).expect("No se pudo crear la carpeta");
-    let path_file_zip_dicc = env::current_dir()
-        .unwrap()
+    let urls = sources
+        .years
+        .iter()
+        .map(|year| sources.url_covid(*year))
+        .collect::<Vec<_>>();
+    let urls = urls.iter().map(|u| u.as_str()).collect::<Vec<_>>();
+    // Declaramos la carpeta donde se van a descargar
+    let dir_zip_files = paths.zip_dir.as_path();
+    let url_dicc = sources.url_dicc.as_str();
+    let dir_dicc_zip = paths.dicc_zip_dir.as_path();
+    create_dir_all(dir_dicc_zip)?;
+    let path_file_zip_dicc = env::current_dir()?
         .join(dir_dicc_zip)
         .join(Path::new(url_dicc).file_name().unwrap());
     // descargamos los archivos
-    let _ = rt.block_on(join(
+    let (data, dicc) = block_on(join(
         download_urls(urls, dir_zip_files),
         download_file(url_dicc, &path_file_zip_dicc),
-    ));
+    ))?;
+    data?;
+    dicc?;
     let mut zip_files = Vec::new();
     for entry in fs::read_dir(dir_zip_files)? {
         let entry = entry?;
         let name = entry.file_name();
-        let file = dir_zip_files.join(name).to_string_lossy().to_string();
-        zip_files.push(to_str(file));
+        zip_files.push(dir_zip_files.join(name).to_string_lossy().to_string());
     }
     // los descomprimimos en una carpeta a parte
-    unzip_data(zip_files, dir_csv)?;
+    unzip_data(
+        zip_files.iter().map(|f| f.as_str()).collect(),
+        &paths.csv_dir,
+    )?;
     for entry in fs::read_dir(dir_dicc_zip)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_file() {
             if let Some(ext) = path.extension() {
                 if ext == "zip" {
-                    extract_zip(path.to_str().unwrap(), dir_dicc)?;
+                    extract_zip(path.to_str().unwrap(), &paths.dicc_dir)?;
                 }
             }
         }
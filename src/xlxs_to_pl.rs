@@ -6,7 +6,7 @@ use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
 
-fn data_excel_to_polars(data_excel: &Data) -> AnyValue {
+fn data_excel_to_polars(data_excel: &Data) -> AnyValue<'_> {
     match data_excel {
         Data::Empty => AnyValue::Null,
         Data::Int(i) => AnyValue::Int64(*i),
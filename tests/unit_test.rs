@@ -1,18 +1,43 @@
 use chrono::prelude::*;
+use clap::Parser;
+use db_cov19mx::cancel::CancelToken;
+use db_cov19mx::cli::*;
+use db_cov19mx::config::*;
+use db_cov19mx::countries::*;
 use db_cov19mx::download::download_file;
+use db_cov19mx::duck_sql::*;
+use db_cov19mx::etl::Etl;
+use db_cov19mx::harness::*;
+use db_cov19mx::ledger::*;
+use db_cov19mx::logging::*;
+use db_cov19mx::output::{OutputBackend, OutputSql};
+use db_cov19mx::pg_sql::*;
+use db_cov19mx::pipeline::*;
+use db_cov19mx::pl_csv::CsvBatchReader;
+use db_cov19mx::pl_parquet::*;
 use db_cov19mx::pl_sql::*;
-use db_cov19mx::unzip::extract_zip;
+use db_cov19mx::progress::*;
+use db_cov19mx::quality::*;
+use db_cov19mx::quarantine::*;
+use db_cov19mx::sentinel::*;
+use db_cov19mx::summary::*;
+use db_cov19mx::synthetic::*;
+use db_cov19mx::unzip::{extract_zip, extract_zip_with_progress};
 use db_cov19mx::utils::{
-    clean_data_covid, download_urls, get_df_cat, get_schema_pl, get_schema_sql, get_unique_contry,
-    trim_cols, unzip_data,
+    block_on, clean_data_covid, download_urls, file_sha256, get_df_cat, get_schema_pl,
+    get_schema_sql, get_unique_contry, municipio_res, parse_dates, trim_cols, unzip_data,
+    DateParseMode, FECHA_COLUMNS,
 };
 use db_cov19mx::xlxs_to_pl::ExcelReader;
 use polars::prelude::*;
+use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::fs::create_dir_all;
+use std::io::Write;
 use std::num::NonZeroUsize;
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 use tokio::runtime::Runtime;
 #[cfg(test)]
@@ -149,13 +174,13 @@ fn test_pl_to_sql() -> Result<(), Box<dyn std::error::Error>> {
 }
 #[test]
 #[ignore = "ok"]
-fn test_unique_contry() -> PolarsResult<()> {
-    let mut df = LazyCsvReader::new("csv_files/COVID19MEXICO2020.csv")
+fn test_unique_contry() -> Result<(), color_eyre::eyre::Error> {
+    let df = LazyCsvReader::new("csv_files/COVID19MEXICO2020.csv")
         .with_has_header(true)
         .with_infer_schema_length(Some(10000))
         .finish()?;
-    df = get_unique_contry(&df, "PAIS", "Id_PAISES")?;
-    eprintln!("{}", df.collect()?);
+    let df = get_unique_contry(&df, &mut CountryDictionary::new())?;
+    eprintln!("{}", df);
 
     Ok(())
 }
@@ -295,7 +320,9 @@ fn data_unique() -> Result<(), color_eyre::eyre::Error> {
         .with_infer_schema_length(Some(10000))
         .with_n_rows(Some(1_000_000))
         .finish()?;
-    lf = clean_data_covid(lf);
+    let mut countries = CountryDictionary::new();
+    get_unique_contry(&lf, &mut countries)?;
+    lf = clean_data_covid(lf, &countries);
     let not_colum = [
         "ID_REGISTRO",
         "FECHA_ACTUALIZACION",
@@ -321,12 +348,13 @@ fn data_unique() -> Result<(), color_eyre::eyre::Error> {
 #[test]
 // #[ignore = "reason"]
 fn test_load_data_covid() -> Result<(), color_eyre::eyre::Error> {
-    let dir_dicc = Path::new(
-        "/Users/luisfranciscohernandezvilla/Documents/proyects/rust/dataCovid19MX/data_dicc",
-    );
-    let dir_csv = Path::new(
-        "/Users/luisfranciscohernandezvilla/Documents/proyects/rust/dataCovid19MX/data_csv",
-    );
+    let dir = env::temp_dir().join("db_cov19mx_load_data_covid");
+    let _ = fs::remove_dir_all(&dir);
+    let dir_dicc = dir.join("data_dicc");
+    let dir_csv = dir.join("data_csv");
+    SyntheticDataset::new(0)
+        .with_rows(3_000)
+        .write(&dir_csv, &dir_dicc)?;
     let file_des = dir_dicc.join("240708 Descriptores_.xlsx");
     let mut schema_sql = get_schema_sql(file_des)?;
     schema_sql.with_column(
@@ -342,7 +370,7 @@ fn test_load_data_covid() -> Result<(), color_eyre::eyre::Error> {
             .foreign_key("PAISES", "CLAVE"),
     );
     let mut files_data = Vec::new();
-    for entry in fs::read_dir(dir_csv)? {
+    for entry in fs::read_dir(&dir_csv)? {
         let entry = entry?;
         let file = dir_csv.join(entry.file_name());
         files_data.push(file);
@@ -350,16 +378,35 @@ fn test_load_data_covid() -> Result<(), color_eyre::eyre::Error> {
     let file_des = dir_dicc.join("240708 Descriptores_.xlsx");
     let schema = get_schema_pl(&file_des)?;
 
-    let sql_write = SqlWriter::new("db_cov19mx.db")?;
+    let sql_write = SqlWriter::new(temp_db("load_data_covid"))?;
     let mut lf = LazyCsvReader::new_paths(files_data.clone().into())
         .with_has_header(true)
         .with_dtype_overwrite(Some(schema.clone()))
         .with_n_rows(Some(2_230))
         .finish()?;
-    lf = clean_data_covid(lf);
+    let mut countries = CountryDictionary::new();
+    get_unique_contry(&lf, &mut countries)?;
+    lf = clean_data_covid(lf, &countries);
     let by = 1;
     let file_cat = dir_dicc.join("240708 Catalogos.xlsx");
     let tables_cat = get_df_cat(file_cat)?;
+    // La base empieza vacía, los catálogos van antes por las llaves foráneas
+    let mut catalogs = tables_cat.clone();
+    catalogs.insert("PAISES".into(), countries.frame()?);
+    for (table, df) in &catalogs {
+        sql_write
+            .clone()
+            .with_schema(Some(SqliteSchema::new(
+                "CLAVE",
+                SqliteColOption::default()
+                    .with_type_sql(SqliteDataType::INTEGER)
+                    .with_primary_key(true),
+            )))
+            .with_table(Some(table))
+            .if_exists(IfExistsOption::Replace)
+            .with_index(false)
+            .finish(&mut df.clone())?;
+    }
     let mun_uniques: Vec<_> = tables_cat
         .get("MUNICIPIOS")
         .unwrap()
@@ -400,9 +447,1927 @@ fn test_load_data_covid() -> Result<(), color_eyre::eyre::Error> {
                 .with_separator(b',')
                 .finish(&mut df)?;
 
-            return res;
+            return res.map(|_| ());
         }
     }
 
     Ok(())
 }
+fn temp_db(name: &str) -> std::path::PathBuf {
+    let path = env::temp_dir().join(format!("db_cov19mx_{}.db", name));
+    let _ = fs::remove_file(&path);
+    path
+}
+#[test]
+fn test_migrate_schema_drift() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("migrate");
+    let sql_write = SqlWriter::new(&path)?
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false);
+    let mut df = df!(
+        "ID_REGISTRO" => ["a1", "a2"],
+        "EDAD" => [34, 56],
+    )?;
+    sql_write
+        .clone()
+        .if_exists(IfExistsOption::Replace)
+        .finish(&mut df)?;
+
+    // Nueva columna: basta con ALTER TABLE ADD COLUMN
+    let mut df_uci = df!(
+        "ID_REGISTRO" => ["a3"],
+        "EDAD" => [41],
+        "UCI" => [2],
+    )?;
+    let writer = sql_write
+        .clone()
+        .if_exists(IfExistsOption::Append)
+        .with_migrate(MigrateOption::AddColumns);
+    let plan = writer.plan_migration(&df_uci)?;
+    assert!(!plan.requires_rebuild);
+    assert_eq!(
+        plan.to_string(),
+        "ALTER TABLE COVID19MEXICO ADD COLUMN UCI INTEGER;\n"
+    );
+    writer.clone().finish(&mut df_uci)?;
+    assert!(writer.plan_migration(&df_uci)?.is_empty());
+
+    // Cambio de tipo: requiere reconstruir la tabla
+    let mut df_text = df!(
+        "ID_REGISTRO" => ["a4"],
+        "EDAD" => ["27"],
+        "UCI" => [1],
+    )?;
+    let plan = writer.plan_migration(&df_text)?;
+    assert!(plan.requires_rebuild);
+    assert_eq!(
+        plan.diff.changed,
+        vec![(
+            "EDAD".to_string(),
+            SqliteDataType::INTEGER,
+            SqliteDataType::TEXT
+        )]
+    );
+    assert!(writer.clone().finish(&mut df_text).is_err());
+    writer
+        .clone()
+        .with_migrate(MigrateOption::Rebuild)
+        .finish(&mut df_text)?;
+
+    let rt = Runtime::new()?;
+    let pool = rt.block_on(sqlx::SqlitePool::connect(&format!(
+        "sqlite://{}",
+        path.display()
+    )))?;
+    let stored = rt
+        .block_on(async { read_table_schema(&mut *pool.acquire().await?, "COVID19MEXICO").await })?
+        .unwrap();
+    assert_eq!(
+        stored.get("EDAD").unwrap().type_sql(),
+        &SqliteDataType::TEXT
+    );
+    let n: i64 =
+        rt.block_on(sqlx::query_scalar("SELECT COUNT(*) FROM COVID19MEXICO").fetch_one(&pool))?;
+    assert_eq!(n, 4);
+    Ok(())
+}
+#[test]
+fn test_sql_reader() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("reader");
+    let mut df = df!(
+        "ID_REGISTRO" => ["a1", "a2", "a3", "a4", "a5"],
+        "EDAD" => [Some(34), Some(81), None, Some(67), Some(5)],
+        "PESO" => [70.5, 61.0, 80.25, 55.0, 20.0],
+        "ENTIDAD_RES" => [9u64, 15, 9, 9, 21],
+    )?;
+    let sql_write = SqlWriter::new(&path)?
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false)
+        .if_exists(IfExistsOption::Replace);
+    sql_write.clone().finish(&mut df)?;
+
+    let reader = sql_write.reader();
+    let all = reader.clone().finish()?;
+    assert_eq!(all.shape(), (5, 4));
+    assert_eq!(all.column("EDAD")?.dtype(), &DataType::Int64);
+    assert_eq!(all.column("PESO")?.dtype(), &DataType::Float64);
+    assert_eq!(all.column("ID_REGISTRO")?.dtype(), &DataType::String);
+    assert_eq!(all.column("EDAD")?.null_count(), 1);
+
+    let filtered = reader
+        .clone()
+        .select(vec!["ID_REGISTRO", "EDAD"])
+        .filter(sql_col("ENTIDAD_RES").eq(9))
+        .filter(sql_col("EDAD").gt_eq(60).or(sql_col("EDAD").is_null()));
+    assert_eq!(
+        filtered.build_query()?,
+        "SELECT ID_REGISTRO,EDAD FROM COVID19MEXICO WHERE (ENTIDAD_RES = ?) AND ((EDAD >= ?) OR (EDAD IS NULL))"
+    );
+    let df_filtered = filtered.finish()?;
+    assert_eq!(df_filtered.get_column_names_str(), ["ID_REGISTRO", "EDAD"]);
+    assert_eq!(df_filtered.height(), 2);
+
+    let by_query = SqlReader::new(&path)?
+        .with_query(Some(
+            "SELECT ENTIDAD_RES, COUNT(*) AS N, AVG(PESO) AS PESO FROM COVID19MEXICO GROUP BY ENTIDAD_RES",
+        ))
+        .with_schema(Some(SqliteSchema::new(
+            "PESO",
+            SqliteColOption::default().with_type_sql(SqliteDataType::NUMERIC(Some(10), Some(2))),
+        )))
+        .filter(sql_col("N").gt(1))
+        .finish()?;
+    assert_eq!(by_query.height(), 1);
+    assert_eq!(by_query.column("N")?.dtype(), &DataType::Int64);
+    assert_eq!(
+        by_query.column("PESO")?.dtype(),
+        &DataType::Decimal(Some(10), Some(2))
+    );
+
+    let chunks = reader
+        .with_chunk_size(NonZeroUsize::new(2).unwrap())
+        .iter_chunks()?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(
+        chunks.iter().map(|df| df.height()).collect::<Vec<_>>(),
+        [2, 2, 1]
+    );
+    Ok(())
+}
+#[test]
+fn test_foreign_key_check() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("foreign_keys");
+    let sql_write = SqlWriter::new(&path)?.with_index(false);
+    let schema_des = SqliteSchema::new(
+        "CLAVE",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .with_primary_key(true),
+    );
+    let mut municipios = df!(
+        "CLAVE" => [9002u64, 9003],
+        "DESCRIPCIÓN" => ["AZCAPOTZALCO", "COYOACÁN"],
+    )?;
+    sql_write
+        .clone()
+        .with_schema(Some(schema_des))
+        .with_table(Some("MUNICIPIOS"))
+        .finish(&mut municipios)?;
+    let mut schema_sql = SqliteSchema::new(
+        "ID_REGISTRO",
+        SqliteColOption::default().with_primary_key(true),
+    );
+    schema_sql.with_column(
+        "MUNICIPIO_RES",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .foreign_key("MUNICIPIOS", "CLAVE"),
+    );
+    schema_sql.with_column(
+        "SEXO",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .foreign_key("SEXO", "CLAVE"),
+    );
+    let covid = df!(
+        "ID_REGISTRO" => ["a1", "a2", "a3", "a4"],
+        "MUNICIPIO_RES" => [9002u64, 9999, 9999, 15001],
+        "SEXO" => [1u64, 2, 1, 1],
+    )?;
+    let writer = sql_write
+        .with_schema(Some(schema_sql))
+        .with_table(Some("COVID19MEXICO"))
+        .if_exists(IfExistsOption::Replace);
+    assert!(writer
+        .clone()
+        .with_foreign_keys(true)
+        .finish(&mut covid.clone())
+        .is_err());
+    writer.clone().finish(&mut covid.clone())?;
+
+    let report = writer.check_foreign_keys()?;
+    assert_eq!(report.n_rows(), 7);
+    let mun = report
+        .violations
+        .iter()
+        .find(|v| v.column == "MUNICIPIO_RES")
+        .unwrap();
+    assert_eq!(mun.parent_table, "MUNICIPIOS");
+    assert_eq!(
+        mun.orphan_values,
+        vec![("9999".to_string(), 2), ("15001".to_string(), 1)]
+    );
+    let sexo = report
+        .violations
+        .iter()
+        .find(|v| v.column == "SEXO")
+        .unwrap();
+    assert_eq!(sexo.n_rows, 4);
+    // Sin tabla se revisa toda la base
+    assert_eq!(SqlWriter::new(&path)?.check_foreign_keys()?.n_rows(), 7);
+    // Un catálogo con registros que lo apuntan solo se puede reemplazar si
+    // las llaves foráneas no se hacen cumplir
+    let mut municipios = df!(
+        "CLAVE" => [9002u64, 9003, 9999],
+        "DESCRIPCIÓN" => ["AZCAPOTZALCO", "COYOACÁN", "NO ESPECIFICADO"],
+    )?;
+    let mut catalog = SqlWriter::new(&path)?
+        .with_index(false)
+        .with_table(Some("MUNICIPIOS"))
+        .if_exists(IfExistsOption::Replace);
+    assert!(catalog
+        .clone()
+        .with_foreign_keys(true)
+        .finish(&mut municipios.clone())
+        .is_err());
+    catalog.finish(&mut municipios)?;
+    Ok(())
+}
+fn synthetic_frame(n: usize) -> PolarsResult<DataFrame> {
+    df!(
+        "ID_REGISTRO" => (0..n).map(|i| format!("z{:07x}", i)).collect::<Vec<_>>(),
+        "ENTIDAD_RES" => (0..n).map(|i| (i % 32 + 1) as u64).collect::<Vec<_>>(),
+        "EDAD" => (0..n).map(|i| (i % 110) as i64).collect::<Vec<_>>(),
+        "FECHA_INGRESO" => (0..n).map(|i| format!("2021-{:02}-{:02}", i % 12 + 1, i % 28 + 1)).collect::<Vec<_>>(),
+    )
+}
+#[test]
+fn test_bulk_load_profile() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("bulk_load");
+    let sql_write = SqlWriter::new_bulk(&path, BulkLoadProfile::default())?
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false);
+    sql_write
+        .clone()
+        .if_exists(IfExistsOption::Replace)
+        .with_batch_size(NonZeroUsize::new(1_000).unwrap())
+        .finish(&mut synthetic_frame(5_000)?)?;
+    sql_write.end_bulk_load()?;
+
+    let rt = Runtime::new()?;
+    let pool = rt.block_on(sqlx::SqlitePool::connect(&format!(
+        "sqlite://{}",
+        path.display()
+    )))?;
+    let journal_mode: String =
+        rt.block_on(sqlx::query_scalar("PRAGMA journal_mode").fetch_one(&pool))?;
+    assert_eq!(journal_mode, "delete");
+    let n: i64 =
+        rt.block_on(sqlx::query_scalar("SELECT COUNT(*) FROM COVID19MEXICO").fetch_one(&pool))?;
+    assert_eq!(n, 5_000);
+    // ANALYZE deja las estadísticas para el planificador
+    let stats: i64 =
+        rt.block_on(sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_stat1").fetch_one(&pool))?;
+    assert!(stats > 0);
+    Ok(())
+}
+#[tokio::test(flavor = "multi_thread")]
+async fn test_sql_writer_async() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("async");
+    let sql_write = SqlWriter::connect(&path)
+        .await?
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false)
+        .if_exists(IfExistsOption::Append);
+    let mut writer = sql_write.clone();
+    tokio::spawn(async move { writer.finish_async(&mut synthetic_frame(100)?).await }).await??;
+    // La API bloqueante reutiliza el runtime en el que ya estamos
+    sql_write.clone().finish(&mut synthetic_frame(150)?)?;
+    let df = sql_write.reader().finish_async().await?;
+    assert_eq!(df.height(), 250);
+    Ok(())
+}
+#[tokio::test]
+async fn test_sql_writer_blocking_in_current_thread() -> Result<(), color_eyre::eyre::Error> {
+    let sql_write = SqlWriter::connect(temp_db("current_thread")).await?;
+    assert!(sql_write
+        .with_index(false)
+        .finish(&mut synthetic_frame(10)?)
+        .is_err());
+    Ok(())
+}
+#[test]
+fn test_create_table_pg() -> Result<(), color_eyre::eyre::Error> {
+    let df = df!(
+        "ID_REGISTRO" => ["z1", "z2"],
+        "ENTIDAD_RES" => [1u64, 2],
+        "FECHA_INGRESO" => [NaiveDate::from_ymd_opt(2021, 1, 1), None],
+        "TASA" => [1.5f64, 2.0],
+    )?;
+    let schema = SqliteSchema::from_polars_schema(df.schema())
+        .add_schema(&SqliteSchema::new(
+            "ENTIDAD_RES",
+            SqliteColOption::default()
+                .with_type_sql(SqliteDataType::INTEGER)
+                .foreign_key("ENTIDADES", "CLAVE_ENTIDAD"),
+        ))
+        .add_schema(&SqliteSchema::new(
+            "TASA",
+            SqliteColOption::default().with_type_sql(SqliteDataType::NUMERIC(Some(10), Some(2))),
+        ));
+    let index = SqliteSchema::new(
+        "Id",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .with_auto_increment(true),
+    )
+    .add_schema(&schema);
+    let qry = create_table_pg(&index, df.schema(), "COVID19MEXICO", true);
+    assert!(qry.contains("Id BIGINT GENERATED BY DEFAULT AS IDENTITY"));
+    assert!(qry.contains("ID_REGISTRO TEXT"));
+    assert!(qry.contains("ENTIDAD_RES BIGINT"));
+    assert!(qry.contains("FECHA_INGRESO DATE"));
+    assert!(qry.contains("TASA NUMERIC(10, 2)"));
+    assert!(qry.contains("FOREIGN KEY (ENTIDAD_RES) REFERENCES ENTIDADES(CLAVE_ENTIDAD)"));
+    let qry = create_table_pg(&index, df.schema(), "COVID19MEXICO", false);
+    assert!(!qry.contains("FOREIGN KEY"));
+
+    let csv = String::from_utf8(df_to_csv(&df.select(["ID_REGISTRO", "FECHA_INGRESO"])?)?)?;
+    assert_eq!(csv, "\"z1\",\"2021-01-01\"\n\"z2\",\n");
+    let bytes = df!("DATOS" => [Some(&b"\x00\xff"[..]), None])?;
+    assert_eq!(String::from_utf8(df_to_csv(&bytes)?)?, "\"\\x00ff\"\n\n");
+    Ok(())
+}
+#[test]
+#[ignore = "requires PostgreSQL, set PG_TEST_URL"]
+fn test_pg_writer() -> Result<(), color_eyre::eyre::Error> {
+    let url = env::var("PG_TEST_URL")?;
+    let sql_write = SqlWriter::new_postgres(&url)?
+        .with_table(Some("COVID19MEXICO"))
+        .with_batch_size(NonZeroUsize::new(1_000).unwrap());
+    sql_write
+        .clone()
+        .if_exists(IfExistsOption::Replace)
+        .finish(&mut synthetic_frame(5_000)?)?;
+    assert!(sql_write.clone().finish(&mut synthetic_frame(10)?).is_err());
+    // Sin inserción estricta las llaves repetidas se ignoran
+    let mut cat = df!("CLAVE" => [1i64, 2, 3], "NOMBRE" => ["a", "b", "c"])?;
+    let cat_write = sql_write
+        .clone()
+        .with_table(Some("CAT_PRUEBA"))
+        .with_index(false)
+        .if_exists(IfExistsOption::Replace)
+        .with_schema(Some(SqliteSchema::new(
+            "CLAVE",
+            SqliteColOption::default()
+                .with_type_sql(SqliteDataType::INTEGER)
+                .with_primary_key(true),
+        )));
+    cat_write.clone().finish(&mut cat)?;
+    cat_write
+        .clone()
+        .if_exists(IfExistsOption::Append)
+        .with_strict_insert(false)
+        .finish(&mut df!("CLAVE" => [3i64, 4], "NOMBRE" => ["x", "d"])?)?;
+    // Con upsert la llave repetida toma el valor nuevo
+    cat_write
+        .if_exists(IfExistsOption::Append)
+        .with_upsert_key(Some("CLAVE"))
+        .finish(&mut df!("CLAVE" => [2i64], "NOMBRE" => ["B"])?)?;
+
+    let pool = sql_write.backend().pool();
+    let n: i64 =
+        block_on(sqlx::query_scalar("SELECT COUNT(*) FROM COVID19MEXICO").fetch_one(pool))??;
+    assert_eq!(n, 5_000);
+    let n: i64 = block_on(sqlx::query_scalar("SELECT COUNT(*) FROM CAT_PRUEBA").fetch_one(pool))??;
+    assert_eq!(n, 4);
+    let names: Vec<String> = block_on(
+        sqlx::query_scalar("SELECT NOMBRE FROM CAT_PRUEBA ORDER BY CLAVE").fetch_all(pool),
+    )??;
+    assert_eq!(names, vec!["a", "B", "c", "d"]);
+    Ok(())
+}
+#[test]
+fn test_duckdb_plan() -> Result<(), color_eyre::eyre::Error> {
+    let df = df!(
+        "CLAVE_ENTIDAD" => [1i64, 2],
+        "FECHA_INGRESO" => [NaiveDate::from_ymd_opt(2021, 1, 1), None],
+    )?;
+    let schema = SqliteSchema::new(
+        "Id",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .with_auto_increment(true),
+    )
+    .add_schema(&SqliteSchema::from_polars_schema(df.schema()))
+    .add_schema(&SqliteSchema::new(
+        "CLAVE_ENTIDAD",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .foreign_key("ENTIDADES", "CLAVE"),
+    ));
+    let mut options = WriteOptions {
+        table_name: "COVID19MEXICO".into(),
+        schema,
+        if_exists: IfExistsOption::Replace,
+        migrate: MigrateOption::Ignore,
+        foreign_keys: true,
+        date_format: SqliteDateFormat::Iso8601,
+        strict_insert: false,
+        upsert_key: None,
+        batch_size: NonZeroUsize::new(1024).unwrap(),
+        n_threads: 1,
+        parallel: false,
+        progress: None,
+    };
+    let plan = duckdb_plan(df.schema(), &options);
+    let setup = plan.setup.join("\n");
+    assert!(setup.starts_with("DROP TABLE IF EXISTS COVID19MEXICO;"));
+    assert!(setup.contains("CREATE SEQUENCE IF NOT EXISTS COVID19MEXICO_Id_seq;"));
+    assert!(setup.contains("Id BIGINT DEFAULT nextval('COVID19MEXICO_Id_seq')"));
+    assert!(setup.contains("FECHA_INGRESO DATE"));
+    assert!(setup.contains("FOREIGN KEY (CLAVE_ENTIDAD) REFERENCES ENTIDADES(CLAVE)"));
+    // El Appender escribe en una tabla con solo las columnas del DataFrame
+    assert_eq!(plan.stage, "COVID19MEXICO__stage");
+    assert!(setup.ends_with(
+        "CREATE TABLE COVID19MEXICO__stage AS \
+         SELECT CLAVE_ENTIDAD,FECHA_INGRESO FROM COVID19MEXICO LIMIT 0;"
+    ));
+    assert_eq!(
+        plan.merge,
+        "INSERT OR IGNORE INTO COVID19MEXICO (CLAVE_ENTIDAD,FECHA_INGRESO) \
+         SELECT CLAVE_ENTIDAD,FECHA_INGRESO FROM COVID19MEXICO__stage;"
+    );
+    assert_eq!(plan.cleanup, "DROP TABLE COVID19MEXICO__stage;");
+    // Solo se abre la versión de `duckdb.h` que copia el backend
+    assert!(duckdb_version_supported(&format!("{}.3", DUCKDB_VERSION)));
+    assert!(!duckdb_version_supported(&format!("{}0.0", DUCKDB_VERSION)));
+    assert!(!duckdb_version_supported("v0.9.2"));
+    // Con upsert solo queda la última fila de cada clave
+    options.upsert_key = Some("CLAVE_ENTIDAD".into());
+    assert_eq!(
+        duckdb_plan(df.schema(), &options).merge,
+        "INSERT INTO COVID19MEXICO (CLAVE_ENTIDAD,FECHA_INGRESO) \
+         SELECT DISTINCT ON (CLAVE_ENTIDAD) CLAVE_ENTIDAD,FECHA_INGRESO FROM COVID19MEXICO__stage \
+         ORDER BY CLAVE_ENTIDAD, rowid DESC \
+         ON CONFLICT (CLAVE_ENTIDAD) DO UPDATE SET FECHA_INGRESO = excluded.FECHA_INGRESO;"
+    );
+    Ok(())
+}
+// Las pruebas con DuckDB corren si se encuentra `libduckdb` o la que indica
+// `DUCKDB_LIB`; sin ella se omiten, con `DUCKDB_LIB` mal puesta fallan
+#[cfg(feature = "duckdb")]
+fn duckdb_available() -> bool {
+    match DuckDbBackend::new(env::temp_dir()).version() {
+        Ok(_) => true,
+        Err(e) if env::var_os("DUCKDB_LIB").is_none() => {
+            eprintln!("se omite, sin libduckdb: {}", e);
+            false
+        }
+        Err(e) => panic!("DUCKDB_LIB: {}", e),
+    }
+}
+#[cfg(feature = "duckdb")]
+#[test]
+fn test_duckdb_writer() -> Result<(), color_eyre::eyre::Error> {
+    if !duckdb_available() {
+        return Ok(());
+    }
+    let path = env::temp_dir().join("db_cov19mx_duckdb.duckdb");
+    let _ = fs::remove_file(&path);
+    let sql_write = SqlWriter::new_duckdb(&path)
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false)
+        .with_schema(Some(SqliteSchema::new(
+            "ID_REGISTRO",
+            SqliteColOption::default().with_primary_key(true),
+        )));
+    let written = sql_write
+        .clone()
+        .if_exists(IfExistsOption::Replace)
+        .finish(&mut synthetic_frame(5_000)?)?;
+    assert_eq!(written, 5_000);
+    assert!(sql_write.clone().finish(&mut synthetic_frame(10)?).is_err());
+    // Sin inserción estricta las claves repetidas se ignoran
+    let written = sql_write
+        .clone()
+        .if_exists(IfExistsOption::Append)
+        .with_strict_insert(false)
+        .finish(&mut synthetic_frame(5_010)?)?;
+    assert_eq!(written, 10);
+    // Con upsert una clave repetida en el mismo DataFrame se queda con la última
+    let mut df = synthetic_frame(3)?;
+    df.vstack_mut(
+        &synthetic_frame(3)?
+            .with_column(Series::new("EDAD".into(), [7i64, 8, 9]))?
+            .clone(),
+    )?;
+    let written = sql_write
+        .if_exists(IfExistsOption::Append)
+        .with_upsert_key(Some("ID_REGISTRO"))
+        .finish(&mut df)?;
+    assert_eq!(written, 3);
+    Ok(())
+}
+// Con DuckDB las llaves foráneas se declaran, COVID19MEXICO queda relacionada
+// con sus catálogos
+#[cfg(feature = "duckdb")]
+#[test]
+fn test_etl_duckdb() -> Result<(), color_eyre::eyre::Error> {
+    if !duckdb_available() {
+        return Ok(());
+    }
+    let dir = env::temp_dir().join("db_cov19mx_etl_duckdb");
+    let _ = fs::remove_dir_all(&dir);
+    let mut config = EtlConfig {
+        paths: EtlPaths {
+            csv_dir: dir.join("data_csv"),
+            dicc_dir: dir.join("data_dicc"),
+            db_dir: dir.join("DB"),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    config.output.parquet = false;
+    config.output.backend = OutputBackend::DuckDb;
+    let path = dir.join("covid.duckdb");
+    config.output.url = Some(path.to_string_lossy().to_string());
+    SyntheticDataset::new(7)
+        .with_rows(300)
+        .write(&config.paths.csv_dir, &config.paths.dicc_dir)?;
+    let etl = Etl::new(config);
+    // Sin los catálogos no se puede crear COVID19MEXICO
+    let err = etl.load(&[]).unwrap_err();
+    assert!(err.to_string().contains("catalogs"), "{}", err);
+    etl.catalogs()?;
+    let summary = etl.load(&[])?;
+    let duckdb = DuckDbBackend::new(&path);
+    assert_eq!(
+        duckdb.query_column("SELECT COUNT(*) FROM COVID19MEXICO")?,
+        vec![Some(summary.limpios().to_string())]
+    );
+    let referenced = |table: &str| {
+        duckdb.query_column(&format!(
+            "SELECT referenced_table FROM duckdb_constraints() \
+             WHERE table_name = '{}' AND constraint_type = 'FOREIGN KEY'",
+            table
+        ))
+    };
+    let covid = referenced("COVID19MEXICO")?;
+    for table in ["MUNICIPIOS", "PAISES", "SEXO"] {
+        assert!(covid.contains(&Some(table.to_string())), "{:?}", covid);
+    }
+    assert_eq!(
+        referenced("PAISES_MAPEO")?,
+        vec![Some("PAISES".to_string())]
+    );
+    // Los catálogos se actualizan con COVID19MEXICO apuntando a ellos
+    etl.catalogs()?;
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+#[test]
+fn test_parquet_export() -> Result<(), color_eyre::eyre::Error> {
+    let dir = env::temp_dir().join("db_cov19mx_parquet");
+    let _ = fs::remove_dir_all(&dir);
+    let source = env::temp_dir().join("db_cov19mx_COVID19MEXICO2021.zip");
+    fs::write(&source, b"archivo de prueba")?;
+    let parquet = ParquetExport::new(&dir)
+        .with_compression(ParquetCompression::Snappy)
+        .with_row_group_size(Some(100))
+        .with_dictionary_version(Some("240708"))
+        .with_sources(vec![&source]);
+    let df = synthetic_frame(2_000)?;
+    // Se escribe en dos lotes como en la carga por partes
+    parquet.write_partitions(&df.slice(0, 1_000), "COVID19MEXICO2021")?;
+    parquet.write_partitions(&df.slice(1_000, 1_000), "COVID19MEXICO2021")?;
+    let mut tables = std::collections::HashMap::new();
+    tables.insert("ENTIDADES".to_string(), df!("CLAVE" => [1i64, 2])?);
+    parquet.write_catalogs(&tables)?;
+    let manifest = parquet.write_manifest()?;
+
+    assert!(dir
+        .join("COVID19MEXICO/ANIO=2021/MES=1/ENTIDAD_RES=1")
+        .is_dir());
+    assert_eq!(manifest, ParquetManifest::read(dir.join("_manifest.json"))?);
+    assert_eq!(manifest.dictionary_version.as_deref(), Some("240708"));
+    assert_eq!(manifest.sources[0].sha256, file_sha256(&source)?);
+    assert_eq!(manifest.partition_by, vec!["ANIO", "MES", "ENTIDAD_RES"]);
+    let n_rows = manifest
+        .files
+        .iter()
+        .filter(|f| f.path.starts_with("COVID19MEXICO/"))
+        .map(|f| f.n_rows)
+        .sum::<usize>();
+    assert_eq!(n_rows, 2_000);
+    assert!(manifest
+        .files
+        .iter()
+        .any(|f| f.path == "catalogos/ENTIDADES.parquet" && f.n_rows == 2));
+    // Al recargar un archivo se borran sus partes anteriores
+    let n_parts = manifest
+        .files
+        .iter()
+        .filter(|f| f.path.starts_with("COVID19MEXICO/"))
+        .count();
+    assert_eq!(parquet.remove_source("COVID19MEXICO2020")?, 0);
+    assert_eq!(parquet.remove_source("COVID19MEXICO2021")?, n_parts);
+    Ok(())
+}
+#[test]
+fn test_parse_dates() -> Result<(), color_eyre::eyre::Error> {
+    let df = df!(
+        "FECHA_INGRESO" => ["2021-01-05", "2021-02-30", "2021-03-01"],
+        "FECHA_DEF" => [Some("9999-99-99"), None, Some("2021-03-09")],
+    )?;
+    assert!(parse_dates(df.clone(), &FECHA_COLUMNS, DateParseMode::Strict).is_err());
+    let (df, report) = parse_dates(df, &FECHA_COLUMNS, DateParseMode::Lenient)?;
+    assert_eq!(
+        report.errors,
+        vec![("FECHA_INGRESO".to_string(), 1, "2021-02-30".to_string())]
+    );
+    assert_eq!(df.column("FECHA_INGRESO")?.dtype(), &DataType::Date);
+    assert_eq!(df.column("FECHA_INGRESO")?.null_count(), 1);
+    assert_eq!(df.column("FECHA_DEF")?.null_count(), 2);
+
+    // Las fechas se guardan como texto ISO-8601 o como día juliano
+    for (name, date_format, type_sql) in [
+        ("dates_iso", SqliteDateFormat::Iso8601, "TEXT"),
+        ("dates_julian", SqliteDateFormat::JulianDay, "REAL"),
+    ] {
+        let path = temp_db(name);
+        let sql_write = SqlWriter::new(&path)?
+            .with_table(Some("COVID19MEXICO"))
+            .with_index(false)
+            .with_date_format(date_format);
+        sql_write.clone().finish(&mut df.clone())?;
+        let reader = sql_write.reader();
+        let rt = Runtime::new()?;
+        let conn = rt.block_on(sqlx::SqlitePool::connect(&format!(
+            "sqlite://{}",
+            path.display()
+        )))?;
+        let typeof_: String = rt.block_on(
+            sqlx::query_scalar(
+                "SELECT typeof(FECHA_DEF) FROM COVID19MEXICO WHERE FECHA_DEF IS NOT NULL",
+            )
+            .fetch_one(&conn),
+        )?;
+        assert_eq!(typeof_, type_sql.to_lowercase());
+        let n: i64 = rt.block_on(
+            sqlx::query_scalar(
+                "SELECT COUNT(*) FROM COVID19MEXICO WHERE date(FECHA_INGRESO) BETWEEN '2021-01-01' AND '2021-03-01'",
+            )
+            .fetch_one(&conn),
+        )?;
+        assert_eq!(n, 2);
+        let stored = reader.finish()?;
+        assert_eq!(stored.height(), 3);
+    }
+    Ok(())
+}
+#[test]
+fn test_sentinel_rules() -> Result<(), color_eyre::eyre::Error> {
+    let mut tables = std::collections::HashMap::new();
+    tables.insert(
+        "SI_NO".to_string(),
+        df!(
+            "CLAVE" => [1u64, 2, 97, 98, 99],
+            "DESCRIPCIÓN" => ["SI", "NO", "NO APLICA", "SE IGNORA", "NO ESPECIFICADO "],
+        )?,
+    );
+    tables.insert(
+        "SEXO".to_string(),
+        df!(
+            "CLAVE" => [1u64, 2, 99],
+            "DESCRIPCIÓN" => ["MUJER", "HOMBRE", "NO ESPECIFICADO"],
+        )?,
+    );
+    let catalog = |table: &str| {
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .foreign_key(table, "CLAVE")
+    };
+    let schema = SqliteSchema::new("INTUBADO", catalog("SI_NO"))
+        .add_schema(&SqliteSchema::new("NEUMONIA", catalog("SI_NO")))
+        .add_schema(&SqliteSchema::new("SEXO", catalog("SEXO")));
+    let rules = SentinelRules::from_catalogs(&tables, &schema)?
+        .with_default_action(SentinelAction::Null)
+        .with_action("NEUMONIA", SentinelAction::Flag)
+        .with_action("SEXO", SentinelAction::Keep);
+    assert_eq!(rules.codes("INTUBADO"), Some(&vec![97, 98, 99]));
+    assert_eq!(rules.codes("SEXO"), Some(&vec![99]));
+
+    let df = df!(
+        "INTUBADO" => [1u64, 97, 99],
+        "NEUMONIA" => [2u64, 98, 1],
+        "SEXO" => [1u64, 2, 99],
+    )?;
+    let df = rules.apply(df.lazy())?.collect()?;
+    let values = |c: &str| -> PolarsResult<Vec<Option<u64>>> {
+        Ok(df.column(c)?.u64()?.into_iter().collect())
+    };
+    assert_eq!(values("INTUBADO")?, vec![Some(1), None, None]);
+    assert_eq!(values("NEUMONIA")?, vec![Some(2), None, Some(1)]);
+    assert_eq!(values("NEUMONIA_CENTINELA")?, vec![None, Some(98), None]);
+    assert_eq!(values("SEXO")?, vec![Some(1), Some(2), Some(99)]);
+    Ok(())
+}
+#[test]
+fn test_quality_report() -> Result<(), color_eyre::eyre::Error> {
+    let mut tables = std::collections::HashMap::new();
+    tables.insert(
+        "SEXO".to_string(),
+        df!(
+            "CLAVE" => [1u64, 2, 99],
+            "DESCRIPCIÓN" => ["MUJER", "HOMBRE", "NO ESPECIFICADO"],
+        )?,
+    );
+    let schema = SqliteSchema::new(
+        "SEXO",
+        SqliteColOption::default()
+            .with_type_sql(SqliteDataType::INTEGER)
+            .foreign_key("SEXO", "CLAVE"),
+    );
+    let df = df!(
+        "FECHA_ACTUALIZACION" => ["2024-01-02", "2024-01-02", "2024-01-02", "2024-01-09", "2024-01-09"],
+        "ID_REGISTRO" => ["a", "a", "b", "a", "c"],
+        "SEXO" => [Some(1i64), Some(3), None, Some(2), Some(7)],
+        "EDAD" => [30i64, -1, 130, 45, 121],
+        "FECHA_INGRESO" => ["2024-01-01", "2024-01-01", "2023-12-30", "2024-01-05", "2024-01-05"],
+        "FECHA_DEF" => ["9999-99-99", "2023-12-31", "2023-12-30", "2024-01-04", "9999-99-99"],
+    )?;
+    let report = QualityCheck::new()
+        .with_catalogs(&tables, &schema)?
+        .check(df.lazy(), "COVID19MEXICO.csv")?;
+    let metric = |fecha: &str, metrica: &str, columna: Option<&str>| {
+        report
+            .metrics
+            .iter()
+            .find(|m| {
+                m.fecha_actualizacion.as_deref() == Some(fecha)
+                    && m.metrica == metrica
+                    && m.columna.as_deref() == columna
+            })
+            .map(|m| (m.valor, m.registros))
+    };
+    assert_eq!(metric("2024-01-02", METRIC_ROWS, None), Some((3, 3)));
+    assert_eq!(
+        metric("2024-01-02", METRIC_NULLS, Some("SEXO")),
+        Some((1, 3))
+    );
+    assert_eq!(
+        metric("2024-01-02", METRIC_OUT_OF_CATALOG, Some("SEXO")),
+        Some((1, 3))
+    );
+    assert_eq!(
+        metric("2024-01-09", METRIC_OUT_OF_CATALOG, Some("SEXO")),
+        Some((1, 2))
+    );
+    assert_eq!(
+        metric("2024-01-02", METRIC_IMPOSSIBLE_AGE, Some("EDAD")),
+        Some((2, 3))
+    );
+    assert_eq!(
+        metric(
+            "2024-01-02",
+            METRIC_DEATH_BEFORE_ADMISSION,
+            Some("FECHA_DEF")
+        ),
+        Some((1, 3))
+    );
+    assert_eq!(
+        metric("2024-01-02", METRIC_DUPLICATED_ID, Some("ID_REGISTRO")),
+        Some((1, 3))
+    );
+    assert_eq!(
+        metric("2024-01-09", METRIC_DUPLICATED_ID, Some("ID_REGISTRO")),
+        Some((0, 2))
+    );
+    assert_eq!(report.total(METRIC_IMPOSSIBLE_AGE, Some("EDAD")), 3);
+    // Los reportes de dos lotes se suman métrica por métrica
+    let mut merged = report.clone();
+    merged.merge(report.clone());
+    assert_eq!(merged.metrics.len(), report.metrics.len());
+    assert_eq!(merged.total(METRIC_IMPOSSIBLE_AGE, Some("EDAD")), 6);
+    assert!(merged
+        .metrics
+        .iter()
+        .zip(&report.metrics)
+        .all(|(m, r)| m.registros == 2 * r.registros && m.tasa == r.tasa));
+
+    let path = env::temp_dir().join("db_cov19mx_quality.json");
+    report.write_json(&path)?;
+    let read: QualityReport = serde_json::from_reader(fs::File::open(&path)?)?;
+    assert_eq!(read, report);
+    assert_eq!(report.to_frame()?.height(), report.metrics.len());
+    Ok(())
+}
+#[test]
+fn test_quarantine() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("quarantine");
+    let sql_write = SqlWriter::new(&path)?
+        .with_table(Some("COVID19MEXICO"))
+        .with_index(false);
+    let keys = Column::new("ID_REGISTRO".into(), ["a1", "b2"]);
+    assert!(existing_keys(
+        &sql_write.reader(),
+        "COVID19MEXICO",
+        "ID_REGISTRO",
+        &keys,
+        None
+    )?
+    .is_empty());
+    let schema = SqliteSchema::new(
+        "ID_REGISTRO",
+        SqliteColOption::default().with_primary_key(true),
+    );
+    sql_write
+        .clone()
+        .with_schema(Some(schema))
+        .finish(&mut df!("ID_REGISTRO" => ["a1"], "EDAD" => [34])?)?;
+
+    let df = df!(
+        "ID_REGISTRO" => [Some("a1"), Some("b2"), None, Some("b2"), Some("c3")],
+        "EDAD" => [34, 20, 51, 21, 60],
+    )?;
+    let existing = |filter: Option<SqlPredicate>| {
+        existing_keys(
+            &sql_write.reader(),
+            "COVID19MEXICO",
+            "ID_REGISTRO",
+            df.column("ID_REGISTRO")?,
+            filter,
+        )
+    };
+    assert!(existing(Some(sql_col("EDAD").gt(40)))?.is_empty());
+    let existing = existing(None)?;
+    assert_eq!(existing.len(), 1);
+    let mut quarantine = Quarantine::default();
+    let (mut accepted, rejected) =
+        quarantine.split(df, &existing, "COVID19MEXICO2024.csv", 900_000)?;
+    assert_eq!(accepted.height(), 2);
+    assert_eq!(
+        rejected
+            .column("MOTIVO")?
+            .str()?
+            .into_iter()
+            .collect::<Vec<_>>(),
+        vec![
+            Some("DUPLICADO_TABLA"),
+            Some("CLAVE_NULA"),
+            Some("DUPLICADO_LOTE")
+        ]
+    );
+    assert_eq!(rejected.column("EDAD")?.str()?.get(2), Some("21"));
+    assert_eq!(rejected.column("OFFSET_LOTE")?.i64()?.get(0), Some(900_000));
+    assert_eq!(quarantine.n_rows(), 3);
+    assert_eq!(quarantine.count(RejectReason::DuplicateInBatch), 1);
+    // El municipio se revisa con la clave que arma la limpieza, los nulos pasan
+    let mut municipios = Quarantine::default().with_catalog(
+        municipio_res(),
+        Arc::new(HashSet::from(["9002".to_string()])),
+    );
+    let (kept, outside) = municipios.split(
+        df!(
+            "ID_REGISTRO" => ["d4", "e5", "f6"],
+            "ENTIDAD_RES" => ["09", "09", "09"],
+            "MUNICIPIO_RES" => [Some("002"), Some("900"), None],
+        )?,
+        &HashSet::new(),
+        "COVID19MEXICO2024.csv",
+        0,
+    )?;
+    assert_eq!(kept.height(), 2);
+    assert_eq!(outside.column("MUNICIPIO_RES")?.str()?.get(0), Some("900"));
+    assert_eq!(
+        outside.column("MOTIVO")?.str()?.get(0),
+        Some("FUERA_DE_CATALOGO")
+    );
+    assert_eq!(municipios.count(RejectReason::OutOfCatalog), 1);
+    // Sin los rechazados la inserción estricta no falla
+    sql_write
+        .clone()
+        .if_exists(IfExistsOption::Append)
+        .with_strict_insert(true)
+        .finish(&mut accepted)?;
+    assert_eq!(sql_write.reader().finish()?.height(), 3);
+
+    // Las claves con una publicación más reciente se encuentran con las
+    // fechas como texto o como día juliano
+    let (df, _) = parse_dates(
+        df!(
+            "ID_REGISTRO" => ["a1", "b2", "c3"],
+            "FECHA_ACTUALIZACION" => ["2024-01-02", "2024-01-09", "2024-01-16"],
+        )?,
+        &["FECHA_ACTUALIZACION"],
+        DateParseMode::Strict,
+    )?;
+    for (name, date_format) in [
+        ("newer_iso", SqliteDateFormat::Iso8601),
+        ("newer_julian", SqliteDateFormat::JulianDay),
+    ] {
+        let sql_write = SqlWriter::new(temp_db(name))?
+            .with_table(Some("COVID19MEXICO"))
+            .with_index(false)
+            .with_date_format(date_format);
+        sql_write.clone().finish(&mut df.clone())?;
+        let output = OutputSql::Sqlite(sql_write.backend().clone());
+        let newer = output.newer_keys(
+            "COVID19MEXICO",
+            "ID_REGISTRO",
+            df.column("ID_REGISTRO")?,
+            "2024-01-09",
+        )?;
+        assert_eq!(newer, HashSet::from(["c3".to_string()]), "{}", name);
+        assert_eq!(output.count("COVID19MEXICO")?, Some(3));
+        assert!(!output.foreign_keys());
+    }
+    Ok(())
+}
+#[test]
+fn test_ingest_ledger() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("ledger");
+    let sql_write = SqlWriter::new(&path)?.with_index(false);
+    let mut ledger = IngestLedger::open(&sql_write)?;
+    assert!(ledger.entries().is_empty());
+    let lf = df!("FECHA_ACTUALIZACION" => ["2024-01-02", "2024-01-09"])?.lazy();
+    let release = release_date(&lf)?;
+    assert_eq!(release.as_deref(), Some("2024-01-09"));
+    let entry = LedgerEntry::new("COVID19MEXICO2024.csv", "abc", release);
+    ledger.record(entry.clone())?;
+    assert!(!IngestLedger::open(&sql_write)?.is_loaded("abc"));
+    ledger.record(entry.with_status(IngestStatus::Loaded, 2))?;
+    let ledger = IngestLedger::open(&sql_write)?;
+    assert_eq!(ledger.entries().len(), 1);
+    assert_eq!(ledger.entries()[0].registros, 2);
+    assert!(ledger.is_loaded("abc"));
+    assert_eq!(
+        ledger.last_release("COVID19MEXICO2024.csv"),
+        Some("2024-01-09")
+    );
+
+    // Una publicación nueva actualiza los registros que ya estaban
+    let writer = sql_write
+        .clone()
+        .with_table(Some("COVID19MEXICO"))
+        .with_schema(Some(SqliteSchema::new(
+            "ID_REGISTRO",
+            SqliteColOption::default().with_primary_key(true),
+        )))
+        .if_exists(IfExistsOption::Append)
+        .with_upsert_key(Some("ID_REGISTRO"));
+    writer.clone().finish(&mut df!(
+        "ID_REGISTRO" => ["a1", "b2"],
+        "CLASIFICACION_FINAL" => [6, 6],
+    )?)?;
+    let written = writer.clone().finish(&mut df!(
+        "ID_REGISTRO" => ["b2", "c3"],
+        "CLASIFICACION_FINAL" => [3, 7],
+    )?)?;
+    assert_eq!(written, 2);
+    let df = writer
+        .reader()
+        .filter(sql_col("ID_REGISTRO").eq("b2"))
+        .finish()?;
+    assert_eq!(df.column("CLASIFICACION_FINAL")?.i64()?.get(0), Some(3));
+    assert_eq!(writer.reader().finish()?.height(), 3);
+    Ok(())
+}
+#[test]
+fn test_pipeline() -> Result<(), color_eyre::eyre::Error> {
+    let config = PipelineConfig {
+        readers: 3,
+        workers: Some(4),
+        memory_budget_mb: 1,
+        channel_capacity: 1,
+    };
+    let pipeline = Pipeline::new(&config);
+    assert_eq!(pipeline.batch_rows(1024), 128);
+    // Cada fuente manda 20 lotes de 100 KiB, nunca hay más de 1 MiB en proceso
+    let kib = 100 * 1024;
+    let peak = std::sync::atomic::AtomicU64::new(0);
+    let mut written = Vec::new();
+    pipeline.run(
+        5,
+        |i, tx| {
+            for seq in 0..20u64 {
+                if !pipeline.budget().acquire(kib) {
+                    return Ok(());
+                }
+                peak.fetch_max(
+                    pipeline.budget().used(),
+                    std::sync::atomic::Ordering::SeqCst,
+                );
+                if tx.send((i, seq)).is_err() {
+                    return Ok(());
+                }
+            }
+            Ok(())
+        },
+        |(i, seq)| Ok((i, seq * 2)),
+        |item| {
+            written.push(item);
+            pipeline.budget().release(kib);
+            Ok(true)
+        },
+    )?;
+    assert_eq!(written.len(), 100);
+    assert!(peak.into_inner() <= pipeline.budget().limit());
+    assert_eq!(pipeline.budget().used(), 0);
+    // Los lotes de una fuente pueden llegar en otro orden, pero llegan todos
+    for i in 0..5 {
+        let mut seqs = written
+            .iter()
+            .filter(|(f, _)| *f == i)
+            .map(|(_, s)| *s)
+            .collect::<Vec<_>>();
+        seqs.sort();
+        assert_eq!(seqs, (0..20).map(|s| s * 2).collect::<Vec<_>>());
+    }
+
+    // El escritor detiene el pipeline sin esperar a que se lea todo
+    let pipeline = Pipeline::new(&config);
+    let mut n = 0;
+    pipeline.run(
+        4,
+        |i, tx| {
+            for seq in 0.. {
+                if pipeline.is_stopped() || tx.send((i, seq)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        },
+        Ok,
+        |_| {
+            n += 1;
+            Ok(n < 10)
+        },
+    )?;
+    assert_eq!(n, 10);
+    assert!(pipeline.is_stopped());
+
+    // Los errores de cualquier etapa llegan a quien llama
+    let pipeline = Pipeline::new(&config);
+    let res = pipeline.run(
+        2,
+        |i, tx| {
+            if i == 1 {
+                return Err(color_eyre::eyre::eyre!("no se pudo leer"));
+            }
+            let _ = tx.send(i);
+            Ok(())
+        },
+        Ok,
+        |_| Ok(true),
+    );
+    assert!(res.unwrap_err().to_string().contains("no se pudo leer"));
+    let pipeline = Pipeline::new(&config);
+    let res = pipeline.run(
+        3,
+        |i, tx| {
+            let _ = tx.send(i);
+            Ok(())
+        },
+        |i: usize| {
+            if i == 2 {
+                Err(color_eyre::eyre::eyre!("lote inválido"))
+            } else {
+                Ok(i)
+            }
+        },
+        |_| Ok(true),
+    );
+    assert!(res.unwrap_err().to_string().contains("lote inválido"));
+    Ok(())
+}
+#[test]
+fn test_csv_batches() -> Result<(), color_eyre::eyre::Error> {
+    let path = env::temp_dir().join("db_cov19mx_batches.csv");
+    let mut file = fs::File::create(&path)?;
+    writeln!(file, "ID_REGISTRO,EDAD,PAIS_ORIGEN")?;
+    for i in 0..10_000 {
+        writeln!(file, "{:06x},{},97", i, i % 100)?;
+    }
+    drop(file);
+    let schema = Arc::new(Schema::from_iter([Field::new(
+        "ID_REGISTRO".into(),
+        DataType::String,
+    )]));
+    let full = LazyCsvReader::new(&path)
+        .with_has_header(true)
+        .with_dtype_overwrite(Some(schema.clone()))
+        .finish()?
+        .collect()?;
+    let mut batches = CsvBatchReader::new(&path)
+        .with_dtype_overwrite(Some(schema.clone()))
+        .with_batch_size(3_000)
+        .finish()?;
+    let mut heights = Vec::new();
+    let mut offsets = Vec::new();
+    let mut read = DataFrame::empty_with_schema(full.schema());
+    while let Some(df) = batches.next().transpose()? {
+        offsets.push(batches.offset());
+        heights.push(df.height());
+        read.vstack_mut(&df)?;
+    }
+    assert_eq!(heights, vec![3_000, 3_000, 3_000, 1_000]);
+    assert_eq!(offsets, vec![3_000, 6_000, 9_000, 10_000]);
+    assert!(read.equals(&full));
+    // Al reanudar se empieza en la fila indicada
+    let rest = CsvBatchReader::new(&path)
+        .with_dtype_overwrite(Some(schema))
+        .with_skip_rows(9_500)
+        .with_batch_size(3_000)
+        .finish()?
+        .collect::<Result<Vec<_>, _>>()?;
+    assert_eq!(rest.len(), 1);
+    assert!(rest[0].equals(&full.slice(9_500, 500)));
+    fs::remove_file(&path)?;
+    Ok(())
+}
+#[test]
+fn test_synthetic_dataset() -> Result<(), color_eyre::eyre::Error> {
+    let dir = env::temp_dir().join("db_cov19mx_synthetic");
+    let _ = fs::remove_dir_all(&dir);
+    let (csv_dir, dicc_dir) = (dir.join("data_csv"), dir.join("data_dicc"));
+    let dataset = SyntheticDataset::new(7)
+        .with_rows(2_000)
+        .with_years(&[2020, 2021]);
+    let files = dataset.write(&csv_dir, &dicc_dir)?;
+    assert_eq!(
+        files,
+        vec![
+            csv_dir.join("COVID19MEXICO2020.csv"),
+            csv_dir.join("COVID19MEXICO2021.csv")
+        ]
+    );
+    // La misma semilla da los mismos archivos, otra semilla no
+    let sha = file_sha256(&files[0])?;
+    dataset.write_csv(2020, dir.join("again.csv"))?;
+    assert_eq!(file_sha256(dir.join("again.csv"))?, sha);
+    SyntheticDataset::new(8)
+        .with_rows(2_000)
+        .write_csv(2020, dir.join("other.csv"))?;
+    assert_ne!(file_sha256(dir.join("other.csv"))?, sha);
+
+    // El diccionario se lee igual que el publicado
+    let descriptors = dicc_dir.join(DESCRIPTORS_FILE);
+    let schema = get_schema_pl(&descriptors)?;
+    assert_eq!(
+        schema.iter_names().map(|c| c.as_str()).collect::<Vec<_>>(),
+        COVID_COLUMNS.to_vec()
+    );
+    assert_eq!(schema.get("SEXO"), Some(&DataType::UInt64));
+    assert_eq!(schema.get("EDAD"), Some(&DataType::Int64));
+    let mut schema_sql = get_schema_sql(&descriptors)?;
+    let fk = |schema: &SqliteSchema, column: &str| {
+        schema
+            .iter_fields()
+            .find(|(c, _)| c.as_str() == column)
+            .and_then(|(_, o)| o.foreign_key_ref().map(|(t, _)| t.to_string()))
+    };
+    assert_eq!(fk(&schema_sql, "INTUBADO").as_deref(), Some("SI_NO"));
+    assert_eq!(
+        fk(&schema_sql, "MUNICIPIO_RES").as_deref(),
+        Some("MUNICIPIOS")
+    );
+    let tables = get_df_cat(dicc_dir.join(CATALOGS_FILE))?;
+    for table in [
+        "ENTIDADES",
+        "MUNICIPIOS",
+        "SI_NO",
+        "SEXO",
+        "CLASIFICACION_FINAL_COVID",
+    ] {
+        assert!(tables.contains_key(table), "falta el catálogo {}", table);
+    }
+    assert_eq!(
+        SentinelRules::from_catalogs(&tables, &schema_sql)?.codes("INTUBADO"),
+        Some(&vec![97, 98, 99])
+    );
+
+    // Todas las claves están en su catálogo, incluidos municipios y países
+    let scan = |path: &Path| {
+        LazyCsvReader::new(path)
+            .with_has_header(true)
+            .with_dtype_overwrite(Some(schema.clone()))
+            .finish()
+    };
+    let mut countries = CountryDictionary::new();
+    get_unique_contry(&scan(&files[0])?, &mut countries)?;
+    let mut tables = tables;
+    tables.insert("PAISES".into(), countries.frame()?);
+    for column in ["PAIS_NACIONALIDAD", "PAIS_ORIGEN"] {
+        schema_sql.with_column(
+            column,
+            SqliteColOption::default()
+                .with_type_sql(SqliteDataType::INTEGER)
+                .foreign_key("PAISES", "CLAVE"),
+        );
+    }
+    assert!(countries.unmatched().is_empty());
+    let quality = QualityCheck::new().with_catalogs(&tables, &schema_sql)?;
+    let lf = clean_data_covid(scan(&files[0])?, &countries);
+    let report = quality.check(lf.clone(), "COVID19MEXICO2020.csv")?;
+    assert_eq!(report.total(METRIC_ROWS, None), 2_000);
+    assert!(report
+        .metrics
+        .iter()
+        .filter(|m| m.metrica != METRIC_ROWS)
+        .all(|m| m.valor == 0 || m.metrica == METRIC_NULLS));
+    let (df, dates) = parse_dates(lf.collect()?, &FECHA_COLUMNS, DateParseMode::Strict)?;
+    assert!(dates.is_empty());
+    assert_eq!(df.column("FECHA_ACTUALIZACION")?.n_unique()?, 1);
+
+    // Con anomalías aparecen en el reporte de calidad
+    let path = dir.join("anomalies.csv");
+    dataset
+        .clone()
+        .with_anomaly_rate(0.2)
+        .write_csv(2020, &path)?;
+    let report = quality.check(clean_data_covid(scan(&path)?, &countries), "anomalies.csv")?;
+    for metric in [
+        METRIC_IMPOSSIBLE_AGE,
+        METRIC_DUPLICATED_ID,
+        METRIC_DEATH_BEFORE_ADMISSION,
+    ] {
+        let n: u64 = report
+            .metrics
+            .iter()
+            .filter(|m| m.metrica == metric)
+            .map(|m| m.valor)
+            .sum();
+        assert!(n > 0, "{} sin casos", metric);
+    }
+    // La edad leída como texto da la misma cuenta
+    let mut text_schema = (*schema).clone();
+    text_schema.with_column("EDAD".into(), DataType::String);
+    let text = LazyCsvReader::new(&path)
+        .with_has_header(true)
+        .with_dtype_overwrite(Some(Arc::new(text_schema)))
+        .finish()?;
+    assert_eq!(
+        quality
+            .check(clean_data_covid(text, &countries), "anomalies.csv")?
+            .total(METRIC_IMPOSSIBLE_AGE, Some("EDAD")),
+        report.total(METRIC_IMPOSSIBLE_AGE, Some("EDAD"))
+    );
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+// Cambia el valor de una fila de un CSV, se lee y se escribe todo como texto
+fn set_csv_value(
+    path: &Path,
+    row: usize,
+    column: &str,
+    value: &str,
+) -> Result<(), color_eyre::eyre::Error> {
+    let mut df = LazyCsvReader::new(path)
+        .with_infer_schema_length(Some(0))
+        .finish()?
+        .collect()?;
+    let values = df
+        .column(column)?
+        .str()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, v)| if i == row { Some(value) } else { v })
+        .collect::<StringChunked>()
+        .with_name(column.into());
+    df.with_column(values)?;
+    CsvWriter::new(&mut fs::File::create(path)?).finish(&mut df)?;
+    Ok(())
+}
+#[test]
+fn test_etl_synthetic() -> Result<(), color_eyre::eyre::Error> {
+    let dir = env::temp_dir().join("db_cov19mx_etl_synthetic");
+    let _ = fs::remove_dir_all(&dir);
+    let mut config = EtlConfig {
+        paths: EtlPaths {
+            csv_dir: dir.join("data_csv"),
+            dicc_dir: dir.join("data_dicc"),
+            db_dir: dir.join("DB"),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    config.output.parquet = false;
+    config.writer.batch_size = 500;
+    SyntheticDataset::new(3)
+        .with_rows(1_500)
+        .with_anomaly_rate(0.05)
+        .write(&config.paths.csv_dir, &config.paths.dicc_dir)?;
+    let csv = config.paths.csv_dir.join(SyntheticDataset::csv_name(2020));
+    set_csv_value(&csv, 20, "MUNICIPIO_RES", "900")?;
+    // La misma clave en el primer y el tercer lote
+    set_csv_value(&csv, 10, "ID_REGISTRO", "z047")?;
+    set_csv_value(&csv, 1_200, "ID_REGISTRO", "z047")?;
+    let etl = Etl::new(config);
+    etl.catalogs()?;
+    let summary = etl.load(&[])?;
+    assert_eq!(summary.leidos(), 1_500);
+    assert!(summary.rechazados() > 0);
+    assert_eq!(summary.limpios() + summary.rechazados(), 1_500);
+    assert_eq!(summary.insertados(), summary.limpios());
+    // El reporte de calidad de los tres lotes es el del archivo completo
+    let total = |metrica: &str| -> color_eyre::Result<i64> {
+        let df = SqlWriter::new(etl.paths().db_path())?
+            .reader()
+            .with_query(Some(format!(
+                "SELECT SUM(VALOR) AS N FROM QUALITY_REPORT WHERE METRICA = '{}'",
+                metrica
+            )))
+            .finish()?;
+        Ok(df
+            .column("N")?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .get(0)
+            .unwrap_or_default())
+    };
+    assert_eq!(total(METRIC_ROWS)?, 1_500);
+    // El municipio fuera de catálogo se rechaza con su valor original
+    let outside = SqlWriter::new(etl.paths().db_path())?
+        .reader()
+        .with_query(Some(format!(
+            "SELECT MUNICIPIO_RES FROM {} WHERE MOTIVO = 'FUERA_DE_CATALOGO'",
+            REJECTED_TABLE
+        )))
+        .finish()?;
+    assert_eq!(outside.height(), 1);
+    assert_eq!(outside.column("MUNICIPIO_RES")?.str()?.get(0), Some("900"));
+    let report = etl.validate(&[])?;
+    assert!(report.total(METRIC_IMPOSSIBLE_AGE, Some("EDAD")) > 0);
+    // `validate` agrega el del archivo completo a QUALITY_REPORT
+    for (metrica, columna) in [
+        (METRIC_IMPOSSIBLE_AGE, "EDAD"),
+        (METRIC_DEATH_BEFORE_ADMISSION, "FECHA_DEF"),
+        (METRIC_DUPLICATED_ID, "ID_REGISTRO"),
+    ] {
+        assert_eq!(
+            total(metrica)?,
+            2 * report.total(metrica, Some(columna)) as i64
+        );
+    }
+    // Los catálogos se pueden volver a cargar con COVID19MEXICO ya apuntando a ellos
+    etl.catalogs()?;
+    let summary = etl.load(&[])?;
+    assert_eq!(summary.omitidos, vec!["COVID19MEXICO2020.csv".to_string()]);
+    // Una publicación anterior con las mismas claves se rechaza con los
+    // valores como vienen en el CSV, sin limpiar
+    SyntheticDataset::new(3)
+        .with_rows(1_500)
+        .with_release(NaiveDate::from_ymd_opt(2023, 5, 29).unwrap())
+        .write_csv(2020, csv.with_file_name("COVID19MEXICO2020_anterior.csv"))?;
+    let summary = etl.load(&[])?;
+    assert!(summary.rechazados() > summary.insertados());
+    let sql_write = SqlWriter::new(etl.paths().db_path())?;
+    let older = sql_write
+        .reader()
+        .with_query(Some(format!(
+            "SELECT FECHA_ACTUALIZACION, FECHA_DEF FROM {} \
+             WHERE MOTIVO = 'DUPLICADO_TABLA'",
+            REJECTED_TABLE
+        )))
+        .finish()?;
+    assert_eq!(older.height() as u64, summary.rechazados());
+    assert_eq!(
+        older.column("FECHA_ACTUALIZACION")?.str()?.get(0),
+        Some("2023-05-29")
+    );
+    assert!(older
+        .column("FECHA_DEF")?
+        .str()?
+        .into_iter()
+        .any(|f| f == Some("9999-99-99")));
+    assert!(sql_write.check_foreign_keys()?.is_empty());
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+#[test]
+fn test_etl_harness() -> Result<(), color_eyre::eyre::Error> {
+    let harness = EtlHarness::new(
+        "e2e",
+        SyntheticDataset::new(5)
+            .with_rows(800)
+            .with_years(&[2020, 2021])
+            .with_anomaly_rate(0.03),
+    )?;
+    let summary = harness.run()?;
+    let mut requests = harness.server().requests();
+    requests.sort();
+    assert_eq!(
+        requests,
+        vec![
+            format!("/{}", DICC_ZIP),
+            "/historicos/2020/COVID19MEXICO2020.zip".to_string(),
+            "/historicos/2021/COVID19MEXICO2021.zip".to_string(),
+        ]
+    );
+    assert_eq!(summary.leidos(), 1_600);
+    assert!(summary.rechazados() > 0);
+    let snapshot = harness.snapshot()?;
+    assert!(snapshot.is_consistent(), "{}", snapshot);
+    assert_eq!(
+        snapshot.registros("COVID19MEXICO"),
+        summary.limpios() as i64
+    );
+    assert!(snapshot.registros(REJECTED_TABLE) > 0);
+    assert!(snapshot.registros("PAISES") > 0);
+    assert!(snapshot.registros("PAISES_MAPEO") >= snapshot.registros("PAISES"));
+    assert_eq!(snapshot.paises_faltantes.len(), 3);
+    // Con los mismos archivos la segunda corrida no carga nada
+    let summary = harness.run()?;
+    assert_eq!(summary.omitidos.len(), 2);
+    let again = harness.snapshot()?;
+    assert!(again.is_consistent(), "{}", again);
+    assert_eq!(again.registros, snapshot.registros);
+    let dir = harness.dir().to_path_buf();
+    drop(harness);
+    assert!(!dir.exists());
+
+    // Si falta un zip en el portal la descarga falla antes de cargar
+    let harness = EtlHarness::new("e2e_404", SyntheticDataset::new(5).with_rows(100))?;
+    fs::remove_file(
+        harness
+            .mirror_dir()
+            .join("historicos/2020/COVID19MEXICO2020.zip"),
+    )?;
+    let err = harness.run().unwrap_err();
+    assert!(err.to_string().contains("404"), "{}", err);
+    assert!(!harness.config().paths.db_path().exists());
+    Ok(())
+}
+#[test]
+fn test_checkpoint() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("checkpoint");
+    let sql_write = SqlWriter::new(&path)?.with_index(false);
+    // Bitácora de antes del checkpoint, sin AVANCE
+    sql_write
+        .clone()
+        .with_table(Some(LEDGER_TABLE))
+        .with_schema(Some(SqliteSchema::new(
+            "SHA256",
+            SqliteColOption::default().with_primary_key(true),
+        )))
+        .finish(&mut df!(
+            "ARCHIVO" => ["COVID19MEXICO2021.csv"],
+            "SHA256" => ["abc"],
+            "FECHA_ACTUALIZACION" => ["2024-01-09"],
+            "REGISTROS" => [10i64],
+            "ESTADO" => ["FALLIDO"],
+            "ACTUALIZADO_EN" => ["2024-01-10"],
+        )?)?;
+    let mut ledger = IngestLedger::open(&sql_write)?;
+    assert_eq!(ledger.entries()[0].avance, 0);
+    assert!(ledger.checkpoint("abc").is_none());
+
+    let entry = LedgerEntry::new("COVID19MEXICO2021.csv", "abc", None);
+    ledger.record(entry.clone().with_checkpoint(900, 850))?;
+    let cancel = CancelToken::new();
+    cancel.clone().cancel();
+    assert!(cancel.is_cancelled());
+    ledger.record(
+        entry
+            .clone()
+            .with_checkpoint(1800, 1700)
+            .with_status(IngestStatus::Cancelled, 1700),
+    )?;
+    let mut ledger = IngestLedger::open(&sql_write)?;
+    let checkpoint = ledger.checkpoint("abc").cloned();
+    assert_eq!(checkpoint.as_ref().map(|e| e.avance), Some(1800));
+    assert_eq!(checkpoint.as_ref().map(|e| e.registros), Some(1700));
+    assert_eq!(checkpoint.map(|e| e.estado), Some(IngestStatus::Cancelled));
+
+    // Un archivo completo ya no se reanuda
+    ledger.record(entry.with_status(IngestStatus::Loaded, 2000))?;
+    assert!(IngestLedger::open(&sql_write)?.checkpoint("abc").is_none());
+    Ok(())
+}
+#[test]
+fn test_run_summary() -> Result<(), color_eyre::eyre::Error> {
+    let path = temp_db("run_summary");
+    let sql_write = SqlWriter::new(&path)?.with_index(false);
+    let mut df = df!("ID_REGISTRO" => ["a1", "b2", "c3"])?;
+    let written = sql_write
+        .clone()
+        .with_table(Some("COVID19MEXICO"))
+        .finish(&mut df)?;
+    assert_eq!(written, 3);
+
+    let mut run = RunRecorder::start(&path);
+    run.add_stage("limpieza", std::time::Duration::from_millis(200));
+    run.add_stage("limpieza", std::time::Duration::from_millis(300));
+    assert_eq!(run.time("lectura", || 7), 7);
+    run.observe_batch(&df);
+    run.add_file(FileSummary {
+        archivo: "COVID19MEXICO2024.csv".into(),
+        leidos: 5,
+        limpios: 4,
+        rechazados: 1,
+        insertados: 4,
+        segundos: 2.0,
+        ..Default::default()
+    });
+    run.skip_file("COVID19MEXICO2023.csv");
+    let summary = run.finish(&path);
+    assert_eq!(summary.stage("limpieza"), Some(0.5));
+    assert!(summary.stage("lectura").is_some());
+    assert_eq!(summary.leidos(), 5);
+    assert_eq!(summary.rechazados(), 1);
+    assert_eq!(summary.archivos[0].rows_per_second(), 2.5);
+    assert!(summary.lote_max_bytes > 0);
+    assert!(summary.db_bytes_antes > 0);
+    assert_eq!(summary.db_bytes_antes, db_size(&path));
+    if cfg!(target_os = "linux") {
+        assert!(summary.memoria_pico_bytes.unwrap_or_default() > 0);
+    }
+    let table = summary.to_string();
+    assert!(table.contains("TOTAL"));
+    assert!(table.contains("COVID19MEXICO2023.csv"));
+
+    let json = env::temp_dir().join("db_cov19mx_etl_run.json");
+    summary.write_json(&json)?;
+    let read: RunSummary = serde_json::from_reader(fs::File::open(&json)?)?;
+    assert_eq!(read, summary);
+
+    // Cada corrida agrega una fila a ETL_RUNS
+    for _ in 0..2 {
+        sql_write
+            .clone()
+            .with_table(Some(RUNS_TABLE))
+            .if_exists(IfExistsOption::Append)
+            .finish(&mut summary.to_frame()?)?;
+    }
+    let runs = sql_write.reader().with_table(Some(RUNS_TABLE)).finish()?;
+    assert_eq!(runs.height(), 2);
+    assert_eq!(
+        runs.column("INSERTADOS")?
+            .cast(&DataType::Int64)?
+            .i64()?
+            .get(0),
+        Some(4)
+    );
+    assert_eq!(summary.files_frame()?.height(), 1);
+    Ok(())
+}
+#[test]
+fn test_cli() -> Result<(), color_eyre::eyre::Error> {
+    let dir = env::temp_dir().join("db_cov19mx_cli");
+    let _ = fs::remove_dir_all(&dir);
+    let csv_dir = dir.join("csv");
+    create_dir_all(&csv_dir)?;
+    for name in ["COVID19MEXICO2021.csv", "COVID19MEXICO2020.csv"] {
+        fs::write(csv_dir.join(name), "ID_REGISTRO\n")?;
+    }
+    let cli = Cli::try_parse_from([
+        "db_cov19mx",
+        "load",
+        "--years",
+        "2021,2022",
+        "--batch-size",
+        "1000",
+        "--csv-dir",
+        csv_dir.to_str().unwrap(),
+        "--db-dir",
+        dir.join("DB").to_str().unwrap(),
+        "--threads",
+        "2",
+    ])?;
+    assert_eq!(cli.threads, Some(2));
+    let config = EtlConfig::default().with_cli(&cli);
+    let Some(Command::Load(args)) = cli.command else {
+        panic!("se esperaba el subcomando load");
+    };
+    assert_eq!(args.years.years, vec![2021, 2022]);
+    assert_eq!(args.batch_size, Some(1000));
+    assert_eq!(config.writer.batch_size, 1000);
+    assert_eq!(config.writer.insert_batch_size.get(), 160_000);
+    assert_eq!(config.writer.n_threads, Some(2));
+    assert_eq!(config.paths.db_path(), dir.join("DB").join("db_cov19mx.db"));
+
+    let etl = Etl::new(config);
+    let files = etl.csv_files(&args.years.years)?;
+    assert_eq!(files, vec![csv_dir.join("COVID19MEXICO2021.csv")]);
+    assert_eq!(etl.csv_files(&[])?.len(), 2);
+    assert!(etl.descriptors().is_err());
+    assert_eq!(
+        etl.status()?.to_string(),
+        "No se ha cargado ningún archivo\n"
+    );
+
+    let cli = Cli::try_parse_from(["db_cov19mx"])?;
+    assert!(cli.command.is_none());
+    assert_eq!(
+        EtlConfig::default().with_cli(&cli).paths.csv_dir,
+        Path::new("data_csv")
+    );
+    assert!(Cli::try_parse_from(["db_cov19mx", "load", "--batch-size", "x"]).is_err());
+    Ok(())
+}
+#[test]
+fn test_config() -> Result<(), color_eyre::eyre::Error> {
+    let config = EtlConfig::from_toml(
+        r#"
+        [sources]
+        years = [2022, 2023]
+
+        [paths]
+        db_dir = "/tmp/DB"
+
+        [cleaning]
+        sentinel_action = "null"
+        sentinel_actions = { EDAD = "flag" }
+
+        [writer]
+        batch_size = 1000
+        strict_insert = false
+        "#,
+    )?;
+    assert_eq!(config.sources.years, vec![2022, 2023]);
+    assert_eq!(
+        config.sources.url_covid(2022),
+        "https://datosabiertos.salud.gob.mx/gobmx/salud/datos_abiertos/historicos/2022/COVID19MEXICO2022.zip"
+    );
+    assert_eq!(config.paths.db_path(), Path::new("/tmp/DB/db_cov19mx.db"));
+    // Lo que no viene en el archivo conserva su valor por omisión
+    assert_eq!(config.paths.csv_dir, Path::new("data_csv"));
+    assert_eq!(config.cleaning.sentinel_action, SentinelAction::Null);
+    assert_eq!(
+        config.cleaning.sentinel_actions.get("EDAD"),
+        Some(&SentinelAction::Flag)
+    );
+    assert_eq!(config.cleaning.date_mode, DateParseMode::Lenient);
+    assert_eq!(config.writer.batch_size, 1000);
+    assert!(!config.writer.strict_insert);
+    assert_eq!(config.catalogs.foreign_keys.len(), 2);
+    config.validate()?;
+    assert_eq!(EtlConfig::from_toml(&config.to_toml()?)?, config);
+    // El ejemplo del repositorio describe los valores por omisión
+    assert_eq!(
+        EtlConfig::from_file("db_cov19mx.example.toml")?,
+        EtlConfig::default()
+    );
+
+    // Las variables de entorno van sobre el archivo y la línea de comandos sobre ambas
+    let config = config.with_env_vars([
+        ("COV19MX_WRITER_BATCH_SIZE", "2000"),
+        ("COV19MX_PATHS_DB_FILE", "2023"),
+        ("COV19MX_SOURCES_YEARS", "2020,2021"),
+        ("COV19MX_OUTPUT_PARQUET", "false"),
+        ("COV19MX_CONFIG", "otro.toml"),
+        ("PATH", "/usr/bin"),
+    ])?;
+    assert_eq!(config.writer.batch_size, 2000);
+    assert_eq!(config.paths.db_file, Path::new("2023"));
+    assert_eq!(config.sources.years, vec![2020, 2021]);
+    assert!(!config.output.parquet);
+    let cli = Cli::try_parse_from(["db_cov19mx", "load", "--batch-size", "3000"])?;
+    assert_eq!(config.clone().with_cli(&cli).writer.batch_size, 3000);
+
+    // Errores con la clave y el problema
+    let err = EtlConfig::from_toml("[writer]\nbatch_sise = 10\n").unwrap_err();
+    assert!(err.to_string().contains("batch_sise"));
+    let err = EtlConfig::from_toml("[writer]\nbatch_size = \"mucho\"\n").unwrap_err();
+    assert!(err.to_string().contains("line 2"));
+    let err = config
+        .clone()
+        .with_env_vars([("COV19MX_WRITER_BATCH_SIZE", "mucho")])
+        .unwrap_err();
+    assert!(err.to_string().contains("COV19MX_WRITER_BATCH_SIZE"));
+    assert!(config
+        .clone()
+        .with_env_vars([("COV19MX_DESTINO_DB", "x")])
+        .is_err());
+    let mut invalid = config.clone();
+    invalid.sources.years = vec![2019];
+    invalid.sources.url_covid = "ftp://datos/COVID19MEXICO.zip".into();
+    invalid.writer.batch_size = 0;
+    let err = invalid.validate().unwrap_err().to_string();
+    for key in ["sources.years", "sources.url_covid", "writer.batch_size"] {
+        assert!(err.contains(key), "{}", err);
+    }
+
+    // Salida a Postgres o DuckDB con su URL o archivo
+    let output = config
+        .clone()
+        .with_env_vars([("COV19MX_OUTPUT_BACKEND", "postgres")])?;
+    assert_eq!(output.output.backend, OutputBackend::Postgres);
+    let err = output.validate().unwrap_err().to_string();
+    assert!(err.contains("output.url"), "{}", err);
+    let output = output.with_env_vars([("COV19MX_OUTPUT_URL", "postgres://localhost/covid")])?;
+    output.validate()?;
+    let output =
+        EtlConfig::from_toml("[output]\nbackend = \"duckdb\"\nurl = \"DB/covid.duckdb\"\n")?;
+    assert_eq!(output.output.backend, OutputBackend::DuckDb);
+    assert_eq!(output.validate().is_ok(), cfg!(feature = "duckdb"));
+    assert!(EtlConfig::from_toml("[output]\nbackend = \"mysql\"\n").is_err());
+    Ok(())
+}
+#[test]
+fn test_logging() -> Result<(), color_eyre::eyre::Error> {
+    let logging = LoggingConfig {
+        level: "info,db_cov19mx=nivel".into(),
+        ..Default::default()
+    };
+    assert!(logging.filter().is_err());
+    let config = EtlConfig {
+        logging,
+        ..Default::default()
+    };
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .to_string()
+        .contains("logging.level"));
+    let cli = Cli::try_parse_from(["db_cov19mx", "status", "--log-format", "json"])?;
+    assert_eq!(config.with_cli(&cli).logging.format, LogFormat::Json);
+
+    // El span de cada escritura queda en la bitácora con la tabla, los registros y la duración
+    let path = env::temp_dir().join("db_cov19mx_etl.log");
+    let _ = fs::remove_file(&path);
+    init_logging(&LoggingConfig {
+        format: LogFormat::Json,
+        file: Some(path.clone()),
+        ..Default::default()
+    })?;
+    let mut df = df!("CLAVE" => [1i64, 2, 3])?;
+    SqlWriter::new(temp_db("logging"))?
+        .with_table(Some("BITACORA"))
+        .with_index(false)
+        .finish(&mut df)?;
+    let close = fs::read_to_string(&path)?
+        .lines()
+        .map(serde_json::from_str::<serde_json::Value>)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .find(|l| l["span"]["table"] == "BITACORA" && l["fields"]["message"] == "close")
+        .expect("no se registró el span sql_write");
+    assert_eq!(close["span"]["name"], "sql_write");
+    assert_eq!(close["span"]["rows"], 3);
+    assert!(close["fields"]["time.busy"].is_string());
+    Ok(())
+}
+#[test]
+fn test_progress() -> Result<(), color_eyre::eyre::Error> {
+    let dir = env::temp_dir().join("db_cov19mx_progress");
+    let _ = fs::remove_dir_all(&dir);
+    create_dir_all(&dir)?;
+    let zip_path = dir.join("datos.zip");
+    let mut zip = ::zip::ZipWriter::new(fs::File::create(&zip_path)?);
+    for name in ["a.csv", "b.csv", "c.csv"] {
+        zip.start_file(name, ::zip::write::SimpleFileOptions::default())?;
+        zip.write_all(b"ID_REGISTRO\n1\n")?;
+    }
+    zip.finish()?;
+
+    let progress = Arc::new(JsonProgress::new(Vec::new()));
+    let zip_name = zip_path.to_str().unwrap();
+    extract_zip_with_progress(zip_name, &dir.join("csv"), progress.as_ref())?;
+    let mut df = df!("CLAVE" => [1i64, 2, 3, 4, 5])?;
+    SqlWriter::new(dir.join("progress.db"))?
+        .with_table(Some("AVANCE"))
+        .with_index(false)
+        .with_batch_size(NonZeroUsize::new(2).unwrap())
+        .n_threads(1)
+        .with_progress(Some(progress.clone()))
+        .finish(&mut df)?;
+
+    let out = Arc::try_unwrap(progress).unwrap().into_inner();
+    let events = String::from_utf8(out)?
+        .lines()
+        .map(serde_json::from_str::<ProgressEvent>)
+        .collect::<Result<Vec<_>, _>>()?;
+    let of = |stage: ProgressStage| {
+        events
+            .iter()
+            .filter(|e| e.stage == stage)
+            .collect::<Vec<_>>()
+    };
+    let extract = of(ProgressStage::Extract);
+    assert_eq!(extract.len(), 5);
+    assert_eq!(extract[0].event, ProgressEventKind::Start);
+    assert_eq!(extract[0].total, Some(3));
+    assert_eq!(extract[0].name, zip_name);
+    assert_eq!(extract[4].event, ProgressEventKind::Finish);
+    assert_eq!(extract[4].done, 3);
+    // Se avanza por cada sentencia INSERT con el acumulado
+    let insert = of(ProgressStage::Insert);
+    assert_eq!(
+        insert
+            .iter()
+            .filter(|e| e.event == ProgressEventKind::Advance)
+            .map(|e| e.done)
+            .collect::<Vec<_>>(),
+        vec![2, 4, 5]
+    );
+    assert_eq!(insert.last().unwrap().name, "AVANCE");
+    assert_eq!(insert.last().unwrap().total, Some(5));
+    // Un DataFrame menor que el lote va en una sola sentencia
+    let progress = Arc::new(JsonProgress::new(Vec::new()));
+    SqlWriter::new(dir.join("progress.db"))?
+        .with_table(Some("AVANCE_CORTO"))
+        .with_index(false)
+        .n_threads(1)
+        .with_progress(Some(progress.clone()))
+        .finish(&mut df!("CLAVE" => [1i64, 2, 3])?)?;
+    let out = Arc::try_unwrap(progress).unwrap().into_inner();
+    let advance = String::from_utf8(out)?
+        .lines()
+        .map(serde_json::from_str::<ProgressEvent>)
+        .filter(|e| matches!(e, Ok(e) if e.event == ProgressEventKind::Advance))
+        .count();
+    assert_eq!(advance, 1);
+
+    let cli = Cli::try_parse_from(["db_cov19mx", "load", "--progress", "json"])?;
+    let config = EtlConfig::default().with_cli(&cli);
+    assert_eq!(config.output.progress, ProgressMode::Json);
+    assert!(ProgressMode::None.observer().is_none());
+    Ok(())
+}
+#[test]
+fn test_country_dictionary() -> Result<(), color_eyre::eyre::Error> {
+    let path = env::temp_dir().join("db_cov19mx_paises.json");
+    let _ = fs::remove_file(&path);
+    let lf = df!(
+        "PAIS_NACIONALIDAD" => ["MÉXICO", "99", "Estados Unidos de América", "México "],
+        "PAIS_ORIGEN" => ["97", "97", "ESTADOS UNIDOS DE AMÉRICA", "Guatemala"],
+    )?
+    .lazy();
+    let mut countries = CountryDictionary::load(&path)?;
+    let df = get_unique_contry(&lf, &mut countries)?;
+    assert_eq!(df.height(), 5);
+    let mexico = countries
+        .key("mexico ")
+        .or(countries.key("MÉXICO"))
+        .unwrap();
+    countries.add_alias("MEJICO", "MÉXICO")?;
+    countries.save(&path)?;
+
+    // Las claves se conservan entre corridas y los países nuevos van al final
+    let mut countries = CountryDictionary::load(&path)?;
+    assert_eq!(countries.key("Mejico"), Some(mexico));
+    let lf_new = df!(
+        "PAIS_NACIONALIDAD" => ["Belice", "MÉXICO"],
+        "PAIS_ORIGEN" => ["97", "97"],
+    )?
+    .lazy();
+    get_unique_contry(&lf_new, &mut countries)?;
+    assert_eq!(countries.key("MÉXICO"), Some(mexico));
+    assert_eq!(countries.len(), 6);
+    assert_eq!(countries.mapping_frame()?.height(), 7);
+
+    // Dos países con la misma clave o un país con dos claves es un error
+    assert!(countries.insert("CANADÁ", mexico).is_err());
+    assert!(countries.insert("MÉXICO", mexico + 1).is_err());
+    assert!(countries.add_alias("MEJICO", "BELICE").is_err());
+
+    let df = clean_data_covid_countries(lf, &countries)?;
+    assert_eq!(df.column("PAIS_NACIONALIDAD")?.u64()?.get(3), Some(mexico));
+    assert!(clean_data_covid_countries(
+        df!("PAIS_NACIONALIDAD" => ["ATLANTIDA"], "PAIS_ORIGEN" => ["97"])?.lazy(),
+        &countries
+    )
+    .is_err());
+    Ok(())
+}
+fn clean_data_covid_countries(
+    lf: LazyFrame,
+    countries: &CountryDictionary,
+) -> PolarsResult<DataFrame> {
+    clean_data_covid(
+        lf.with_columns([
+            lit(9u64).alias("ENTIDAD_RES"),
+            lit(2u64).alias("MUNICIPIO_RES"),
+            lit("2021-01-01").alias("FECHA_DEF"),
+        ]),
+        countries,
+    )
+    .collect()
+}
+#[test]
+fn test_country_iso() -> Result<(), color_eyre::eyre::Error> {
+    let iso = IsoCountries::bundled();
+    let alpha3 = |name: &str| iso.lookup(name).map(|c| c.alpha3.as_str());
+    assert_eq!(alpha3("Estados Unidos de America"), Some("USA"));
+    assert_eq!(alpha3("REPÚBLICA DE COREA"), Some("KOR"));
+    assert_eq!(alpha3("Holanda"), Some("NLD"));
+    assert_eq!(alpha3("GUATEMLA"), Some("GTM"));
+    assert_eq!(alpha3("Mejico"), Some("MEX"));
+    assert_eq!(alpha3("ATLÁNTIDA"), None);
+    assert_eq!(alpha3("SE IGNORA"), None);
+
+    let mut countries = CountryDictionary::new();
+    let lf = df!(
+        "PAIS_NACIONALIDAD" => ["MÉXICO", "99", "ESTADOS UNIDOS DE AMÉRICA", "ATLÁNTIDA"],
+        "PAIS_ORIGEN" => ["97", "97", "ESTADOS UNIDOS", "MEXICO"],
+    )?
+    .lazy();
+    let df = get_unique_contry(&lf, &mut countries)?;
+    // Las distintas formas de escribir un país comparten su código numérico
+    assert_eq!(countries.key("México"), Some(484));
+    assert_eq!(countries.key("MEXICO"), Some(484));
+    assert_eq!(countries.key("ESTADOS UNIDOS"), Some(840));
+    assert_eq!(countries.unmatched(), vec!["ATLÁNTIDA"]);
+    assert_eq!(df.height(), 5);
+    let usa = df
+        .clone()
+        .lazy()
+        .filter(col("CLAVE").eq(lit(840u64)))
+        .collect()?;
+    assert_eq!(usa.column("ISO_ALFA2")?.str()?.get(0), Some("US"));
+    assert_eq!(usa.column("CONTINENTE")?.str()?.get(0), Some("AMÉRICA"));
+    Ok(())
+}